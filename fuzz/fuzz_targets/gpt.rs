@@ -0,0 +1,20 @@
+//! Fuzz GPT header and entry array parsing.
+//!
+//! Extension disks are host-supplied, so the partition table is untrusted
+//! input. Err is an expected rejection; any panic (out-of-bounds slice,
+//! overflow, huge allocation) is a real bug and becomes a libFuzzer crash.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use NVRC::gpt;
+
+fuzz_target!(|data: &[u8]| {
+    // First byte picks the logical block size, including invalid ones.
+    let Some((&shift, disk)) = data.split_first() else {
+        return;
+    };
+    let sector = 256u64 << (shift % 6);
+    let _ = gpt::table_end(disk, sector);
+    let _ = gpt::parse(disk, sector);
+});
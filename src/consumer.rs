@@ -0,0 +1,627 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Consume mounted extensions: every extension, not just `gpu`, can bind
+//! files into the base image, add loader dirs, serve modules to modprobe and
+//! hand env to kata-agent.
+//!
+//! Each extension goes to the first registered [`Consumer`] that accepts it;
+//! kinds with special needs (the `gpu` extension requires a manifest) register
+//! ahead of the generic [`ManifestConsumer`]. Contributions are merged into one
+//! plan before anything is applied, and two extensions claiming the same bind
+//! target, module prefix or env var is fatal: which one wins would otherwise
+//! depend on mount order.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use nix::mount::MsFlags;
+use once_cell::sync::OnceCell;
+
+use crate::gpu_extension;
+use crate::guest_extension_image::{Extension, MOUNT_BASE};
+use crate::ldcache;
+use crate::macros::ResultExt;
+use crate::manifest::Manifest;
+use crate::modprobe::normalize;
+
+/// Env var kata-agent reads to pick its attestation-agent. Contract shared
+/// with kata-agent (`src/agent/src/main.rs`).
+pub const ATTESTER_VARIANT_ENV: &str = "KATA_ATTESTER_VARIANT";
+
+/// Loader cache: the base rootfs copy, and ours on the writable `/run`.
+const LDSO_CACHE: &str = "/etc/ld.so.cache";
+const LDSO_CACHE_TMP: &str = "/run/ld.so.cache";
+
+/// Consumers in priority order; the generic one accepts everything.
+static CONSUMERS: &[&dyn Consumer] = &[&gpu_extension::GpuConsumer, &ManifestConsumer];
+
+/// The merged plan, recorded once by [`setup`] for modprobe and kata-agent.
+static PLAN: OnceCell<Plan> = OnceCell::new();
+
+/// Bind `src` (inside the extension) over `dst` (a mountpoint baked into the
+/// read-only base image).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bind {
+    pub src: String,
+    pub dst: String,
+}
+
+/// What one extension adds to the guest. Paths are absolute.
+#[derive(Debug, Default, PartialEq)]
+pub struct Contribution {
+    pub binds: Vec<Bind>,
+    pub library_dirs: Vec<String>,
+    /// `(module name prefix, modprobe --dirname)`.
+    pub modprobe: Vec<(String, String)>,
+    /// Environment for kata-agent.
+    pub env: Vec<(String, String)>,
+}
+
+/// Turns one kind of extension into its [`Contribution`].
+pub trait Consumer: Sync {
+    fn accepts(&self, ext: &Extension) -> bool;
+    /// `root` is where `ext` is mounted.
+    fn contribute(&self, root: &str, ext: &Extension) -> Contribution;
+}
+
+/// Any extension: whatever its manifest declares, nothing without one.
+pub struct ManifestConsumer;
+
+impl Consumer for ManifestConsumer {
+    fn accepts(&self, _: &Extension) -> bool {
+        true
+    }
+
+    fn contribute(&self, root: &str, ext: &Extension) -> Contribution {
+        ext.manifest
+            .as_ref()
+            .map(|m| from_manifest(root, m))
+            .unwrap_or_default()
+    }
+}
+
+/// Firmware dirs bind onto the same path in the base image; the single
+/// declared attester variant becomes `KATA_ATTESTER_VARIANT`.
+pub fn from_manifest(root: &str, m: &Manifest) -> Contribution {
+    let dirname = match m.module_dirname.as_deref() {
+        None | Some(".") => root.to_owned(),
+        Some(dir) => format!("{root}/{dir}"),
+    };
+    let env = match m.attester_variants.as_slice() {
+        [] => Vec::new(),
+        [variant] => vec![(ATTESTER_VARIANT_ENV.to_owned(), variant.clone())],
+        variants => panic!("extension {root}: ambiguous attester variants {variants:?}"),
+    };
+    Contribution {
+        binds: m
+            .firmware_dirs
+            .iter()
+            .map(|dir| Bind {
+                src: format!("{root}/{dir}"),
+                dst: format!("/{dir}"),
+            })
+            .collect(),
+        library_dirs: m
+            .library_dirs
+            .iter()
+            .map(|d| format!("{root}/{d}"))
+            .collect(),
+        modprobe: m
+            .module_prefixes
+            .iter()
+            .map(|p| (p.clone(), dirname.clone()))
+            .collect(),
+        env,
+    }
+}
+
+/// Every extension's contribution merged, each entry tagged with its source.
+#[derive(Debug, Default)]
+struct Plan {
+    binds: Vec<(String, Bind)>,
+    library_dirs: Vec<String>,
+    modprobe: Vec<(String, (String, String))>,
+    env: Vec<(String, (String, String))>,
+    manifests: BTreeMap<String, Manifest>,
+}
+
+/// Merge and apply the contributions of all mounted extensions: binds first,
+/// then one loader cache over every library dir.
+pub fn setup(extensions: &[Extension]) {
+    let plan = plan(MOUNT_BASE, CONSUMERS, extensions)
+        .unwrap_or_else(|conflicts| panic!("extension conflicts: {}", conflicts.join("; ")));
+    let plan = PLAN.get_or_init(|| plan);
+    for (name, bind) in &plan.binds {
+        bind_dir(name, &bind.src, &bind.dst);
+    }
+    if !plan.library_dirs.is_empty() {
+        refresh_ldcache(&plan.library_dirs);
+    }
+}
+
+fn plan(
+    mount_base: &str,
+    consumers: &[&dyn Consumer],
+    extensions: &[Extension],
+) -> Result<Plan, Vec<String>> {
+    let mut plan = Plan::default();
+    let mut conflicts = Vec::new();
+
+    for ext in extensions {
+        let Some(consumer) = consumers.iter().find(|c| c.accepts(ext)) else {
+            continue;
+        };
+        let c = consumer.contribute(&format!("{mount_base}/{}", ext.name), ext);
+        let name = &ext.name;
+
+        for bind in c.binds {
+            // A bind inside another is hidden or hides part of it, whichever mounts last.
+            let nested = |b: &Bind| {
+                Path::new(&b.dst).starts_with(&bind.dst) || Path::new(&bind.dst).starts_with(&b.dst)
+            };
+            match plan.binds.iter().find(|(_, b)| nested(b)) {
+                Some((other, b)) if b.dst == bind.dst => {
+                    conflicts.push(format!("{} provided by both {other} and {name}", bind.dst))
+                }
+                Some((other, b)) => conflicts.push(format!(
+                    "{} from {other} and {} from {name} are nested",
+                    b.dst, bind.dst
+                )),
+                None => plan.binds.push((name.clone(), bind)),
+            }
+        }
+        for (prefix, dirname) in c.modprobe {
+            // Overlapping prefixes (nvidia vs nvidia-uvm) are as ambiguous as
+            // equal ones, with `-` and `_` alike as in [`provider`].
+            let normalized = normalize(&prefix);
+            match plan.modprobe.iter().find(|(_, (p, _))| {
+                let p = normalize(p);
+                p.starts_with(&normalized) || normalized.starts_with(&p)
+            }) {
+                Some((other, (p, _))) => conflicts.push(format!(
+                    "modules {p}* from {other} overlap {prefix}* from {name}"
+                )),
+                None => plan.modprobe.push((name.clone(), (prefix, dirname))),
+            }
+        }
+        for (key, value) in c.env {
+            match plan.env.iter().find(|(_, (k, _))| *k == key) {
+                Some((other, _)) => conflicts.push(format!("{key} set by both {other} and {name}")),
+                None => plan.env.push((name.clone(), (key, value))),
+            }
+        }
+        for dir in c.library_dirs {
+            if !plan.library_dirs.contains(&dir) {
+                plan.library_dirs.push(dir);
+            }
+        }
+        if let Some(m) = &ext.manifest {
+            plan.manifests.insert(name.clone(), m.clone());
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(plan)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Manifest of the mounted extension `name`, once [`setup`] ran.
+pub fn manifest(name: &str) -> Option<&'static Manifest> {
+    PLAN.get()?.manifests.get(name)
+}
+
+/// `modprobe --dirname` for `module`: the extension that declared a matching
+/// prefix, `None` for modules in the base image. `-` and `_` match alike.
+pub fn modprobe_dirname(module: &str) -> Option<String> {
+    PLAN.get()
+        .and_then(|plan| modprobe_dirname_in(plan, module))
+}
+
+fn modprobe_dirname_in(plan: &Plan, module: &str) -> Option<String> {
+    provider(plan, module).map(|(_, (_, dirname))| dirname.clone())
+}
+
+/// The `(extension, (prefix, dirname))` whose prefix matches `module`.
+fn provider<'a>(plan: &'a Plan, module: &str) -> Option<&'a (String, (String, String))> {
+    let module = normalize(module);
+    plan.modprobe
+        .iter()
+        .find(|(_, (prefix, _))| module.starts_with(&normalize(prefix)))
+}
+
+/// The pin the providing extension's manifest declares for `module`
+/// (normalized name), `None` for base-image modules or no pin.
+pub fn module_sha256(module: &str) -> Option<String> {
+    PLAN.get().and_then(|plan| module_sha256_in(plan, module))
+}
+
+fn module_sha256_in(plan: &Plan, module: &str) -> Option<String> {
+    let (ext, _) = provider(plan, module)?;
+    let module = normalize(module);
+    plan.manifests
+        .get(ext)?
+        .module_sha256
+        .iter()
+        .find(|(name, _)| normalize(name) == module)
+        .map(|(_, digest)| digest.clone())
+}
+
+/// Library dirs the extensions added to the loader cache, once [`setup`] ran.
+pub fn library_dirs() -> Vec<String> {
+    PLAN.get()
+        .map(|plan| plan.library_dirs.clone())
+        .unwrap_or_default()
+}
+
+/// Environment extensions hand to kata-agent.
+pub fn agent_env() -> Vec<(String, String)> {
+    PLAN.get()
+        .map(|plan| plan.env.iter().map(|(_, kv)| kv.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// Bind an extension dir over its base image mountpoint. Skipped when the
+/// extension lacks `src`.
+fn bind_dir(name: &str, src: &str, dst: &str) {
+    if !Path::new(src).is_dir() {
+        return;
+    }
+    bind_over(src, dst);
+    info!("extension {name}: bound {src} -> {dst}");
+}
+
+/// Add the extension lib dirs to the loader cache so kata-agent and the CDI
+/// hooks it runs resolve them without an inherited `LD_LIBRARY_PATH`.
+/// Base rootfs is read-only, so build on `/run` and bind over the cache.
+fn refresh_ldcache(lib_dirs: &[String]) {
+    let cache = ldcache::generate(&ldso_conf(lib_dirs));
+    fs::write(LDSO_CACHE_TMP, cache).or_panic(format_args!("write {LDSO_CACHE_TMP}"));
+    bind_over(LDSO_CACHE_TMP, LDSO_CACHE);
+    info!("extensions: cached libraries from {lib_dirs:?}");
+}
+
+/// The base `.conf.d`, then the extension lib dirs.
+fn ldso_conf(lib_dirs: &[String]) -> String {
+    let mut conf = "include /etc/ld.so.conf.d/*.conf\n".to_owned();
+    for dir in lib_dirs {
+        conf.push_str(dir);
+        conf.push('\n');
+    }
+    conf
+}
+
+/// Bind `src` (file or dir) onto `dst`. `dst` must already exist: the base
+/// rootfs is read-only, so the mountpoint is baked in at image build time.
+fn bind_over(src: &str, dst: &str) {
+    nix::mount::mount(Some(src), dst, None::<&str>, MsFlags::MS_BIND, None::<&str>)
+        .or_panic(format_args!("bind {src} -> {dst}"));
+    // A bind starts out writable and executable whatever its source; nothing
+    // here is ever written or run (see `mount::audit`).
+    let flags = MsFlags::MS_BIND
+        | MsFlags::MS_REMOUNT
+        | MsFlags::MS_RDONLY
+        | MsFlags::MS_NOSUID
+        | MsFlags::MS_NODEV
+        | MsFlags::MS_NOEXEC;
+    nix::mount::mount(None::<&str>, dst, None::<&str>, flags, None::<&str>)
+        .or_panic(format_args!("remount {dst} read-only"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::require_root;
+    use tempfile::TempDir;
+
+    const BASE: &str = "/run/kata-extensions";
+
+    fn ext(name: &str, manifest: Option<Manifest>) -> Extension {
+        Extension {
+            name: name.to_owned(),
+            root_hash: String::new(),
+            hash_alg: "sha256",
+            signed: false,
+            manifest,
+        }
+    }
+
+    fn gpu_manifest() -> Manifest {
+        Manifest {
+            firmware_dirs: vec!["lib/firmware/nvidia".to_owned()],
+            library_dirs: vec!["usr/lib/x86_64-linux-gnu".to_owned()],
+            module_dirname: Some(".".to_owned()),
+            module_prefixes: vec!["nvidia".to_owned()],
+            attester_variants: vec!["nvidia".to_owned()],
+            ..Manifest::default()
+        }
+    }
+
+    /// IB userspace: libraries and in-tree-named modules from a subdir.
+    fn ib_manifest() -> Manifest {
+        Manifest {
+            library_dirs: vec!["usr/lib/x86_64-linux-gnu".to_owned()],
+            module_dirname: Some("opt".to_owned()),
+            module_prefixes: vec!["ib_".to_owned()],
+            ..Manifest::default()
+        }
+    }
+
+    #[test]
+    fn test_from_manifest() {
+        let c = from_manifest("/run/kata-extensions/gpu", &gpu_manifest());
+        assert_eq!(
+            c,
+            Contribution {
+                binds: vec![Bind {
+                    src: "/run/kata-extensions/gpu/lib/firmware/nvidia".to_owned(),
+                    dst: "/lib/firmware/nvidia".to_owned(),
+                }],
+                library_dirs: vec!["/run/kata-extensions/gpu/usr/lib/x86_64-linux-gnu".to_owned()],
+                modprobe: vec![("nvidia".to_owned(), "/run/kata-extensions/gpu".to_owned())],
+                env: vec![(ATTESTER_VARIANT_ENV.to_owned(), "nvidia".to_owned())],
+            }
+        );
+        assert_eq!(
+            from_manifest("/x", &Manifest::default()),
+            Contribution::default()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ambiguous attester variants")]
+    fn test_from_manifest_ambiguous_variant() {
+        let m = Manifest {
+            attester_variants: vec!["nvidia".to_owned(), "tdx".to_owned()],
+            ..Manifest::default()
+        };
+        from_manifest("/x", &m);
+    }
+
+    #[test]
+    fn test_plan_merges_extensions() {
+        let plan = plan(
+            BASE,
+            CONSUMERS,
+            &[
+                ext("gpu", Some(gpu_manifest())),
+                ext("ib", Some(ib_manifest())),
+                ext("coco", None),
+            ],
+        )
+        .unwrap();
+        assert_eq!(plan.binds.len(), 1);
+        assert_eq!(
+            plan.library_dirs,
+            [
+                "/run/kata-extensions/gpu/usr/lib/x86_64-linux-gnu",
+                "/run/kata-extensions/ib/usr/lib/x86_64-linux-gnu"
+            ]
+        );
+        assert_eq!(
+            modprobe_dirname_in(&plan, "nvidia-uvm").as_deref(),
+            Some("/run/kata-extensions/gpu")
+        );
+        assert_eq!(
+            modprobe_dirname_in(&plan, "ib_umad").as_deref(),
+            Some("/run/kata-extensions/ib/opt")
+        );
+        assert_eq!(modprobe_dirname_in(&plan, "mlx5_ib"), None);
+        assert_eq!(plan.env.len(), 1);
+        assert_eq!(plan.manifests.keys().collect::<Vec<_>>(), ["gpu", "ib"]);
+    }
+
+    #[test]
+    fn test_module_sha256_from_providing_manifest() {
+        let pin = "ab".repeat(32);
+        let gpu = Manifest {
+            module_sha256: vec![("nvidia_uvm".to_owned(), pin.clone())],
+            ..gpu_manifest()
+        };
+        let plan = plan(BASE, CONSUMERS, &[ext("gpu", Some(gpu))]).unwrap();
+        assert_eq!(module_sha256_in(&plan, "nvidia_uvm"), Some(pin));
+        assert_eq!(module_sha256_in(&plan, "nvidia"), None);
+        assert_eq!(module_sha256_in(&plan, "loop"), None);
+    }
+
+    /// A `nvidia_` prefix covers `nvidia-uvm` for both the dirname and the pin.
+    #[test]
+    fn test_module_lookup_normalizes() {
+        let pin = "ab".repeat(32);
+        let gpu = Manifest {
+            module_prefixes: vec!["nvidia_".to_owned()],
+            module_sha256: vec![("nvidia_uvm".to_owned(), pin.clone())],
+            ..gpu_manifest()
+        };
+        let plan = plan(BASE, CONSUMERS, &[ext("gpu", Some(gpu))]).unwrap();
+        for module in ["nvidia-uvm", "nvidia_uvm"] {
+            assert_eq!(
+                modprobe_dirname_in(&plan, module).as_deref(),
+                Some("/run/kata-extensions/gpu")
+            );
+            assert_eq!(module_sha256_in(&plan, module).as_deref(), Some(&*pin));
+        }
+    }
+
+    #[test]
+    fn test_plan_reports_all_conflicts() {
+        // A debug toolkit re-shipping the GPU firmware, modules and attester.
+        let debug = Manifest {
+            module_prefixes: vec!["nvidia-".to_owned()],
+            ..gpu_manifest()
+        };
+        let conflicts = plan(
+            BASE,
+            CONSUMERS,
+            &[ext("gpu", Some(gpu_manifest())), ext("debug", Some(debug))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            conflicts,
+            [
+                "/lib/firmware/nvidia provided by both gpu and debug",
+                "modules nvidia* from gpu overlap nvidia-* from debug",
+                "KATA_ATTESTER_VARIANT set by both gpu and debug",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_prefix_conflict_normalized() {
+        let ib = Manifest {
+            module_prefixes: vec!["nvidia_".to_owned()],
+            ..Manifest::default()
+        };
+        let debug = Manifest {
+            module_prefixes: vec!["nvidia-uvm".to_owned()],
+            ..Manifest::default()
+        };
+        let conflicts = plan(
+            BASE,
+            CONSUMERS,
+            &[ext("ib", Some(ib)), ext("debug", Some(debug))],
+        )
+        .unwrap_err();
+        assert_eq!(
+            conflicts,
+            ["modules nvidia_* from ib overlap nvidia-uvm* from debug"]
+        );
+    }
+
+    #[test]
+    fn test_plan_rejects_nested_binds() {
+        let nested = Manifest {
+            firmware_dirs: vec!["lib/firmware/nvidia/570.133.20".to_owned()],
+            ..Manifest::default()
+        };
+        let sibling = Manifest {
+            firmware_dirs: vec!["lib/firmware/nvidia-debug".to_owned()],
+            ..Manifest::default()
+        };
+        let conflicts = plan(
+            BASE,
+            CONSUMERS,
+            &[
+                ext("gpu", Some(gpu_manifest())),
+                ext("debug", Some(nested)),
+                ext("tools", Some(sibling)),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            conflicts,
+            ["/lib/firmware/nvidia from gpu and /lib/firmware/nvidia/570.133.20 from debug are nested"]
+        );
+    }
+
+    #[test]
+    fn test_plan_first_accepting_consumer_wins() {
+        struct Nothing;
+        impl Consumer for Nothing {
+            fn accepts(&self, ext: &Extension) -> bool {
+                ext.name == "debug"
+            }
+            fn contribute(&self, _: &str, _: &Extension) -> Contribution {
+                Contribution::default()
+            }
+        }
+        let consumers: &[&dyn Consumer] = &[&Nothing, &ManifestConsumer];
+        let plan = plan(BASE, consumers, &[ext("debug", Some(gpu_manifest()))]).unwrap();
+        assert!(plan.binds.is_empty());
+        assert!(plan.env.is_empty());
+    }
+
+    #[test]
+    fn test_plan_gpu_without_manifest() {
+        let plan = plan(BASE, CONSUMERS, &[ext("gpu", None)]).unwrap();
+        assert_eq!(plan.binds[0].1.dst, "/lib/firmware/nvidia");
+        assert_eq!(plan.modprobe[0].1 .0, "nvidia");
+        assert!(plan.manifests.is_empty());
+    }
+
+    #[test]
+    fn test_empty_plan() {
+        // Monolithic image: nothing from extensions.
+        let plan = Plan::default();
+        assert_eq!(modprobe_dirname_in(&plan, "nvidia"), None);
+        assert_eq!(module_sha256_in(&plan, "nvidia"), None);
+    }
+
+    #[test]
+    fn test_setup_without_extensions() {
+        setup(&[]);
+        assert_eq!(modprobe_dirname("nvidia"), None);
+    }
+
+    #[test]
+    fn test_ldso_conf_includes_base_and_extension() {
+        assert_eq!(
+            ldso_conf(&["/run/kata-extensions/gpu/usr/lib".to_owned()]),
+            "include /etc/ld.so.conf.d/*.conf\n/run/kata-extensions/gpu/usr/lib\n"
+        );
+        assert_eq!(
+            ldso_conf(&["/a".to_owned(), "/b".to_owned()]),
+            "include /etc/ld.so.conf.d/*.conf\n/a\n/b\n"
+        );
+    }
+
+    #[test]
+    fn test_bind_dir_skips_without_src() {
+        // No firmware tree in the extension: no-op, and in particular no attempt
+        // to bind onto the (nonexistent) destination. Needs no root.
+        bind_dir(
+            "gpu",
+            "/nonexistent/firmware/src",
+            "/nonexistent/firmware/dst",
+        );
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_bind_dir_binds_when_src_present() {
+        require_root();
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        fs::write(src.path().join("gsp.bin.txt"), "firmware").unwrap();
+
+        bind_dir(
+            "gpu",
+            src.path().to_str().unwrap(),
+            dst.path().to_str().unwrap(),
+        );
+
+        let visible = dst.path().join("gsp.bin.txt");
+        assert!(visible.exists());
+        assert_eq!(fs::read_to_string(visible).unwrap(), "firmware");
+
+        nix::mount::umount(dst.path()).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_bind_over_makes_source_visible() {
+        require_root();
+        let src = TempDir::new().unwrap();
+        let dst = TempDir::new().unwrap();
+        fs::write(src.path().join("gsp.bin.txt"), "firmware").unwrap();
+
+        let src_str = src.path().to_str().unwrap();
+        let dst_str = dst.path().to_str().unwrap();
+        bind_over(src_str, dst_str);
+
+        let visible = dst.path().join("gsp.bin.txt");
+        assert!(visible.exists());
+        assert_eq!(fs::read_to_string(visible).unwrap(), "firmware");
+
+        nix::mount::umount(dst.path()).unwrap();
+    }
+}
@@ -2,9 +2,10 @@
 // Copyright (c) NVIDIA CORPORATION
 
 use crate::config::update_config_file;
-use crate::execute::background;
+use crate::execute::{background, background_sandboxed};
 use crate::gpu_extension;
 use crate::kmsg;
+use crate::landlock::{self, Rule};
 use crate::macros::ResultExt;
 use crate::nvrc::NVRC;
 use std::fs;
@@ -41,6 +42,26 @@ pub const FABRIC_MODE_FULL: u8 = 0;
 /// FABRIC_MODE=1: shared NVSwitch virtualization, GPUs in tenant VMs.
 pub const FABRIC_MODE_SHARED: u8 = 1;
 
+/// Every daemon may read and execute the rootfs and use the GPU device nodes;
+/// writes are limited to its own state dirs in `writable`.
+fn daemon_rules<'a>(writable: &[&'a str]) -> Vec<Rule<'a>> {
+    let mut rules = vec![
+        Rule {
+            path: "/",
+            access: landlock::READ | landlock::EXECUTE,
+        },
+        Rule {
+            path: "/dev",
+            access: landlock::DEVICE | landlock::WRITE,
+        },
+    ];
+    rules.extend(writable.iter().map(|path| Rule {
+        path,
+        access: landlock::READ | landlock::WRITE,
+    }));
+    rules
+}
+
 /// Configurable path parameters allow testing with /bin/true instead of real
 /// NVIDIA binaries that don't exist in the test environment.
 impl NVRC {
@@ -60,8 +81,7 @@ impl NVRC {
         fs::create_dir_all(run_dir).or_panic(format_args!("create_dir_all {run_dir}"));
         let uvm_enabled = self.uvm_persistence_mode.unwrap_or(true);
         let args = persistenced_args(uvm_enabled);
-        let child = background(bin, &args);
-        self.track_daemon("nvidia-persistenced", child);
+        self.spawn_daemon("nvidia-persistenced", bin, &args, &[run_dir]);
     }
 
     /// nv-hostengine is the DCGM backend daemon. Only started when DCGM monitoring
@@ -74,8 +94,7 @@ impl NVRC {
         if !self.dcgm_enabled.unwrap_or(false) {
             return;
         }
-        let child = background(bin, hostengine_args());
-        self.track_daemon("nv-hostengine", child);
+        self.spawn_daemon("nv-hostengine", bin, hostengine_args(), &["/tmp"]);
     }
 
     /// dcgm-exporter exposes GPU metrics for Prometheus. Only started when DCGM
@@ -88,8 +107,7 @@ impl NVRC {
         if !self.dcgm_enabled.unwrap_or(false) {
             return;
         }
-        let child = background(bin, dcgm_exporter_args());
-        self.track_daemon("dcgm-exporter", child);
+        self.spawn_daemon("dcgm-exporter", bin, dcgm_exporter_args(), &["/tmp"]);
     }
 
     /// NVSwitch fabric manager is only needed for multi-GPU NVLink topologies.
@@ -116,8 +134,7 @@ impl NVRC {
             args.push("-g");
             args.push(&guid_owned);
         }
-        let child = background(bin, &args);
-        self.track_daemon("nv-fabricmanager", child);
+        self.spawn_daemon("nv-fabricmanager", bin, &args, &["/run", "/tmp"]);
     }
 
     /// CX7 bridges require NVLSM to manage NVLink subnet before FM can initialize the fabric.
@@ -132,8 +149,19 @@ impl NVRC {
         let guid_owned = guid.clone();
         let nvlsm_config = gpu_extension::path(NVLSM_CONFIG);
         let args = vec!["-F", &nvlsm_config, "-g", &guid_owned, "-f", "stdout"];
-        let child = background(bin, &args);
-        self.track_daemon("nvlsm", child);
+        self.spawn_daemon("nvlsm", bin, &args, &["/run", "/tmp"]);
+    }
+
+    /// Spawn and track a daemon, Landlock-confined to [`daemon_rules`] when
+    /// `nvrc.landlock.daemons` is set.
+    fn spawn_daemon(&mut self, name: &str, bin: &str, args: &[&str], writable: &[&str]) {
+        let child = if self.landlock_daemons.unwrap_or(false) {
+            let ruleset = landlock::ruleset(name, &daemon_rules(writable));
+            background_sandboxed(bin, args, &ruleset)
+        } else {
+            background(bin, args)
+        };
+        self.track_daemon(name, child);
     }
 
     /// Write FABRIC_MODE and PARTITION_RAIL_POLICY to fabricmanager.cfg.
@@ -236,6 +264,28 @@ mod tests {
         nvrc.health_checks();
     }
 
+    #[test]
+    fn test_daemon_rules() {
+        let rules = daemon_rules(&["/run/state"]);
+        let paths: Vec<_> = rules.iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/", "/dev", "/run/state"]);
+        // rootfs is never writable; only declared dirs are
+        assert_eq!(rules[0].access & landlock::WRITE, 0);
+        assert_ne!(rules[2].access & landlock::ACCESS_FS_WRITE_FILE, 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
+    fn test_spawn_persistenced_landlocked() {
+        let tmpdir = TempDir::new().unwrap();
+        let run_dir = tmpdir.path().join("nvidia-persistenced");
+
+        let mut nvrc = NVRC::default();
+        nvrc.landlock_daemons = Some(true);
+        nvrc.spawn_persistenced(run_dir.to_str().unwrap(), "/bin/true");
+        nvrc.health_checks();
+    }
+
     #[test]
     #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
     fn test_spawn_persistenced_uvm_disabled() {
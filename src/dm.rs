@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Minimal device-mapper client over `/dev/mapper/control` ioctls.
+//!
+//! Just enough of libdevmapper to activate a single-target table:
+//! DM_DEV_CREATE, DM_TABLE_LOAD, DM_DEV_SUSPEND (resume) and DM_TABLE_STATUS.
+//! Keeps cryptsetup/lvm2 and their shared libraries out of the trusted rootfs.
+
+use nix::sys::stat::{major, minor, mknod, Mode, SFlag};
+use std::fs::{self, File, OpenOptions};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::macros::ResultExt;
+
+const CONTROL: &str = "/dev/mapper/control";
+const MAPPER_DIR: &str = "/dev/mapper";
+
+const DM_NAME_LEN: usize = 128;
+const DM_UUID_LEN: usize = 129;
+const DM_MAX_TYPE_NAME: usize = 16;
+
+/// Interface version we speak; the kernel accepts any 4.x request.
+const DM_VERSION: [u32; 3] = [4, 0, 0];
+
+const DM_READONLY_FLAG: u32 = 1 << 0;
+const DM_STATUS_TABLE_FLAG: u32 = 1 << 4;
+const DM_BUFFER_FULL_FLAG: u32 = 1 << 8;
+
+/// Room for the header, one target spec and its parameter string; verity
+/// tables and status lines are a few hundred bytes.
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// `struct dm_ioctl` from `<linux/dm-ioctl.h>`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DmIoctl {
+    version: [u32; 3],
+    data_size: u32,
+    data_start: u32,
+    target_count: u32,
+    open_count: i32,
+    flags: u32,
+    event_nr: u32,
+    padding: u32,
+    dev: u64,
+    name: [u8; DM_NAME_LEN],
+    uuid: [u8; DM_UUID_LEN],
+    data: [u8; 7],
+}
+
+/// `struct dm_target_spec`; the parameter string follows it directly.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DmTargetSpec {
+    sector_start: u64,
+    length: u64,
+    status: i32,
+    next: u32,
+    target_type: [u8; DM_MAX_TYPE_NAME],
+}
+
+const _: () = assert!(std::mem::size_of::<DmIoctl>() == 312);
+const _: () = assert!(std::mem::size_of::<DmTargetSpec>() == 40);
+
+const HEADER_SIZE: usize = std::mem::size_of::<DmIoctl>();
+const SPEC_SIZE: usize = std::mem::size_of::<DmTargetSpec>();
+
+nix::ioctl_readwrite!(dm_dev_create, 0xfd, 3, DmIoctl);
+nix::ioctl_readwrite!(dm_dev_suspend, 0xfd, 6, DmIoctl);
+nix::ioctl_readwrite!(dm_table_load, 0xfd, 9, DmIoctl);
+nix::ioctl_readwrite!(dm_table_status, 0xfd, 12, DmIoctl);
+
+/// One line of a device-mapper table, in 512-byte sectors.
+pub struct Target {
+    pub start: u64,
+    pub length: u64,
+    pub kind: &'static str,
+    pub params: String,
+}
+
+impl Target {
+    /// The line as `dmsetup table` prints it.
+    pub fn line(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.start, self.length, self.kind, self.params
+        )
+    }
+}
+
+/// `major:minor` of a block device node, the form dmsetup uses in tables.
+pub fn dev_id(path: &str) -> String {
+    let st = nix::sys::stat::stat(path).or_panic(format_args!("stat {path}"));
+    format!("{}:{}", major(st.st_rdev), minor(st.st_rdev))
+}
+
+/// Create `/dev/mapper/<name>` with a single read-only target and activate it.
+/// Returns the node path.
+pub fn create_readonly(name: &str, target: &Target) -> String {
+    let control = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(CONTROL)
+        .or_panic(format_args!("open {CONTROL}"));
+
+    let mut buf = Buffer::new(name, DM_READONLY_FLAG, &[]);
+    // SAFETY: buf holds a valid dm_ioctl header followed by BUFFER_SIZE bytes.
+    unsafe { dm_dev_create(control.as_raw_fd(), buf.as_mut_ptr()) }
+        .or_panic(format_args!("DM_DEV_CREATE {name}"));
+    let dev = buf.header().dev;
+
+    let mut buf = Buffer::new(name, DM_READONLY_FLAG, &encode_target(target));
+    buf.header_mut().target_count = 1;
+    // SAFETY: as above; the target spec and params lie within data_size.
+    unsafe { dm_table_load(control.as_raw_fd(), buf.as_mut_ptr()) }
+        .or_panic(format_args!("DM_TABLE_LOAD {name}"));
+
+    // DM_DEV_SUSPEND without DM_SUSPEND_FLAG resumes, swapping in the table.
+    let mut buf = Buffer::new(name, 0, &[]);
+    // SAFETY: as above.
+    unsafe { dm_dev_suspend(control.as_raw_fd(), buf.as_mut_ptr()) }
+        .or_panic(format_args!("DM_DEV_SUSPEND (resume) {name}"));
+
+    // No udev: create the node ourselves.
+    fs::create_dir_all(MAPPER_DIR).or_panic(format_args!("create_dir_all {MAPPER_DIR}"));
+    let node = format!("{MAPPER_DIR}/{name}");
+    if !Path::new(&node).exists() {
+        mknod(
+            node.as_str(),
+            SFlag::S_IFBLK,
+            Mode::S_IRUSR | Mode::S_IWUSR,
+            dev as libc::dev_t,
+        )
+        .or_panic(format_args!("mknod {node}"));
+    }
+
+    node
+}
+
+/// The `dmsetup status` line of a single-target device.
+pub fn status(name: &str) -> String {
+    table_query(name, 0)
+}
+
+/// The `dmsetup table` line of a single-target device.
+pub fn table(name: &str) -> String {
+    table_query(name, DM_STATUS_TABLE_FLAG)
+}
+
+fn table_query(name: &str, flags: u32) -> String {
+    let control = File::open(CONTROL).or_panic(format_args!("open {CONTROL}"));
+    let mut buf = Buffer::new(name, flags, &[]);
+    // SAFETY: buf holds a valid dm_ioctl header followed by BUFFER_SIZE bytes.
+    unsafe { dm_table_status(control.as_raw_fd(), buf.as_mut_ptr()) }
+        .or_panic(format_args!("DM_TABLE_STATUS {name}"));
+    if buf.header().flags & DM_BUFFER_FULL_FLAG != 0 {
+        panic!("DM_TABLE_STATUS {name}: result exceeds {BUFFER_SIZE} bytes");
+    }
+    decode_target(buf.data()).unwrap_or_else(|| panic!("DM_TABLE_STATUS {name}: no target"))
+}
+
+/// Target spec plus NUL-terminated params, padded to 8 bytes like libdevmapper.
+fn encode_target(target: &Target) -> Vec<u8> {
+    if target.kind.len() >= DM_MAX_TYPE_NAME {
+        panic!("dm target type {:?} too long", target.kind);
+    }
+    let mut spec = DmTargetSpec {
+        sector_start: target.start,
+        length: target.length,
+        status: 0,
+        next: 0,
+        target_type: [0; DM_MAX_TYPE_NAME],
+    };
+    spec.target_type[..target.kind.len()].copy_from_slice(target.kind.as_bytes());
+
+    let mut out = Vec::with_capacity(SPEC_SIZE + target.params.len() + 8);
+    // SAFETY: DmTargetSpec is repr(C) plain data without padding holes.
+    out.extend_from_slice(unsafe {
+        std::slice::from_raw_parts((&spec as *const DmTargetSpec).cast::<u8>(), SPEC_SIZE)
+    });
+    out.extend_from_slice(target.params.as_bytes());
+    out.push(0);
+    out.resize(out.len().next_multiple_of(8), 0);
+    out
+}
+
+/// `"<start> <length> <type> <params>"` from a returned target spec.
+fn decode_target(data: &[u8]) -> Option<String> {
+    if data.len() < SPEC_SIZE {
+        return None;
+    }
+    // SAFETY: length checked; read_unaligned tolerates any alignment.
+    let spec: DmTargetSpec = unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) };
+    let kind = c_str(&spec.target_type);
+    let params = c_str(&data[SPEC_SIZE..]);
+    Some(format!(
+        "{} {} {kind} {params}",
+        spec.sector_start, spec.length
+    ))
+}
+
+fn c_str(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or("")
+}
+
+/// 8-byte aligned ioctl buffer: dm_ioctl header, then the payload.
+struct Buffer(Vec<u64>);
+
+impl Buffer {
+    fn new(name: &str, flags: u32, payload: &[u8]) -> Self {
+        if name.is_empty() || name.len() >= DM_NAME_LEN {
+            panic!("invalid dm device name {name:?}");
+        }
+        let size = (HEADER_SIZE + payload.len()).max(BUFFER_SIZE);
+        let mut buf = Buffer(vec![0u64; size.div_ceil(8)]);
+
+        let mut header = DmIoctl {
+            version: DM_VERSION,
+            data_size: (buf.0.len() * 8) as u32,
+            data_start: HEADER_SIZE as u32,
+            target_count: 0,
+            open_count: 0,
+            flags,
+            event_nr: 0,
+            padding: 0,
+            dev: 0,
+            name: [0; DM_NAME_LEN],
+            uuid: [0; DM_UUID_LEN],
+            data: [0; 7],
+        };
+        header.name[..name.len()].copy_from_slice(name.as_bytes());
+        *buf.header_mut() = header;
+        buf.bytes_mut()[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        buf
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut DmIoctl {
+        self.0.as_mut_ptr().cast()
+    }
+
+    fn header(&self) -> &DmIoctl {
+        // SAFETY: the buffer is 8-byte aligned and larger than DmIoctl.
+        unsafe { &*self.0.as_ptr().cast() }
+    }
+
+    fn header_mut(&mut self) -> &mut DmIoctl {
+        // SAFETY: as in header().
+        unsafe { &mut *self.as_mut_ptr() }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: u64 storage reinterpreted as bytes, same length in bytes.
+        unsafe { std::slice::from_raw_parts_mut(self.0.as_mut_ptr().cast(), self.0.len() * 8) }
+    }
+
+    /// Returned payload: data_start..data_size as written back by the kernel.
+    fn data(&self) -> &[u8] {
+        let h = self.header();
+        // SAFETY: as in bytes_mut().
+        let bytes: &[u8] =
+            unsafe { std::slice::from_raw_parts(self.0.as_ptr().cast(), self.0.len() * 8) };
+        let end = (h.data_size as usize).min(bytes.len());
+        let start = (h.data_start as usize).min(end);
+        &bytes[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    fn verity() -> Target {
+        Target {
+            start: 0,
+            length: 772096,
+            kind: "verity",
+            params: "1 253:17 253:18 4096 4096 96512 0 sha256 abc123 def456".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_target_line() {
+        assert_eq!(
+            verity().line(),
+            "0 772096 verity 1 253:17 253:18 4096 4096 96512 0 sha256 abc123 def456"
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_target_roundtrip() {
+        let encoded = encode_target(&verity());
+        assert_eq!(encoded.len() % 8, 0);
+        assert_eq!(&encoded[24..30], b"verity");
+        assert_eq!(decode_target(&encoded).unwrap(), verity().line());
+    }
+
+    #[test]
+    fn test_encode_target_type_too_long() {
+        let mut t = verity();
+        t.kind = "a-target-type-name-way-too-long";
+        assert!(panic::catch_unwind(|| encode_target(&t)).is_err());
+    }
+
+    #[test]
+    fn test_decode_target_short() {
+        assert!(decode_target(&[0; 8]).is_none());
+    }
+
+    #[test]
+    fn test_buffer_layout() {
+        let payload = encode_target(&verity());
+        let mut buf = Buffer::new("extension-gpu", DM_READONLY_FLAG, &payload);
+        let h = *buf.header();
+        assert_eq!(h.version, DM_VERSION);
+        assert_eq!(h.data_start as usize, HEADER_SIZE);
+        assert_eq!(h.data_size as usize, BUFFER_SIZE);
+        assert_eq!(h.flags, DM_READONLY_FLAG);
+        assert_eq!(c_str(&h.name), "extension-gpu");
+        assert_eq!(
+            &buf.bytes_mut()[HEADER_SIZE..HEADER_SIZE + payload.len()],
+            payload
+        );
+        assert_eq!(decode_target(buf.data()).unwrap(), verity().line());
+    }
+
+    #[test]
+    fn test_buffer_invalid_name() {
+        assert!(panic::catch_unwind(|| Buffer::new("", 0, &[])).is_err());
+        let long = "x".repeat(DM_NAME_LEN);
+        assert!(panic::catch_unwind(|| Buffer::new(&long, 0, &[])).is_err());
+    }
+
+    #[test]
+    fn test_dev_id() {
+        assert_eq!(dev_id("/dev/null"), "1:3");
+        assert!(panic::catch_unwind(|| dev_id("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_status_without_device_mapper() {
+        if Path::new(CONTROL).exists() {
+            return;
+        }
+        assert!(panic::catch_unwind(|| status("extension-none")).is_err());
+        assert!(panic::catch_unwind(|| create_readonly("extension-none", &verity())).is_err());
+    }
+}
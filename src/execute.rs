@@ -1,11 +1,33 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright (c) NVIDIA CORPORATION
 
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
 
 use crate::kmsg::kmsg;
+use crate::landlock::{self, Ruleset};
 use crate::macros::ResultExt;
 
+/// The whole environment of every process NVRC starts. NVRC's own is never
+/// passed on: the kernel hands init any unrecognised `key=value` from the
+/// host-supplied cmdline, `LD_PRELOAD` and `LD_LIBRARY_PATH` included.
+/// Libraries resolve via the loader cache instead.
+pub const ENV: &[(&str, &str)] = &[
+    ("HOME", "/"),
+    (
+        "PATH",
+        "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+    ),
+    ("TERM", "linux"),
+];
+
+/// `command` with [`ENV`] instead of the inherited environment.
+pub fn command(command: &str) -> Command {
+    let mut cmd = Command::new(command);
+    cmd.env_clear().envs(ENV.iter().copied());
+    cmd
+}
+
 /// Run a command and block until completion. Output goes to kmsg so it appears
 /// in dmesg/kernel log - the only reliable log destination in minimal VMs.
 /// Used for setup commands that must succeed before continuing (nvidia-smi, modprobe).
@@ -13,7 +35,7 @@ pub fn foreground(command: &str, args: &[&str]) {
     debug!("{} {}", command, args.join(" "));
 
     let kmsg_file = kmsg();
-    let status = Command::new(command)
+    let status = self::command(command)
         .args(args)
         .stdout(Stdio::from(kmsg_file.try_clone().unwrap()))
         .stderr(Stdio::from(kmsg_file))
@@ -25,13 +47,30 @@ pub fn foreground(command: &str, args: &[&str]) {
     }
 }
 
+/// Run a command and return its stdout; stderr goes to kmsg. For querying
+/// tools (`--version`) whose answer NVRC acts on.
+pub fn output(command: &str, args: &[&str]) -> String {
+    debug!("{} {}", command, args.join(" "));
+
+    let output = self::command(command)
+        .args(args)
+        .stderr(Stdio::from(kmsg()))
+        .output()
+        .or_panic(format_args!("execute {command}"));
+
+    if !output.status.success() {
+        panic!("{command} failed with status: {}", output.status);
+    }
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
 /// Spawn a daemon without waiting. Returns Child so caller can track it later.
 /// Used for long-running services (nvidia-persistenced, fabricmanager) that run
 /// alongside kata-agent. Output to kmsg for visibility in kernel log.
 pub fn background(command: &str, args: &[&str]) -> Child {
     debug!("{} {}", command, args.join(" "));
     let kmsg_file = kmsg();
-    Command::new(command)
+    self::command(command)
         .args(args)
         .stdout(Stdio::from(kmsg_file.try_clone().unwrap()))
         .stderr(Stdio::from(kmsg_file))
@@ -39,6 +78,21 @@ pub fn background(command: &str, args: &[&str]) -> Child {
         .or_panic(format_args!("start {command}"))
 }
 
+/// [`background`] with the child confined by a Landlock ruleset before exec.
+/// The ruleset is built by the caller; only the raw fd crosses into pre_exec.
+pub fn background_sandboxed(command: &str, args: &[&str], ruleset: &Ruleset) -> Child {
+    debug!("{} {} (landlock)", command, args.join(" "));
+    let kmsg_file = kmsg();
+    let fd = ruleset.raw_fd();
+    let mut cmd = self::command(command);
+    cmd.args(args)
+        .stdout(Stdio::from(kmsg_file.try_clone().unwrap()))
+        .stderr(Stdio::from(kmsg_file));
+    // SAFETY: restrict() only issues raw syscalls, safe between fork and exec.
+    unsafe { cmd.pre_exec(move || landlock::restrict(fd)) };
+    cmd.spawn().or_panic(format_args!("start {command}"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,6 +137,32 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
+    fn test_command_replaces_environment() {
+        // The test runner's environment (CARGO_*, HOME, ...) must not leak.
+        let output = command("/usr/bin/env").output().unwrap();
+        let env = String::from_utf8(output.stdout).unwrap();
+        let expected: Vec<String> = ENV.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        assert_eq!(env.lines().collect::<Vec<_>>(), expected);
+    }
+
+    // ==================== output tests ====================
+
+    #[test]
+    #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
+    fn test_output() {
+        assert_eq!(
+            output("/bin/sh", &["-c", "echo 570.133.20"]),
+            "570.133.20\n"
+        );
+
+        let result = panic::catch_unwind(|| {
+            output("/bin/sh", &["-c", "echo partial; exit 1"]);
+        });
+        assert!(result.is_err());
+    }
+
     // ==================== background tests ====================
 
     #[test]
@@ -113,4 +193,33 @@ mod tests {
         assert!(!status.success());
         assert_eq!(status.code(), Some(7));
     }
+
+    // ==================== background_sandboxed tests ====================
+
+    #[test]
+    #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
+    fn test_background_sandboxed_confines_child() {
+        use crate::landlock::{Rule, EXECUTE, READ};
+
+        if landlock::abi() < 1 {
+            return;
+        }
+        let dir = tempfile::TempDir::new().unwrap();
+        let secret = dir.path().join("secret");
+        std::fs::write(&secret, "secret").unwrap();
+
+        // Enough for the shell and its loader; the tempdir is not granted.
+        let rules: Vec<_> = ["/usr", "/lib", "/lib64", "/bin"]
+            .into_iter()
+            .map(|path| Rule {
+                path,
+                access: READ | EXECUTE,
+            })
+            .collect();
+        let ruleset = landlock::ruleset("test", &rules);
+        // `read` is a builtin, so the only open is the redirect of the secret.
+        let script = format!("read -r x < {} && exit 1; exit 0", secret.display());
+        let mut child = background_sandboxed("/bin/sh", &["-c", &script], &ruleset);
+        assert!(child.wait().unwrap().success());
+    }
 }
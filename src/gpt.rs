@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Minimal GUID Partition Table reader for extension images.
+//!
+//! Only the primary header and its entry array are read. Both CRCs must hold
+//! and every field is bounds-checked: the bytes come from a host-supplied
+//! disk, so a corrupt or hostile table is an error, never a wrong mapping.
+//! There is no fallback to the backup header; an extension with a damaged
+//! primary table is not mounted.
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+const SIGNATURE: &[u8] = b"EFI PART";
+const REVISION: u32 = 0x0001_0000;
+const HEADER_MIN: usize = 92;
+const ENTRY_MIN: usize = 128;
+const LABEL_UNITS: usize = 36;
+/// Header plus entry array must fit here; real tables use 16 KiB at LBA 2.
+const MAX_TABLE: u64 = 1 << 20;
+
+/// Linux filesystem data (`8300` in sgdisk).
+pub const LINUX_DATA: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+/// Root verity hash partitions of the Discoverable Partitions Specification.
+pub const ROOT_VERITY_X86_64: &str = "2C7357ED-EBD2-46D9-AEC1-23D437EC2BF5";
+pub const ROOT_VERITY_ARM64: &str = "DF3300CE-D69F-4C92-978C-9BFB0F38D820";
+
+/// A used entry of the partition array.
+#[derive(Debug, PartialEq)]
+pub struct Partition {
+    /// 1-based slot in the entry array; the kernel's partition number.
+    pub number: u32,
+    pub type_guid: String,
+    pub label: String,
+    pub first_lba: u64,
+    pub last_lba: u64,
+}
+
+impl Partition {
+    /// Size in logical sectors.
+    pub fn sectors(&self) -> u64 {
+        self.last_lba - self.first_lba + 1
+    }
+}
+
+struct Header {
+    first_usable: u64,
+    last_usable: u64,
+    entries_lba: u64,
+    entries: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+/// Read the partition table of `disk` (a block device) with `sector`-byte
+/// logical blocks.
+pub fn read(disk: &Path, sector: u64) -> Result<Vec<Partition>, String> {
+    let file = File::open(disk).map_err(|e| format!("open {}: {e}", disk.display()))?;
+    let read_at = |len: u64| {
+        let mut buf = vec![0; len as usize];
+        file.read_exact_at(&mut buf, 0)
+            .map_err(|e| format!("read {}: {e}", disk.display()))?;
+        Ok::<_, String>(buf)
+    };
+    let end = table_end(&read_at(2 * check_sector(sector)?)?, sector)?;
+    parse(&read_at(end)?, sector)
+}
+
+/// Parse the table from the first bytes of a disk (LBA 0 onward), as many as
+/// [`table_end`] asks for.
+pub fn parse(disk: &[u8], sector: u64) -> Result<Vec<Partition>, String> {
+    let header = header(disk, sector)?;
+    let start = (header.entries_lba * sector) as usize;
+    let array = disk
+        .get(start..start + header.entries * header.entry_size)
+        .ok_or("GPT entry array truncated")?;
+    if crc32(array) != header.entries_crc {
+        return Err("GPT entry array CRC mismatch".to_owned());
+    }
+
+    let mut partitions = Vec::new();
+    for (i, raw) in array.chunks_exact(header.entry_size).enumerate() {
+        let type_guid = &raw[..16];
+        if type_guid.iter().all(|&b| b == 0) {
+            continue;
+        }
+        let number = i as u32 + 1;
+        let first_lba = le64(raw, 32);
+        let last_lba = le64(raw, 40);
+        if first_lba > last_lba || first_lba < header.first_usable || last_lba > header.last_usable
+        {
+            return Err(format!(
+                "GPT partition {number}: LBA {first_lba}..{last_lba} outside usable area"
+            ));
+        }
+        partitions.push(Partition {
+            number,
+            type_guid: guid(type_guid),
+            label: label(&raw[56..56 + 2 * LABEL_UNITS])
+                .ok_or_else(|| format!("GPT partition {number}: invalid UTF-16 label"))?,
+            first_lba,
+            last_lba,
+        });
+    }
+
+    for (i, a) in partitions.iter().enumerate() {
+        if let Some(b) = partitions[i + 1..]
+            .iter()
+            .find(|b| a.first_lba <= b.last_lba && b.first_lba <= a.last_lba)
+        {
+            return Err(format!(
+                "GPT partitions {} and {} overlap",
+                a.number, b.number
+            ));
+        }
+    }
+    Ok(partitions)
+}
+
+/// Bytes from LBA 0 through the end of the entry array, validated against
+/// [`MAX_TABLE`] so a forged header cannot make us read the whole disk.
+pub fn table_end(disk: &[u8], sector: u64) -> Result<u64, String> {
+    let h = header(disk, sector)?;
+    Ok(h.entries_lba * sector + (h.entries * h.entry_size) as u64)
+}
+
+fn check_sector(sector: u64) -> Result<u64, String> {
+    if !(512..=4096).contains(&sector) || !sector.is_power_of_two() {
+        return Err(format!("unsupported logical block size {sector}"));
+    }
+    Ok(sector)
+}
+
+fn header(disk: &[u8], sector: u64) -> Result<Header, String> {
+    let sector = check_sector(sector)?;
+    let raw = disk
+        .get(sector as usize..2 * sector as usize)
+        .ok_or("GPT header truncated")?;
+    if &raw[..8] != SIGNATURE {
+        return Err("no GPT signature".to_owned());
+    }
+    if le32(raw, 8) != REVISION {
+        return Err(format!("unsupported GPT revision {:#x}", le32(raw, 8)));
+    }
+    let size = le32(raw, 12) as usize;
+    if !(HEADER_MIN..=raw.len()).contains(&size) {
+        return Err(format!("invalid GPT header size {size}"));
+    }
+    let mut copy = raw[..size].to_vec();
+    copy[16..20].fill(0);
+    if crc32(&copy) != le32(raw, 16) {
+        return Err("GPT header CRC mismatch".to_owned());
+    }
+    if le64(raw, 24) != 1 {
+        return Err("GPT header is not the primary header".to_owned());
+    }
+
+    let entries_lba = le64(raw, 72);
+    let entries = le32(raw, 80) as u64;
+    let entry_size = le32(raw, 84) as u64;
+    if entry_size < ENTRY_MIN as u64 || !entry_size.is_power_of_two() {
+        return Err(format!("invalid GPT entry size {entry_size}"));
+    }
+    let end = entries_lba
+        .checked_mul(sector)
+        .and_then(|start| start.checked_add(entries * entry_size))
+        .filter(|&end| end <= MAX_TABLE)
+        .ok_or("GPT entry array out of range")?;
+    let (first_usable, last_usable) = (le64(raw, 40), le64(raw, 48));
+    if entries_lba < 2 || end > first_usable.saturating_mul(sector) {
+        return Err("GPT entry array overlaps header or partitions".to_owned());
+    }
+
+    Ok(Header {
+        first_usable,
+        last_usable,
+        entries_lba,
+        entries: entries as usize,
+        entry_size: entry_size as usize,
+        entries_crc: le32(raw, 88),
+    })
+}
+
+fn le32(raw: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(raw[at..at + 4].try_into().unwrap())
+}
+
+fn le64(raw: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(raw[at..at + 8].try_into().unwrap())
+}
+
+/// Mixed-endian on-disk GUID to its canonical uppercase text form.
+fn guid(raw: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        le32(raw, 0),
+        u16::from_le_bytes([raw[4], raw[5]]),
+        u16::from_le_bytes([raw[6], raw[7]]),
+        raw[8],
+        raw[9],
+        raw[10..16]
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<String>()
+    )
+}
+
+/// UTF-16LE name, NUL-terminated or filling all 36 units.
+fn label(raw: &[u8]) -> Option<String> {
+    let units = raw
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0);
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+/// CRC-32/ISO-HDLC, the GPT checksum. Bitwise: tables are a few KiB at most.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Build a disk image with a valid primary GPT: `(type GUID, label, first
+/// LBA, last LBA)` per entry, 128 entries of 128 bytes at LBA 2.
+#[cfg(test)]
+pub(crate) fn image(sector: u64, parts: &[(&str, &str, u64, u64)], sectors: u64) -> Vec<u8> {
+    let s = sector as usize;
+    let mut disk = vec![0u8; sectors as usize * s];
+    let array_at = 2 * s;
+    for (i, (type_guid, name, first, last)) in parts.iter().enumerate() {
+        let e = &mut disk[array_at + i * ENTRY_MIN..array_at + (i + 1) * ENTRY_MIN];
+        e[..16].copy_from_slice(&guid_bytes(type_guid));
+        e[16] = i as u8 + 1;
+        e[32..40].copy_from_slice(&first.to_le_bytes());
+        e[40..48].copy_from_slice(&last.to_le_bytes());
+        for (j, unit) in name.encode_utf16().enumerate() {
+            e[56 + 2 * j..58 + 2 * j].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&disk[array_at..array_at + 128 * ENTRY_MIN]);
+    let first_usable = (array_at + 128 * ENTRY_MIN).div_ceil(s) as u64;
+
+    let h = &mut disk[s..2 * s];
+    h[..8].copy_from_slice(SIGNATURE);
+    h[8..12].copy_from_slice(&REVISION.to_le_bytes());
+    h[12..16].copy_from_slice(&(HEADER_MIN as u32).to_le_bytes());
+    h[24..32].copy_from_slice(&1u64.to_le_bytes());
+    h[32..40].copy_from_slice(&(sectors - 1).to_le_bytes());
+    h[40..48].copy_from_slice(&first_usable.to_le_bytes());
+    h[48..56].copy_from_slice(&(sectors - 2).to_le_bytes());
+    h[72..80].copy_from_slice(&2u64.to_le_bytes());
+    h[80..84].copy_from_slice(&128u32.to_le_bytes());
+    h[84..88].copy_from_slice(&(ENTRY_MIN as u32).to_le_bytes());
+    h[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&h[..HEADER_MIN]);
+    h[16..20].copy_from_slice(&crc.to_le_bytes());
+    disk
+}
+
+/// Inverse of [`guid`].
+#[cfg(test)]
+fn guid_bytes(text: &str) -> [u8; 16] {
+    let hex: Vec<u8> = text
+        .split('-')
+        .flat_map(|g| {
+            (0..g.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&g[i..i + 2], 16).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let mut raw = [0u8; 16];
+    raw.copy_from_slice(&hex);
+    raw[..4].reverse();
+    raw[4..6].reverse();
+    raw[6..8].reverse();
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::fs;
+    use tempfile::TempDir;
+
+    const PARTS: &[(&str, &str, u64, u64)] = &[
+        (LINUX_DATA, "data", 34, 97),
+        (ROOT_VERITY_X86_64, "verity", 98, 105),
+    ];
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_guid_round_trip() {
+        for g in [LINUX_DATA, ROOT_VERITY_X86_64, ROOT_VERITY_ARM64] {
+            assert_eq!(guid(&guid_bytes(g)), g);
+        }
+    }
+
+    #[rstest]
+    #[case::sector_512(512)]
+    #[case::sector_4k(4096)]
+    fn test_parse(#[case] sector: u64) {
+        let disk = image(sector, PARTS, 128);
+        let parts = parse(&disk, sector).unwrap();
+        assert_eq!(
+            parts,
+            [
+                Partition {
+                    number: 1,
+                    type_guid: LINUX_DATA.to_owned(),
+                    label: "data".to_owned(),
+                    first_lba: 34,
+                    last_lba: 97,
+                },
+                Partition {
+                    number: 2,
+                    type_guid: ROOT_VERITY_X86_64.to_owned(),
+                    label: "verity".to_owned(),
+                    first_lba: 98,
+                    last_lba: 105,
+                },
+            ]
+        );
+        assert_eq!(parts[0].sectors(), 64);
+        assert_eq!(table_end(&disk, sector).unwrap(), 2 * sector + 128 * 128);
+    }
+
+    #[test]
+    fn test_parse_skips_empty_slots() {
+        let parts = &[
+            PARTS[0],
+            ("00000000-0000-0000-0000-000000000000", "", 0, 0),
+            (LINUX_DATA, "fec", 106, 107),
+        ];
+        let parsed = parse(&image(512, parts, 128), 512).unwrap();
+        let numbers: Vec<u32> = parsed.iter().map(|p| p.number).collect();
+        assert_eq!(numbers, [1, 3]);
+    }
+
+    #[test]
+    fn test_read_device() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vdb");
+        fs::write(&path, image(512, PARTS, 128)).unwrap();
+        assert_eq!(read(&path, 512).unwrap().len(), 2);
+        assert!(read(&tmp.path().join("missing"), 512).is_err());
+    }
+
+    /// Flip one byte at `offset` and expect `parse` to fail with `err`.
+    #[rstest]
+    #[case::signature(512, "no GPT signature")]
+    #[case::header_crc(512 + 40, "header CRC")]
+    #[case::entry_crc(1024 + 33, "entry array CRC")]
+    #[case::header_in_mbr(0, "")]
+    fn test_parse_corrupt(#[case] offset: usize, #[case] err: &str) {
+        let mut disk = image(512, PARTS, 128);
+        disk[offset] ^= 0xff;
+        match parse(&disk, 512) {
+            Err(e) => assert!(e.contains(err), "{e}"),
+            // The protective MBR is not covered by any CRC.
+            Ok(_) => assert_eq!(offset, 0),
+        }
+    }
+
+    #[rstest]
+    #[case::before_usable(&[(LINUX_DATA, "data", 1, 40)])]
+    #[case::past_usable(&[(LINUX_DATA, "data", 34, 200)])]
+    #[case::reversed(&[(LINUX_DATA, "data", 90, 40)])]
+    #[case::overlap(&[(LINUX_DATA, "data", 34, 97), (LINUX_DATA, "verity", 97, 100)])]
+    fn test_parse_invalid_partitions(#[case] parts: &[(&str, &str, u64, u64)]) {
+        assert!(parse(&image(512, parts, 128), 512).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_huge_array() {
+        // Re-sign a header claiming 2^32-1 entries: must be refused before
+        // anything tries to read or allocate that much.
+        let mut disk = image(512, PARTS, 128);
+        disk[512 + 80..512 + 84].copy_from_slice(&u32::MAX.to_le_bytes());
+        disk[512 + 16..512 + 20].fill(0);
+        let crc = crc32(&disk[512..512 + HEADER_MIN]);
+        disk[512 + 16..512 + 20].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            parse(&disk, 512).unwrap_err(),
+            "GPT entry array out of range"
+        );
+    }
+
+    #[rstest]
+    #[case::empty(&[], 512)]
+    #[case::truncated(&[0u8; 600], 512)]
+    #[case::bad_sector(&[0u8; 2048], 1000)]
+    fn test_parse_short_input(#[case] disk: &[u8], #[case] sector: u64) {
+        assert!(parse(disk, sector).is_err());
+    }
+
+    #[test]
+    fn test_label_invalid_utf16() {
+        // lone high surrogate
+        assert_eq!(label(&[0x00, 0xd8, 0x41, 0x00]), None);
+        assert_eq!(label(&[0x41, 0x00, 0, 0, 0x42, 0x00]).unwrap(), "A");
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Keep conflicting drivers off the GPUs.
+//!
+//! A guest kernel with nouveau built in, or a GPU the VMM hands over bound to
+//! vfio-pci, lets `nvidia` load fine while the GPU never binds to it. Before
+//! the load, every NVIDIA GPU gets `driver_override=nvidia`, which keeps any
+//! other driver from probing it, and is unbound from whatever holds it. After
+//! the load every GPU must be bound to `nvidia`. A GPU left unbound is
+//! re-probed, since an `nvidia` that is already registered (built in, or
+//! loaded earlier) will not look at it again by itself.
+
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+use crate::macros::ResultExt;
+
+const PCI_DEVICES: &str = "/sys/bus/pci/devices";
+const DRIVERS_PROBE: &str = "/sys/bus/pci/drivers_probe";
+const DRIVER: &str = "nvidia";
+
+/// BDFs of the NVIDIA display-class functions under `pci`, sorted.
+pub fn gpus(pci: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(pci) else {
+        return Vec::new();
+    };
+    let mut gpus: Vec<String> = entries
+        .flatten()
+        .filter(|e| {
+            let vendor = fs::read_to_string(e.path().join("vendor")).unwrap_or_default();
+            let class = fs::read_to_string(e.path().join("class")).unwrap_or_default();
+            vendor.trim() == "0x10de" && class.trim().starts_with("0x03")
+        })
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    gpus.sort();
+    gpus
+}
+
+/// The driver `bdf` is bound to, from its `driver` symlink.
+fn driver(pci: &Path, bdf: &str) -> Option<String> {
+    let link = fs::read_link(pci.join(bdf).join("driver")).ok()?;
+    Some(link.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Reserve every GPU for nvidia and unbind other drivers. Must run before
+/// nvidia is loaded.
+pub fn prepare() {
+    prepare_at(Path::new(PCI_DEVICES), Path::new(DRIVERS_PROBE))
+}
+
+fn prepare_at(pci: &Path, drivers_probe: &Path) {
+    for bdf in gpus(pci) {
+        let dev = pci.join(&bdf);
+        // Override first, so the unbound GPU cannot be claimed back.
+        let driver_override = dev.join("driver_override");
+        fs::write(&driver_override, format!("{DRIVER}\n"))
+            .or_panic(format_args!("write {}", driver_override.display()));
+        match driver(pci, &bdf) {
+            Some(d) if d != DRIVER => {
+                warn!("{bdf}: unbinding from {d}");
+                let unbind = dev.join("driver/unbind");
+                fs::write(&unbind, &bdf).or_panic(format_args!("write {}", unbind.display()));
+            }
+            Some(_) => continue,
+            None => {}
+        }
+        // No-op until nvidia registers; binds it right away if it already has
+        fs::write(drivers_probe, &bdf).or_panic(format_args!("write {}", drivers_probe.display()));
+    }
+}
+
+/// Fail unless every GPU is bound to nvidia.
+pub fn verify() {
+    verify_at(Path::new(PCI_DEVICES))
+}
+
+fn verify_at(pci: &Path) {
+    let gpus = gpus(pci);
+    let unbound: Vec<String> = gpus
+        .iter()
+        .filter_map(|bdf| match driver(pci, bdf) {
+            Some(d) if d == DRIVER => None,
+            Some(d) => Some(format!("{bdf} (bound to {d})")),
+            None => Some(format!("{bdf} (unbound)")),
+        })
+        .collect();
+    if !unbound.is_empty() {
+        panic!("GPUs not bound to {DRIVER}: {}", unbound.join(", "));
+    }
+    info!("{} GPU bound to {DRIVER}", gpus.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::panic;
+    use tempfile::TempDir;
+
+    /// A PCI device under `pci`, bound to `driver` when given. Bound devices
+    /// link to `<pci>/drivers/<driver>`, where `unbind` is a plain file.
+    fn device(pci: &Path, bdf: &str, class: &str, driver: Option<&str>) {
+        let dev = pci.join(bdf);
+        fs::create_dir_all(&dev).unwrap();
+        fs::write(dev.join("vendor"), "0x10de\n").unwrap();
+        fs::write(dev.join("class"), class).unwrap();
+        if let Some(driver) = driver {
+            let dir = pci.join("drivers").join(driver);
+            fs::create_dir_all(&dir).unwrap();
+            symlink(&dir, dev.join("driver")).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_gpus() {
+        let pci = TempDir::new().unwrap();
+        device(pci.path(), "0000:41:00.0", "0x030200\n", None);
+        device(pci.path(), "0000:01:00.0", "0x030000\n", None);
+        // NVSwitch
+        device(pci.path(), "0000:05:00.0", "0x068000\n", None);
+        assert_eq!(gpus(pci.path()), ["0000:01:00.0", "0000:41:00.0"]);
+        assert!(gpus(&pci.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_prepare_at() {
+        let pci = TempDir::new().unwrap();
+        device(pci.path(), "0000:41:00.0", "0x030200\n", Some("vfio-pci"));
+        device(pci.path(), "0000:42:00.0", "0x030200\n", Some("nvidia"));
+        device(pci.path(), "0000:43:00.0", "0x030200\n", None);
+        let probe = pci.path().join("drivers_probe");
+        prepare_at(pci.path(), &probe);
+
+        for bdf in ["0000:41:00.0", "0000:42:00.0", "0000:43:00.0"] {
+            let over = fs::read_to_string(pci.path().join(bdf).join("driver_override")).unwrap();
+            assert_eq!(over, "nvidia\n");
+        }
+        let unbind = pci.path().join("drivers/vfio-pci/unbind");
+        assert_eq!(fs::read_to_string(unbind).unwrap(), "0000:41:00.0");
+        // nvidia keeps its GPU
+        assert!(!pci.path().join("drivers/nvidia/unbind").exists());
+        // The unbound GPUs are re-probed, the last one last
+        assert_eq!(fs::read_to_string(&probe).unwrap(), "0000:43:00.0");
+    }
+
+    #[test]
+    fn test_prepare_at_bound_to_nvidia() {
+        let pci = TempDir::new().unwrap();
+        device(pci.path(), "0000:42:00.0", "0x030200\n", Some("nvidia"));
+        let probe = pci.path().join("drivers_probe");
+        prepare_at(pci.path(), &probe);
+        assert!(!probe.exists());
+    }
+
+    #[test]
+    fn test_verify_at() {
+        let pci = TempDir::new().unwrap();
+        device(pci.path(), "0000:41:00.0", "0x030200\n", Some("nvidia"));
+        verify_at(pci.path());
+
+        device(pci.path(), "0000:42:00.0", "0x030200\n", Some("nouveau"));
+        device(pci.path(), "0000:43:00.0", "0x030200\n", None);
+        let err = panic::catch_unwind(|| verify_at(pci.path())).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            msg,
+            "GPUs not bound to nvidia: 0000:42:00.0 (bound to nouveau), 0000:43:00.0 (unbound)"
+        );
+    }
+}
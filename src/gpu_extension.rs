@@ -5,38 +5,66 @@
 //!
 //! With composable images the GPU userspace (libraries, modules, binaries,
 //! configs, firmware) lives in the extension rather than the base rootfs, so each
-//! helper maps its component to the extension mount. With monolithic images (no
-//! extension) they fall back to the canonical rootfs paths.
+//! helper maps its component to the extension mount. Firmware, libraries,
+//! modules and the attester variant are applied through [`crate::consumer`]
+//! from the extension's [`Manifest`], or from the fixed layout extensions
+//! used before they shipped one. With monolithic images (no extension) the
+//! helpers fall back to the canonical rootfs paths.
 
-use std::fs;
+use log::warn;
 use std::path::Path;
 
-use nix::mount::MsFlags;
+use crate::consumer::{self, Consumer, Contribution};
+use crate::guest_extension_image::Extension;
+use crate::manifest::{self, Manifest};
 
-use crate::execute::foreground;
-use crate::macros::ResultExt;
+/// Extension name, as in the `extension-gpu` serial.
+const NAME: &str = "gpu";
 
 /// The `gpu` extension mount ([`crate::guest_extension_image::MOUNT_BASE`]`/gpu`).
 pub const ROOT: &str = "/run/kata-extensions/gpu";
 
-/// Kernel firmware loader's default search path: an empty dir baked into the
-/// read-only base image, over which the extension's firmware tree is bound.
-const FIRMWARE_DST: &str = "/lib/firmware/nvidia";
-
-/// Loader cache regeneration. `ldconfig`/`LDSO_CACHE` come from the base rootfs;
-/// the `_TMP` outputs go to the writable `/run`.
-const LDCONFIG: &str = "/sbin/ldconfig";
-const LDSO_CACHE: &str = "/etc/ld.so.cache";
-const LDSO_CACHE_TMP: &str = "/run/ld.so.cache";
-const LDSO_CONF_TMP: &str = "/run/ld.so.conf";
-
-/// Extension GPU libraries, in the multiarch triplet dir mirroring the monolithic
-/// base image. nvidia-ctk records where it finds each lib and strips the driver
-/// root, so a flat `usr/lib` yields `/usr/lib` container paths its CDI hooks
-/// (create-symlinks/update-ldcache) can't reconcile; the triplet dir yields the
-/// canonical `/usr/lib/<triplet>` the monolithic path already proves out.
-fn lib_dir(root: &str) -> String {
-    format!("{root}/usr/lib/{}-linux-gnu", std::env::consts::ARCH)
+/// Binary the generated CDI createContainer hooks run.
+const CDI_HOOK: &str = "nvidia-cdi-hook";
+
+/// The `gpu` extension. Without a manifest it gets [`legacy_manifest`]. With
+/// the extension the GPU must be attested, and the stock attester emits no
+/// `gpu0` evidence, so the manifest is also what selects the `nvidia`
+/// attester variant.
+pub struct GpuConsumer;
+
+impl Consumer for GpuConsumer {
+    fn accepts(&self, ext: &Extension) -> bool {
+        ext.name == NAME
+    }
+
+    fn contribute(&self, root: &str, ext: &Extension) -> Contribution {
+        match &ext.manifest {
+            Some(m) => consumer::from_manifest(root, m),
+            None => {
+                warn!(
+                    "gpu extension: no {}, assuming the fixed layout",
+                    manifest::FILE
+                );
+                consumer::from_manifest(root, &legacy_manifest())
+            }
+        }
+    }
+}
+
+/// What a `gpu` extension built before `components.toml` holds: firmware
+/// under `lib/firmware/nvidia`, libraries in the multiarch triplet dir, the
+/// nvidia modules at its root and `nvidia-cdi-hook` in `bin`.
+fn legacy_manifest() -> Manifest {
+    Manifest {
+        firmware_dirs: vec!["lib/firmware/nvidia".to_owned()],
+        library_dirs: vec![format!("usr/lib/{}-linux-gnu", std::env::consts::ARCH)],
+        module_dirname: Some(".".to_owned()),
+        module_prefixes: vec!["nvidia".to_owned()],
+        binaries: vec![format!("bin/{CDI_HOOK}")],
+        attester_variants: vec!["nvidia".to_owned()],
+        ..Manifest::default()
+    }
 }
 
 pub fn present() -> bool {
@@ -57,16 +85,6 @@ fn path_in(present: bool, root: &str, path: &str) -> String {
     }
 }
 
-/// `modprobe --dirname` for `module`: the extension root for NVIDIA modules,
-/// `None` otherwise. In-tree modules (`ib_umad`/`mlx5_ib`) stay in the base image.
-pub fn modprobe_dirname(module: &str) -> Option<String> {
-    modprobe_dirname_in(present(), ROOT, module)
-}
-
-fn modprobe_dirname_in(present: bool, root: &str, module: &str) -> Option<String> {
-    (present && module.starts_with("nvidia")).then(|| root.to_owned())
-}
-
 /// `--driver-root` for `nvidia-ctk cdi generate`. nvidia-ctk strips the driver
 /// root from each library's recorded mount path, so passing `<root>` lands the
 /// extension libs at the canonical `/usr/lib/<triplet>` in the container. Must be
@@ -88,87 +106,26 @@ pub const DEV_ROOT: &str = "/";
 /// `--nvidia-cdi-hook-path` for `nvidia-ctk cdi generate`. The generated
 /// createContainer hooks run `nvidia-cdi-hook` from the guest; it lives in the
 /// extension, not nvidia-ctk's `/usr/bin` default, so without this the hooks
-/// silently no-op and CUDA breaks. `None` for the monolithic image.
+/// silently no-op and CUDA breaks. `None` for the monolithic image or when the
+/// manifest lists no such binary.
 pub fn cdi_hook_path() -> Option<String> {
-    cdi_hook_path_in(present(), ROOT)
-}
-
-fn cdi_hook_path_in(present: bool, root: &str) -> Option<String> {
-    present.then(|| format!("{root}/bin/nvidia-cdi-hook"))
-}
-
-/// attestation-agent variant launching the NVIDIA attester; matches
-/// `[process.variants.nvidia]` in the extension's `components.toml`.
-pub const ATTESTER_VARIANT_NVIDIA: &str = "nvidia";
-
-/// Attester variant kata-agent should use (via `KATA_ATTESTER_VARIANT`). With the
-/// GPU extension the GPU must be attested, but the stock attester emits no `gpu0`
-/// evidence so a GPU KBS policy can never pass; the `nvidia` variant does. `None`
-/// (stock) without the extension: nothing to attest.
-pub fn attester_variant() -> Option<&'static str> {
-    attester_variant_in(present())
-}
-
-fn attester_variant_in(present: bool) -> Option<&'static str> {
-    present.then_some(ATTESTER_VARIANT_NVIDIA)
-}
-
-pub fn setup() {
-    bind_firmware();
-    refresh_ldcache();
-}
-
-/// Add the extension's lib dir to the loader cache so kata-agent and the CDI
-/// hooks it runs resolve the GPU libraries without an inherited `LD_LIBRARY_PATH`.
-/// Base rootfs is read-only, so build on `/run` and bind over the cache.
-fn refresh_ldcache() {
-    if !present() {
-        return;
+    match consumer::manifest(NAME) {
+        Some(m) => cdi_hook_path_in(Some(m), ROOT),
+        None => cdi_hook_path_in(present().then(legacy_manifest).as_ref(), ROOT),
     }
-    refresh_ldcache_in(&lib_dir(ROOT));
-}
-
-fn refresh_ldcache_in(lib_dir: &str) {
-    fs::write(LDSO_CONF_TMP, ldso_conf(lib_dir)).or_panic(format_args!("write {LDSO_CONF_TMP}"));
-    // -X: leave the read-only lib dir's symlinks alone; -f/-C: our conf, write to /run.
-    foreground(LDCONFIG, &["-X", "-f", LDSO_CONF_TMP, "-C", LDSO_CACHE_TMP]);
-    bind_over(LDSO_CACHE_TMP, LDSO_CACHE);
-    info!("gpu extension: cached libraries from {lib_dir}");
-}
-
-/// Our `-f` conf replaces the base one, so re-include its `.conf.d` before the
-/// extension lib dir.
-fn ldso_conf(lib_dir: &str) -> String {
-    format!("include /etc/ld.so.conf.d/*.conf\n{lib_dir}\n")
 }
 
-/// Bind the extension firmware onto the kernel's default search path so
-/// `request_firmware` (e.g. GSP) finds it. Skipped when the extension ships none.
-fn bind_firmware() {
-    bind_firmware_in(&format!("{ROOT}/lib/firmware/nvidia"), FIRMWARE_DST);
-}
-
-fn bind_firmware_in(src: &str, dst: &str) {
-    if !Path::new(src).is_dir() {
-        return;
-    }
-    bind_over(src, dst);
-    info!("gpu extension: bound firmware {src} -> {dst}");
-}
-
-/// Bind `src` (file or dir) onto `dst`. `dst` must already exist: the base
-/// rootfs is read-only, so the mountpoint is baked in at image build time.
-fn bind_over(src: &str, dst: &str) {
-    nix::mount::mount(Some(src), dst, None::<&str>, MsFlags::MS_BIND, None::<&str>)
-        .or_panic(format_args!("bind {src} -> {dst}"));
+fn cdi_hook_path_in(manifest: Option<&Manifest>, root: &str) -> Option<String> {
+    manifest?
+        .binaries
+        .iter()
+        .find(|b| Path::new(b).file_name().is_some_and(|f| f == CDI_HOOK))
+        .map(|b| format!("{root}/{b}"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_utils::require_root;
-    use std::fs;
-    use tempfile::TempDir;
 
     // === ROOT / MOUNT_BASE contract ===
 
@@ -203,30 +160,21 @@ mod tests {
         );
     }
 
-    // === modprobe_dirname ===
-
-    #[test]
-    fn test_modprobe_dirname_nvidia_with_extension() {
-        assert_eq!(
-            modprobe_dirname_in(true, ROOT, "nvidia"),
-            Some(ROOT.to_owned())
-        );
-        assert_eq!(
-            modprobe_dirname_in(true, ROOT, "nvidia-uvm"),
-            Some(ROOT.to_owned())
-        );
-    }
-
-    #[test]
-    fn test_modprobe_dirname_nvidia_without_extension() {
-        assert_eq!(modprobe_dirname_in(false, ROOT, "nvidia"), None);
-    }
-
-    #[test]
-    fn test_modprobe_dirname_base_module_with_extension() {
-        // In-tree modules ship in the base image, not the extension.
-        assert_eq!(modprobe_dirname_in(true, ROOT, "ib_umad"), None);
-        assert_eq!(modprobe_dirname_in(true, ROOT, "mlx5_ib"), None);
+    /// What the `gpu` extension's `components.toml` declares.
+    fn gpu_manifest() -> Manifest {
+        Manifest {
+            firmware_dirs: vec!["lib/firmware/nvidia".to_owned()],
+            library_dirs: vec!["usr/lib/x86_64-linux-gnu".to_owned()],
+            module_dirname: Some(".".to_owned()),
+            module_prefixes: vec!["nvidia".to_owned()],
+            module_sha256: Vec::new(),
+            binaries: vec![
+                "bin/nvidia-smi".to_owned(),
+                "bin/nvidia-cdi-hook".to_owned(),
+            ],
+            attester_variants: vec!["nvidia".to_owned()],
+            digest: String::new(),
+        }
     }
 
     // === driver_root ===
@@ -246,26 +194,74 @@ mod tests {
     #[test]
     fn test_cdi_hook_path_with_extension() {
         assert_eq!(
-            cdi_hook_path_in(true, ROOT),
+            cdi_hook_path_in(Some(&gpu_manifest()), ROOT),
             Some(format!("{ROOT}/bin/nvidia-cdi-hook"))
         );
     }
 
     #[test]
     fn test_cdi_hook_path_without_extension() {
-        assert_eq!(cdi_hook_path_in(false, ROOT), None);
+        assert_eq!(cdi_hook_path_in(None, ROOT), None);
+        assert_eq!(cdi_hook_path_in(Some(&Manifest::default()), ROOT), None);
     }
 
-    // === attester_variant ===
+    // === GpuConsumer ===
+
+    fn gpu_extension(manifest: Option<Manifest>) -> Extension {
+        Extension {
+            name: NAME.to_owned(),
+            root_hash: String::new(),
+            hash_alg: "sha256",
+            signed: false,
+            manifest,
+        }
+    }
 
     #[test]
-    fn test_attester_variant_with_extension() {
-        assert_eq!(attester_variant_in(true), Some(ATTESTER_VARIANT_NVIDIA));
+    fn test_consumer_accepts_only_gpu() {
+        assert!(GpuConsumer.accepts(&gpu_extension(None)));
+        let mut other = gpu_extension(None);
+        other.name = "coco".to_owned();
+        assert!(!GpuConsumer.accepts(&other));
     }
 
     #[test]
-    fn test_attester_variant_without_extension() {
-        assert_eq!(attester_variant_in(false), None);
+    fn test_consumer_contributes_manifest() {
+        let c = GpuConsumer.contribute(ROOT, &gpu_extension(Some(gpu_manifest())));
+        assert_eq!(c.binds[0].dst, "/lib/firmware/nvidia");
+        assert_eq!(c.library_dirs, [format!("{ROOT}/usr/lib/x86_64-linux-gnu")]);
+        assert_eq!(c.modprobe, [("nvidia".to_owned(), ROOT.to_owned())]);
+        assert_eq!(
+            c.env,
+            [(
+                consumer::ATTESTER_VARIANT_ENV.to_owned(),
+                "nvidia".to_owned()
+            )]
+        );
+    }
+
+    /// Extensions built before `components.toml` keep their fixed layout.
+    #[test]
+    fn test_consumer_without_manifest() {
+        let c = GpuConsumer.contribute(ROOT, &gpu_extension(None));
+        assert_eq!(
+            c,
+            GpuConsumer.contribute(ROOT, &gpu_extension(Some(legacy_manifest())))
+        );
+        assert_eq!(c.binds[0].src, format!("{ROOT}/lib/firmware/nvidia"));
+        assert_eq!(
+            c.library_dirs,
+            [format!(
+                "{ROOT}/usr/lib/{}-linux-gnu",
+                std::env::consts::ARCH
+            )]
+        );
+        assert_eq!(c.modprobe, [("nvidia".to_owned(), ROOT.to_owned())]);
+        assert_eq!(c.env[0].1, "nvidia");
+        assert_eq!(
+            cdi_hook_path_in(Some(&legacy_manifest()), ROOT),
+            Some(format!("{ROOT}/bin/nvidia-cdi-hook"))
+        );
     }
 
     // === public wrappers (monolithic image: no extension mounted) ===
@@ -280,73 +276,7 @@ mod tests {
             return;
         }
         assert_eq!(path("/bin/nvidia-smi"), "/bin/nvidia-smi");
-        assert_eq!(modprobe_dirname("nvidia"), None);
         assert_eq!(driver_root(), None);
         assert_eq!(cdi_hook_path(), None);
-        assert_eq!(attester_variant(), None);
-        setup(); // no-op without the extension
-    }
-
-    // === bind_firmware ===
-
-    #[test]
-    fn test_bind_firmware_skips_without_src() {
-        // No firmware tree in the extension: no-op, and in particular no attempt
-        // to bind onto the (nonexistent) destination. Needs no root.
-        bind_firmware_in("/nonexistent/firmware/src", "/nonexistent/firmware/dst");
-    }
-
-    #[test]
-    #[cfg_attr(
-        miri,
-        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
-    )]
-    fn test_bind_firmware_binds_when_src_present() {
-        require_root();
-        let src = TempDir::new().unwrap();
-        let dst = TempDir::new().unwrap();
-        fs::write(src.path().join("gsp.bin.txt"), "firmware").unwrap();
-
-        bind_firmware_in(src.path().to_str().unwrap(), dst.path().to_str().unwrap());
-
-        let visible = dst.path().join("gsp.bin.txt");
-        assert!(visible.exists());
-        assert_eq!(fs::read_to_string(visible).unwrap(), "firmware");
-
-        nix::mount::umount(dst.path()).unwrap();
-    }
-
-    // === ldso_conf ===
-
-    #[test]
-    fn test_ldso_conf_includes_base_and_extension() {
-        assert_eq!(
-            ldso_conf(&format!("{ROOT}/usr/lib")),
-            format!("include /etc/ld.so.conf.d/*.conf\n{ROOT}/usr/lib\n")
-        );
-    }
-
-    // === bind_over (needs root for mount(2)) ===
-
-    #[test]
-    #[cfg_attr(
-        miri,
-        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
-    )]
-    fn test_bind_over_makes_source_visible() {
-        require_root();
-        let src = TempDir::new().unwrap();
-        let dst = TempDir::new().unwrap();
-        fs::write(src.path().join("gsp.bin.txt"), "firmware").unwrap();
-
-        let src_str = src.path().to_str().unwrap();
-        let dst_str = dst.path().to_str().unwrap();
-        bind_over(src_str, dst_str);
-
-        let visible = dst.path().join("gsp.bin.txt");
-        assert!(visible.exists());
-        assert_eq!(fs::read_to_string(visible).unwrap(), "firmware");
-
-        nix::mount::umount(dst.path()).unwrap();
     }
 }
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! GPUDirect RDMA, Storage and gdrcopy from `nvrc.gpudirect=rdma,gds,gdrcopy`.
+//!
+//! Each feature is a module loaded after nvidia and nvidia-uvm: `rdma` loads
+//! mlx5_ib and then nvidia-peermem, which registers the GPU as a peer memory
+//! client of ib_core; `gds` loads nvidia-fs; `gdrcopy` loads gdrdrv. Without
+//! udev, NVRC creates the character devices the kernel does not.
+
+use log::{info, warn};
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::macros::ResultExt;
+use crate::modprobe;
+
+const PROC_DEVICES: &str = "/proc/devices";
+const MEMORY_PEERS: &str = "/sys/kernel/mm/memory_peers";
+const SYS_MODULE: &str = "/sys/module";
+const DEV: &str = "/dev";
+
+/// nvidia-fs registers one minor per mount it can serve.
+const NVFS_DEVICES: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    Rdma,
+    Gds,
+    Gdrcopy,
+}
+
+/// Parse the comma-separated value of `nvrc.gpudirect`.
+pub fn parse(value: &str) -> Result<Vec<Feature>, String> {
+    let mut features = Vec::new();
+    for name in value.split(',').filter(|s| !s.is_empty()) {
+        let feature = match name {
+            "rdma" => Feature::Rdma,
+            "gds" => Feature::Gds,
+            "gdrcopy" => Feature::Gdrcopy,
+            _ => return Err(format!("nvrc.gpudirect: unknown feature {name:?}")),
+        };
+        if !features.contains(&feature) {
+            features.push(feature);
+        }
+    }
+    Ok(features)
+}
+
+/// Modules the features add to the GPU mode allow-list, dependencies included.
+pub fn modules(features: &[Feature]) -> Vec<&'static str> {
+    let mut modules = Vec::new();
+    for feature in features {
+        match feature {
+            Feature::Rdma => {
+                modules.extend(modprobe::CX7_MODULES);
+                modules.push("nvidia_peermem");
+            }
+            Feature::Gds => modules.push("nvidia_fs"),
+            Feature::Gdrcopy => modules.push("gdrdrv"),
+        }
+    }
+    modules
+}
+
+/// Load and check every feature, in `rdma`, `gds`, `gdrcopy` order.
+/// Must run after nvidia and nvidia-uvm are loaded.
+pub fn setup(features: &[Feature]) {
+    if features.contains(&Feature::Rdma) {
+        // nvidia-peermem registers with ib_core but needs an HCA driver behind it
+        modprobe::load("mlx5_ib");
+        modprobe::load("nvidia-peermem");
+        check_peer(Path::new(MEMORY_PEERS), Path::new(SYS_MODULE));
+    }
+    if features.contains(&Feature::Gds) {
+        modprobe::load("nvidia-fs");
+        let devices = fs::read_to_string(PROC_DEVICES).or_panic(PROC_DEVICES);
+        let nodes: Vec<_> = (0..NVFS_DEVICES)
+            .map(|minor| (format!("nvidia-fs{minor}"), minor))
+            .collect();
+        create_nodes(Path::new(DEV), &devices, "nvidia-fs", &nodes);
+    }
+    if features.contains(&Feature::Gdrcopy) {
+        modprobe::load("gdrdrv");
+        let devices = fs::read_to_string(PROC_DEVICES).or_panic(PROC_DEVICES);
+        create_nodes(Path::new(DEV), &devices, "gdrdrv", &[("gdrdrv".into(), 0)]);
+    }
+}
+
+/// nvidia-peermem loads even when ib_core lacks peer memory support, and
+/// then RDMA silently falls back to bouncing through host memory. With the
+/// peer memory core present, nv_mem must show up under `memory_peers`;
+/// otherwise the module being live is all there is to check.
+fn check_peer(memory_peers: &Path, sys_module: &Path) {
+    if memory_peers.is_dir() {
+        let client = memory_peers.join("nv_mem");
+        if !client.is_dir() {
+            panic!(
+                "gpudirect: nvidia-peermem not registered in {}",
+                memory_peers.display()
+            );
+        }
+        info!("gpudirect: rdma peer memory client {}", client.display());
+        return;
+    }
+    let live = fs::read_to_string(sys_module.join("nvidia_peermem/initstate"))
+        .is_ok_and(|state| state.trim() == "live");
+    if !live {
+        panic!("gpudirect: nvidia-peermem is not live");
+    }
+    warn!(
+        "gpudirect: no {}, cannot confirm peer memory registration",
+        memory_peers.display()
+    );
+}
+
+/// The character major `driver` registered, from `/proc/devices`.
+fn char_major(devices: &str, driver: &str) -> Option<u64> {
+    devices
+        .lines()
+        .skip_while(|line| line.trim() != "Character devices:")
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty())
+        .find_map(|line| {
+            let (major, name) = line.trim().split_once(' ')?;
+            (name.trim() == driver).then(|| major.parse().ok())?
+        })
+}
+
+/// Create the `(name, minor)` nodes of `driver` under `dev`, skipping any
+/// devtmpfs already created.
+fn create_nodes(dev: &Path, devices: &str, driver: &str, nodes: &[(String, u64)]) {
+    let major = char_major(devices, driver)
+        .unwrap_or_else(|| panic!("gpudirect: {driver} has no character major"));
+    for (name, minor) in nodes {
+        let node = dev.join(name);
+        if node.exists() {
+            continue;
+        }
+        mknod(
+            &node,
+            SFlag::S_IFCHR,
+            Mode::from_bits_truncate(0o666),
+            makedev(major, *minor),
+        )
+        .or_panic(format_args!("mknod {}", node.display()));
+        // mknod honours the umask
+        fs::set_permissions(&node, fs::Permissions::from_mode(0o666))
+            .or_panic(format_args!("chmod {}", node.display()));
+        info!("gpudirect: created {} ({major}:{minor})", node.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::require_root;
+    use nix::sys::stat::{major, minor, stat};
+    use std::panic;
+    use tempfile::TempDir;
+
+    const DEVICES: &str = "\
+Character devices:
+  1 mem
+195 nvidia-frontend
+234 gdrdrv
+241 nvidia-fs
+
+Block devices:
+  7 loop
+241 gdrdrv-block
+";
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("rdma,gds,gdrcopy").unwrap(),
+            [Feature::Rdma, Feature::Gds, Feature::Gdrcopy]
+        );
+        assert_eq!(parse("gds,gds,").unwrap(), [Feature::Gds]);
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("rdma,nvme").unwrap_err().contains("nvme"));
+    }
+
+    #[test]
+    fn test_modules() {
+        let rdma = modules(&[Feature::Rdma]);
+        assert!(rdma.contains(&"mlx5_ib") && rdma.contains(&"ib_core"));
+        assert_eq!(rdma.last(), Some(&"nvidia_peermem"));
+        assert_eq!(
+            modules(&[Feature::Gds, Feature::Gdrcopy]),
+            ["nvidia_fs", "gdrdrv"]
+        );
+    }
+
+    #[test]
+    fn test_char_major() {
+        assert_eq!(char_major(DEVICES, "nvidia-fs"), Some(241));
+        assert_eq!(char_major(DEVICES, "gdrdrv"), Some(234));
+        // Block majors do not count
+        assert_eq!(char_major(DEVICES, "loop"), None);
+        assert_eq!(char_major(DEVICES, "nvidia"), None);
+    }
+
+    #[test]
+    fn test_check_peer_registered() {
+        let sys = TempDir::new().unwrap();
+        let peers = sys.path().join("memory_peers");
+        fs::create_dir_all(peers.join("nv_mem")).unwrap();
+        check_peer(&peers, sys.path());
+
+        fs::remove_dir(peers.join("nv_mem")).unwrap();
+        let result = panic::catch_unwind(|| check_peer(&peers, sys.path()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_peer_without_peer_core() {
+        let sys = TempDir::new().unwrap();
+        let peers = sys.path().join("memory_peers");
+        let result = panic::catch_unwind(|| check_peer(&peers, sys.path()));
+        assert!(result.is_err());
+
+        let module = sys.path().join("nvidia_peermem");
+        fs::create_dir_all(&module).unwrap();
+        fs::write(module.join("initstate"), "live\n").unwrap();
+        check_peer(&peers, sys.path());
+    }
+
+    #[test]
+    fn test_create_nodes_missing_driver() {
+        let dev = TempDir::new().unwrap();
+        let result =
+            panic::catch_unwind(|| create_nodes(dev.path(), DEVICES, "nvidia", &[("x".into(), 0)]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_create_nodes() {
+        require_root();
+        let dev = TempDir::new().unwrap();
+        fs::write(dev.path().join("nvidia-fs0"), "").unwrap();
+        let nodes = [("nvidia-fs0".into(), 0), ("nvidia-fs1".into(), 1)];
+        create_nodes(dev.path(), DEVICES, "nvidia-fs", &nodes);
+
+        // An existing node is left alone
+        assert!(dev.path().join("nvidia-fs0").metadata().unwrap().is_file());
+        let st = stat(&dev.path().join("nvidia-fs1")).unwrap();
+        assert_eq!(major(st.st_rdev), 241);
+        assert_eq!(minor(st.st_rdev), 1);
+        assert_eq!(st.st_mode & 0o777, 0o666);
+    }
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Graphics sub-mode of GPU mode, enabled with `nvrc.graphics=on`.
+//!
+//! Rendering and video-encode workloads need the display side of the driver:
+//! nvidia-modeset, and nvidia-drm with `modeset=1 fbdev=1` so the GPU shows up
+//! as a DRM device. devtmpfs creates `/dev/dri` from that; without a card and
+//! a render node there is nothing for the CDI spec to hand to containers.
+
+use log::info;
+use std::fs;
+use std::path::Path;
+
+use crate::modprobe;
+
+const DRI: &str = "/dev/dri";
+
+/// Modules graphics adds to the GPU mode allow-list, with the DRM helpers
+/// nvidia-drm pulls in from the guest kernel.
+pub const MODULES: &[&str] = &[
+    "nvidia_modeset",
+    "nvidia_drm",
+    "drm",
+    "drm_kms_helper",
+    "drm_ttm_helper",
+    "drm_client_lib",
+    "ttm",
+    "video",
+    "backlight",
+    "i2c_core",
+];
+
+/// Load nvidia-modeset and nvidia-drm and check the DRM nodes appeared.
+/// Must run after nvidia is loaded.
+pub fn setup() {
+    modprobe::load("nvidia-modeset");
+    modprobe::load("nvidia-drm");
+    check_dri(Path::new(DRI));
+}
+
+fn check_dri(dri: &Path) {
+    let entries: Vec<String> = fs::read_dir(dri)
+        .unwrap_or_else(|e| panic!("graphics: read {}: {e}", dri.display()))
+        .filter_map(Result::ok)
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    for prefix in ["card", "renderD"] {
+        if !entries.iter().any(|name| name.starts_with(prefix)) {
+            panic!("graphics: no {prefix}* in {}", dri.display());
+        }
+    }
+    info!("graphics: {} {}", dri.display(), entries.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_check_dri() {
+        let dev = TempDir::new().unwrap();
+        let dri = dev.path().join("dri");
+        let result = panic::catch_unwind(|| check_dri(&dri));
+        assert!(result.is_err());
+
+        fs::create_dir(&dri).unwrap();
+        fs::write(dri.join("card0"), "").unwrap();
+        // modeset without a render node is not enough
+        let result = panic::catch_unwind(|| check_dri(&dri));
+        assert!(result.is_err());
+
+        fs::write(dri.join("renderD128"), "").unwrap();
+        check_dri(&dri);
+    }
+}
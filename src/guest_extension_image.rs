@@ -4,9 +4,10 @@
 //! Mount cold-plugged composable VM image extensions before kata-agent starts.
 //!
 //! For each virtio-blk extension (serial `extension-<name>`) NVRC dm-verity-opens
-//! the device with `kata.extension.<name>.verity_params` from the (measured)
-//! kernel command line and mounts the EROFS read-only at
-//! `/run/kata-extensions/<name>/`.
+//! the device (natively, via [`crate::dm`]) with `kata.extension.<name>.verity_params` from the (measured)
+//! kernel command line and mounts the payload read-only at
+//! `/run/kata-extensions/<name>/`. The payload is EROFS unless `fstype=` names
+//! squashfs or ext4.
 //!
 //! Being confidential-only, NVRC fails closed rather than downgrade: discovery is
 //! device-driven and reconciled 1:1 with the command line, with no unmeasured
@@ -16,21 +17,36 @@
 //!   device, no params -> panic (verity stripped)
 //!   params, no device -> panic (mismatch / missing extension)
 //!
+//! Data and hash partitions are found in the device's GPT by label (`data`,
+//! `verity`) or type GUID, parsed by [`crate::gpt`] rather than assumed from
+//! partition order.
+//!
+//! An optional `sig=` (inline base64, or `partition` for the `verity-sig`
+//! partition) carries a PKCS#7 signature over the root hash. The kernel checks it against the
+//! secondary/`.platform` keyring when the table loads, so a bad or unsigned
+//! hash never becomes a device (`CONFIG_DM_VERITY_VERIFY_ROOTHASH_SIG`).
+//!
 //! See ARCHITECTURE.md ("Composable Image Extensions") for the design rationale.
 
 use log::info;
 use nix::mount::MsFlags;
 use std::fs;
+use std::path::Path;
 
-use crate::execute::foreground;
+use crate::dm::{self, Target};
+use crate::gpt;
 use crate::macros::ResultExt;
+use crate::manifest::{self, Manifest};
+use crate::modprobe;
+use crate::mount;
 
 const CMDLINE: &str = "/proc/cmdline";
+const FILESYSTEMS: &str = "/proc/filesystems";
 const SYS_BLOCK: &str = "/sys/block";
+const DEV: &str = "/dev";
 /// Extension mount tree (`<MOUNT_BASE>/<name>`); source of truth for
 /// [`crate::gpu_extension::ROOT`].
 pub(crate) const MOUNT_BASE: &str = "/run/kata-extensions";
-const VERITYSETUP: &str = "/usr/sbin/veritysetup";
 
 /// Prefix for the virtio-blk serial and the dm-verity device-mapper target.
 const EXTENSION_PREFIX: &str = "extension-";
@@ -40,24 +56,174 @@ const EXTENSION_PREFIX: &str = "extension-";
 const CMDLINE_KEY_PREFIX: &str = "kata.extension.";
 const CMDLINE_KEY_SUFFIX: &str = ".verity_params";
 
+/// `sig=`/`fec_device=` value selecting a sidecar partition.
+const SIG_PARTITION: &str = "partition";
+
+/// GPT partition labels (`systemd-repart` naming) and, for data and hash,
+/// the type GUIDs accepted when no partition carries the label.
+const DATA_LABEL: &str = "data";
+const DATA_TYPES: &[&str] = &[gpt::LINUX_DATA];
+const HASH_LABEL: &str = "verity";
+const HASH_TYPES: &[&str] = &[gpt::ROOT_VERITY_X86_64, gpt::ROOT_VERITY_ARM64];
+const SIG_LABEL: &str = "verity-sig";
+const FEC_LABEL: &str = "fec";
+const LABELS: &[&str] = &[DATA_LABEL, HASH_LABEL, SIG_LABEL, FEC_LABEL];
+
+/// Reed-Solomon parity bytes per 255-byte codeword the kernel accepts.
+const FEC_ROOTS: std::ops::RangeInclusive<u64> = 2..=24;
+
 /// dm-verity parameters from `kata.extension.<name>.verity_params`, matching the
-/// comma-separated list emitted by the Kata image builder. Hash is sha256.
+/// comma-separated list emitted by the Kata image builder. Hash defaults to
+/// sha256; unknown keys are rejected so a typo cannot drop a protection.
 struct VerityParams {
     root_hash: String,
     salt: String,
     data_blocks: u64,
     data_block_size: u64,
     hash_block_size: u64,
+    hash_alg: HashAlg,
+    fec: Option<Fec>,
+    sig: Option<RootHashSig>,
+    fstype: FsType,
+}
+
+/// `hash_alg=` values, as `veritysetup --hash` spells them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HashAlg {
+    Sha256,
+    Sha512,
+    Blake2b,
+}
+
+impl HashAlg {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sha256" => Some(Self::Sha256),
+            "sha512" => Some(Self::Sha512),
+            "blake2b" => Some(Self::Blake2b),
+            _ => None,
+        }
+    }
+
+    /// Crypto API name: what the table carries and IPE matches against.
+    fn kernel_name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake2b => "blake2b-256",
+        }
+    }
+
+    fn digest_size(self) -> u64 {
+        match self {
+            Self::Sha512 => 64,
+            Self::Sha256 | Self::Blake2b => 32,
+        }
+    }
+}
+
+/// `fstype=` values: the payload filesystem. The manifest lives inside the
+/// payload, so only the (measured) command line can name it; EROFS by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FsType {
+    Erofs,
+    Squashfs,
+    Ext4,
+}
+
+impl FsType {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "erofs" => Some(Self::Erofs),
+            "squashfs" => Some(Self::Squashfs),
+            "ext4" => Some(Self::Ext4),
+            _ => None,
+        }
+    }
+
+    /// Name in `/proc/filesystems`, for mount(2) and the module alias.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Erofs => "erofs",
+            Self::Squashfs => "squashfs",
+            Self::Ext4 => "ext4",
+        }
+    }
+
+    /// Mount data. A dirty ext4 journal cannot be replayed onto the read-only
+    /// verity device, so the mount would fail; `noload` skips it.
+    fn options(self) -> Option<&'static str> {
+        match self {
+            Self::Ext4 => Some("noload"),
+            Self::Erofs | Self::Squashfs => None,
+        }
+    }
+}
+
+/// Forward error correction (`veritysetup --fec-*`): Reed-Solomon parity over
+/// data and hash tree, so a few corrupt blocks are repaired, not fatal.
+#[derive(Debug, PartialEq)]
+struct Fec {
+    roots: u64,
+    /// `fec_device=partition` (the `fec` partition); otherwise the hash partition.
+    partition: bool,
+    /// Byte offset of the parity area on the FEC device.
+    offset: u64,
+}
+
+/// Optional PKCS#7 signature over the root hash (`roothash_sig`), verified by
+/// the kernel against the secondary/platform keyring when the table loads.
+#[derive(Debug, PartialEq)]
+enum RootHashSig {
+    /// `sig=<base64 DER>` on the cmdline.
+    Inline(Vec<u8>),
+    /// `sig=partition`: DER blob at the start of the `verity-sig` partition.
+    Partition,
+}
+
+/// Extension block device partitions, as `/dev` paths.
+struct Partitions {
+    data: String,
+    hash: String,
+    /// Hash partition size in bytes.
+    hash_size: u64,
+    sig: Option<String>,
+    fec: Option<String>,
+}
+
+/// A mounted extension, the dm-verity root hash it was opened with and the
+/// manifest it ships, if any.
+#[derive(Debug)]
+pub struct Extension {
+    pub name: String,
+    pub root_hash: String,
+    /// Kernel name of the verity hash algorithm (`sha256`, `blake2b-256`, ...).
+    pub hash_alg: &'static str,
+    /// Opened with a root hash signature the kernel verified.
+    pub signed: bool,
+    pub manifest: Option<Manifest>,
 }
 
 /// Mount every cold-plugged extension; no-op on non-composable images.
-pub fn mount_all() {
+/// Returns what was mounted so the root hashes can be handed to the LSMs and
+/// the manifests to their consumers.
+pub fn mount_all() -> Vec<Extension> {
     let cmdline = fs::read_to_string(CMDLINE).or_panic(format_args!("read {CMDLINE}"));
     let params = parse_extensions(&cmdline);
     let devices = discover_extensions(SYS_BLOCK);
-    for (name, dev, verity) in plan_mounts(&params, &devices) {
-        mount_extension(name, dev, verity);
-    }
+    plan_mounts(&params, &devices)
+        .into_iter()
+        .map(|(name, dev, verity)| {
+            mount_extension(name, dev, verity);
+            Extension {
+                name: name.to_owned(),
+                root_hash: verity.root_hash.clone(),
+                hash_alg: verity.hash_alg.kernel_name(),
+                signed: verity.sig.is_some(),
+                manifest: manifest::load(&format!("{MOUNT_BASE}/{name}")),
+            }
+        })
+        .collect()
 }
 
 /// Reconcile discovered devices against command-line params, failing closed:
@@ -90,14 +256,58 @@ fn plan_mounts<'a>(
 }
 
 fn mount_extension(name: &str, dev: &str, params: &VerityParams) {
-    let (data, hash) = find_partitions(SYS_BLOCK, dev);
+    let parts = find_partitions(SYS_BLOCK, DEV, dev, params);
 
     let dm_name = format!("{EXTENSION_PREFIX}{name}");
-    let args = verity_args(&dm_name, &data, &hash, params);
-    let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
-    foreground(VERITYSETUP, &arg_refs);
+    // The kernel looks the signature up by description in our keyrings while
+    // the table loads; it is not needed afterwards.
+    let sig_key = params.sig.as_ref().map(|sig| {
+        let blob = match sig {
+            RootHashSig::Inline(blob) => blob.clone(),
+            RootHashSig::Partition => read_sig_partition(name, parts.sig.as_deref()),
+        };
+        let desc = format!("nvrc:{dm_name}");
+        (add_user_key(&desc, &blob), desc)
+    });
+    let fec_dev = params.fec.as_ref().map(|fec| {
+        if !fec.partition {
+            return dm::dev_id(&parts.hash);
+        }
+        let part = parts.fec.as_deref().unwrap_or_else(|| {
+            panic!("extension {name}: fec_device=partition but no {FEC_LABEL} partition")
+        });
+        dm::dev_id(part)
+    });
+    let table = verity_table(
+        &dm::dev_id(&parts.data),
+        &dm::dev_id(&parts.hash),
+        fec_dev.as_deref(),
+        parts.hash_size,
+        params,
+        sig_key.as_ref().map(|(_, desc)| desc.as_str()),
+    );
+    let mapper = dm::create_readonly(&dm_name, &table);
+    if let Some((key, desc)) = &sig_key {
+        invalidate_key(*key);
+        info!("extension {name}: root hash signature verified ({desc})");
+    }
 
-    let mapper = format!("/dev/mapper/{dm_name}");
+    // Read back what the kernel actually loaded, and refuse a device that is
+    // already corrupted (`C`) instead of verified (`V`).
+    let loaded = dm::table(&dm_name);
+    if loaded != table.line() {
+        panic!(
+            "extension {name}: dm table {loaded:?} differs from requested {:?}",
+            table.line()
+        );
+    }
+    let status = dm::status(&dm_name);
+    info!("extension {name}: dm {status}");
+    if status.split_whitespace().nth(3) != Some("V") {
+        panic!("extension {name}: dm-verity status {status:?}, expected V");
+    }
+
+    require_fs(name, params.fstype);
     let target = format!("{MOUNT_BASE}/{name}");
     fs::create_dir_all(&target).or_panic(format_args!("create_dir_all {target}"));
 
@@ -106,15 +316,35 @@ fn mount_extension(name: &str, dev: &str, params: &VerityParams) {
     nix::mount::mount(
         Some(mapper.as_str()),
         target.as_str(),
-        Some("erofs"),
+        Some(params.fstype.name()),
         flags,
-        None::<&str>,
+        params.fstype.options(),
     )
     .or_panic(format_args!(
         "mount extension {name} ({mapper}) on {target}"
     ));
 
-    info!("mounted extension {name} at {target}");
+    info!(
+        "mounted extension {name} ({}) at {target}",
+        params.fstype.name()
+    );
+}
+
+/// Modular filesystems only appear in `/proc/filesystems` once loaded, so
+/// load the module before giving up on one.
+fn require_fs(name: &str, fstype: FsType) {
+    let available = || {
+        let filesystems =
+            fs::read_to_string(FILESYSTEMS).or_panic(format_args!("read {FILESYSTEMS}"));
+        mount::fs_available(&filesystems, fstype.name())
+    };
+    if available() {
+        return;
+    }
+    modprobe::load(fstype.name());
+    if !available() {
+        panic!("extension {name}: kernel lacks {} support", fstype.name());
+    }
 }
 
 /// Parse `kata.extension.<name>.verity_params` entries into `(name, params)`.
@@ -138,15 +368,40 @@ fn parse_verity_params(name: &str, value: &str) -> VerityParams {
     let mut data_blocks = None;
     let mut data_block_size = None;
     let mut hash_block_size = None;
+    let mut hash_alg = HashAlg::Sha256;
+    let mut fec_roots = None;
+    let mut fec_partition = None;
+    let mut fec_offset = None;
+    let mut sig = None;
+    let mut fstype = FsType::Erofs;
 
-    for (key, val) in value.split(',').filter_map(|kv| kv.split_once('=')) {
+    for kv in value.split(',') {
+        let (key, val) = kv
+            .split_once('=')
+            .unwrap_or_else(|| panic!("extension {name}: malformed verity_params entry {kv:?}"));
         match key {
             "root_hash" => root_hash = Some(val.to_owned()),
-            "salt" => salt = Some(val.to_owned()),
+            // The kernel reports hex in lowercase; normalize so the table
+            // reads back identical and IPE rules match.
+            "salt" => salt = Some(val.to_ascii_lowercase()),
             "data_blocks" => data_blocks = Some(parse_u64(name, "data_blocks", val)),
             "data_block_size" => data_block_size = Some(parse_u64(name, "data_block_size", val)),
             "hash_block_size" => hash_block_size = Some(parse_u64(name, "hash_block_size", val)),
-            _ => {}
+            "hash_alg" => {
+                hash_alg = HashAlg::parse(val).unwrap_or_else(|| {
+                    panic!("extension {name}: unsupported verity_params hash_alg {val:?}")
+                })
+            }
+            "fec_roots" => fec_roots = Some(parse_u64(name, "fec_roots", val)),
+            "fec_device" => fec_partition = Some(parse_fec_device(name, val)),
+            "fec_offset" => fec_offset = Some(parse_u64(name, "fec_offset", val)),
+            "sig" => sig = Some(parse_sig(name, val)),
+            "fstype" => {
+                fstype = FsType::parse(val).unwrap_or_else(|| {
+                    panic!("extension {name}: unsupported verity_params fstype {val:?}")
+                })
+            }
+            _ => panic!("extension {name}: unknown verity_params key {key:?}"),
         }
     }
 
@@ -158,13 +413,212 @@ fn parse_verity_params(name: &str, value: &str) -> VerityParams {
             .unwrap_or_else(|| panic!("extension {name}: verity_params missing or zero {field}"))
     };
 
-    VerityParams {
-        root_hash: require("root_hash", root_hash),
+    let root_hash = require("root_hash", root_hash).to_ascii_lowercase();
+    if root_hash.len() as u64 != hash_alg.digest_size() * 2
+        || !root_hash.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        panic!(
+            "extension {name}: root_hash is not a {} digest",
+            hash_alg.kernel_name()
+        );
+    }
+
+    // dm-verity only takes power-of-two blocks from a sector up.
+    let require_block_size = |field: &str, v: Option<u64>| {
+        let size = require_num(field, v);
+        if !size.is_power_of_two() || size < 512 {
+            panic!("extension {name}: verity_params {field} {size} is not a power of two >= 512");
+        }
+        size
+    };
+
+    let mut params = VerityParams {
+        root_hash,
         salt: require("salt", salt),
         data_blocks: require_num("data_blocks", data_blocks),
-        data_block_size: require_num("data_block_size", data_block_size),
-        hash_block_size: require_num("hash_block_size", hash_block_size),
+        data_block_size: require_block_size("data_block_size", data_block_size),
+        hash_block_size: require_block_size("hash_block_size", hash_block_size),
+        hash_alg,
+        fec: None,
+        sig,
+        fstype,
+    };
+
+    params.fec = match fec_roots {
+        None if fec_partition.is_some() || fec_offset.is_some() => {
+            panic!("extension {name}: FEC device/offset without fec_roots")
+        }
+        None => None,
+        Some(roots) => Some(check_fec(
+            name,
+            &params,
+            Fec {
+                roots,
+                partition: fec_partition.unwrap_or(false),
+                offset: fec_offset.unwrap_or(0),
+            },
+        )),
+    };
+    params
+}
+
+/// `fec_device=partition` or `fec_device=hash` (parity behind the hash tree,
+/// located by `fec_offset`).
+fn parse_fec_device(name: &str, value: &str) -> bool {
+    match value {
+        SIG_PARTITION => true,
+        "hash" => false,
+        _ => panic!("extension {name}: invalid verity_params fec_device {value:?}"),
+    }
+}
+
+/// FEC parity is addressed in data blocks and must not overlap the hash tree
+/// when it shares the hash partition.
+fn check_fec(name: &str, p: &VerityParams, fec: Fec) -> Fec {
+    if !FEC_ROOTS.contains(&fec.roots) {
+        panic!(
+            "extension {name}: fec_roots {} outside {FEC_ROOTS:?}",
+            fec.roots
+        );
+    }
+    if !fec.offset.is_multiple_of(p.data_block_size) {
+        panic!(
+            "extension {name}: fec_offset {} not a multiple of data_block_size",
+            fec.offset
+        );
+    }
+    let tree = hash_tree_blocks(p) * p.hash_block_size;
+    if !fec.partition && fec.offset < tree {
+        panic!(
+            "extension {name}: fec_offset {} overlaps the {tree}-byte hash tree",
+            fec.offset
+        );
+    }
+    fec
+}
+
+/// Blocks the parity covers, as veritysetup counts them: the data area
+/// followed by the hash device up to the parity area when both share the hash
+/// partition, or the whole hash partition when parity lives on its own.
+fn fec_blocks(p: &VerityParams, fec: &Fec, hash_size: u64) -> u64 {
+    let hash_area = if fec.partition { hash_size } else { fec.offset };
+    p.data_blocks + hash_area / p.data_block_size
+}
+
+/// Hash blocks in the tree (all levels), as the kernel lays it out: each
+/// block holds the largest power of two of digests that fits.
+fn hash_tree_blocks(p: &VerityParams) -> u64 {
+    let per_block = 1u64 << (p.hash_block_size / p.hash_alg.digest_size()).ilog2();
+    let mut level = p.data_blocks;
+    let mut total = 0;
+    while level > 1 {
+        level = level.div_ceil(per_block);
+        total += level;
+    }
+    total
+}
+
+/// `sig=partition` or `sig=<base64>`; an undecodable signature is fatal.
+fn parse_sig(name: &str, value: &str) -> RootHashSig {
+    if value == SIG_PARTITION {
+        return RootHashSig::Partition;
+    }
+    match base64_decode(value) {
+        Some(blob) if !blob.is_empty() => RootHashSig::Inline(blob),
+        _ => panic!("extension {name}: invalid verity_params sig"),
+    }
+}
+
+/// Standard-alphabet base64 with optional `=` padding. No dependency for the
+/// one cmdline field that needs it.
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let digits = input.trim_end_matches('=');
+    if input.len() - digits.len() > 2 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in digits.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // A lone trailing sextet cannot encode a byte.
+    (bits < 6).then_some(out)
+}
+
+/// The sidecar partition is zero-padded to a sector multiple; the DER header
+/// gives the real length of the PKCS#7 blob.
+fn read_sig_partition(name: &str, part: Option<&str>) -> Vec<u8> {
+    let part = part
+        .unwrap_or_else(|| panic!("extension {name}: sig=partition but no {SIG_LABEL} partition"));
+    let raw = fs::read(part).or_panic(format_args!("read {part}"));
+    let len =
+        der_len(&raw).unwrap_or_else(|| panic!("extension {name}: {part} holds no DER signature"));
+    raw[..len].to_vec()
+}
+
+/// Total length of a DER SEQUENCE (tag 0x30) with short or long form length.
+fn der_len(raw: &[u8]) -> Option<usize> {
+    let (&tag, rest) = raw.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (len, header) = if first < 0x80 {
+        (usize::from(first), 2)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |l, &b| (l << 8) | usize::from(b));
+        (len, 2 + n)
+    };
+    let total = header + len;
+    (total <= raw.len()).then_some(total)
+}
+
+/// Add a `user` key to the thread keyring (created on demand), where the
+/// kernel's request_key() from our DM_TABLE_LOAD ioctl finds it.
+fn add_user_key(desc: &str, payload: &[u8]) -> i32 {
+    let kind = c"user";
+    let desc_c = std::ffi::CString::new(desc).or_panic(format_args!("key description {desc}"));
+    // SAFETY: all pointers are valid for the given lengths for the call.
+    let key = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            kind.as_ptr(),
+            desc_c.as_ptr(),
+            payload.as_ptr(),
+            payload.len(),
+            libc::KEY_SPEC_THREAD_KEYRING,
+        )
+    };
+    if key < 0 {
+        panic!("add_key {desc}: {}", std::io::Error::last_os_error());
     }
+    key as i32
+}
+
+/// Best effort: the thread keyring dies with the thread anyway.
+fn invalidate_key(key: i32) {
+    // SAFETY: KEYCTL_INVALIDATE takes a key serial and no pointers.
+    let _ = unsafe { libc::syscall(libc::SYS_keyctl, libc::KEYCTL_INVALIDATE, key) };
 }
 
 fn parse_u64(name: &str, field: &str, value: &str) -> u64 {
@@ -173,27 +627,52 @@ fn parse_u64(name: &str, field: &str, value: &str) -> u64 {
         .unwrap_or_else(|_| panic!("extension {name}: invalid verity_params {field}={value}"))
 }
 
-/// `veritysetup open` args, passed explicitly because the image is built
-/// `--no-superblock`.
-fn verity_args(dm_name: &str, data: &str, hash: &str, p: &VerityParams) -> Vec<String> {
-    vec![
-        "open".to_owned(),
-        "--no-superblock".to_owned(),
-        "--hash".to_owned(),
-        "sha256".to_owned(),
-        "--data-block-size".to_owned(),
-        p.data_block_size.to_string(),
-        "--hash-block-size".to_owned(),
-        p.hash_block_size.to_string(),
-        "--data-blocks".to_owned(),
-        p.data_blocks.to_string(),
-        "--salt".to_owned(),
-        p.salt.clone(),
-        data.to_owned(),
-        dm_name.to_owned(),
-        hash.to_owned(),
-        p.root_hash.clone(),
-    ]
+/// dm-verity table equivalent to `veritysetup open --no-superblock`: format
+/// version 1, hash tree at block 0 of the hash device, devices as
+/// `major:minor`. Length is the data area in 512-byte sectors. With a
+/// signature key the kernel refuses the table unless the signature verifies
+/// (`veritysetup --root-hash-signature`). Optional arguments follow the order
+/// the kernel reports them in, so the table reads back byte-identical.
+fn verity_table(
+    data: &str,
+    hash: &str,
+    fec_dev: Option<&str>,
+    hash_size: u64,
+    p: &VerityParams,
+    sig_key: Option<&str>,
+) -> Target {
+    let salt = if p.salt.is_empty() { "-" } else { &p.salt };
+    let mut params = format!(
+        "1 {data} {hash} {} {} {} 0 {} {} {salt}",
+        p.data_block_size,
+        p.hash_block_size,
+        p.data_blocks,
+        p.hash_alg.kernel_name(),
+        p.root_hash
+    );
+
+    let mut opts = Vec::new();
+    if let (Some(fec), Some(dev)) = (&p.fec, fec_dev) {
+        opts.extend([
+            format!("use_fec_from_device {dev}"),
+            format!("fec_blocks {}", fec_blocks(p, fec, hash_size)),
+            format!("fec_start {}", fec.offset / p.data_block_size),
+            format!("fec_roots {}", fec.roots),
+        ]);
+    }
+    if let Some(desc) = sig_key {
+        opts.push(format!("root_hash_sig_key_desc {desc}"));
+    }
+    if !opts.is_empty() {
+        let count: usize = opts.iter().map(|o| o.split(' ').count()).sum();
+        params.push_str(&format!(" {count} {}", opts.join(" ")));
+    }
+    Target {
+        start: 0,
+        length: p.data_blocks * p.data_block_size / 512,
+        kind: "verity",
+        params,
+    }
 }
 
 /// Discover extension devices by `extension-<name>` serial into `(name, dev)`
@@ -224,32 +703,103 @@ fn discover_extensions(sys_block: &str) -> Vec<(String, String)> {
         .collect()
 }
 
-/// Data (partition 1) and hash (partition 2) paths, read from sysfs so the
-/// naming convention (`vdb1` vs `nvme0n1p1`) does not matter.
-fn find_partitions(sys_block: &str, dev: &str) -> (String, String) {
+/// Locate data, hash and the optional signature and FEC partitions in the
+/// GPT of `dev`, by label or type GUID, and map them to device nodes.
+///
+/// The kernel's view (sysfs `start`/`size`) must agree with the table, and the
+/// data partition must be exactly `data_blocks * data_block_size` long: a
+/// layout we misread is fatal rather than silently verity-opening the wrong
+/// range.
+fn find_partitions(sys_block: &str, dev_dir: &str, dev: &str, p: &VerityParams) -> Partitions {
     let dir = format!("{sys_block}/{dev}");
-    let mut data = None;
-    let mut hash = None;
+    let sector: u64 = read_sysfs_u64(&format!("{dir}/queue/logical_block_size"));
+    let table = gpt::read(Path::new(&format!("{dev_dir}/{dev}")), sector)
+        .or_panic(format_args!("extension device {dev}: partition table"));
 
-    for entry in fs::read_dir(&dir)
-        .or_panic(format_args!("read_dir {dir}"))
-        .flatten()
-    {
-        let Ok(number) = fs::read_to_string(entry.path().join("partition")) else {
-            continue;
-        };
-        let part = format!("/dev/{}", entry.file_name().to_string_lossy());
-        match number.trim() {
-            "1" => data = Some(part),
-            "2" => hash = Some(part),
-            _ => {}
+    let node = |part: &gpt::Partition| {
+        let name = partition_node(&dir, part.number).unwrap_or_else(|| {
+            panic!(
+                "extension device {dev}: no kernel partition {}",
+                part.number
+            )
+        });
+        let start = read_sysfs_u64(&format!("{dir}/{name}/start"));
+        let size = read_sysfs_u64(&format!("{dir}/{name}/size"));
+        if start * 512 != part.first_lba * sector || size * 512 != part.sectors() * sector {
+            panic!(
+                "extension device {dev}: {name} does not match GPT partition {}",
+                part.number
+            );
         }
+        (format!("{dev_dir}/{name}"), part.sectors() * sector)
+    };
+    let role = |label: &str, types: &[&str]| {
+        select_partition(&table, label, types)
+            .unwrap_or_else(|e| panic!("extension device {dev}: {e}"))
+            .map(node)
+    };
+
+    let (data, data_size) = role(DATA_LABEL, DATA_TYPES)
+        .unwrap_or_else(|| panic!("extension device {dev}: missing {DATA_LABEL} partition"));
+    let (hash, hash_size) = role(HASH_LABEL, HASH_TYPES)
+        .unwrap_or_else(|| panic!("extension device {dev}: missing {HASH_LABEL} partition"));
+
+    if data_size != p.data_blocks * p.data_block_size {
+        panic!(
+            "extension device {dev}: data partition is {data_size} bytes, verity_params describe {}",
+            p.data_blocks * p.data_block_size
+        );
+    }
+    let tree = hash_tree_blocks(p) * p.hash_block_size;
+    if hash_size < tree {
+        panic!("extension device {dev}: hash partition is {hash_size} bytes, tree needs {tree}");
     }
 
-    (
-        data.unwrap_or_else(|| panic!("extension device {dev}: missing data partition (1)")),
-        hash.unwrap_or_else(|| panic!("extension device {dev}: missing hash partition (2)")),
-    )
+    Partitions {
+        data,
+        hash,
+        hash_size,
+        sig: role(SIG_LABEL, &[]).map(|(path, _)| path),
+        fec: role(FEC_LABEL, &[]).map(|(path, _)| path),
+    }
+}
+
+/// The partition labelled `label`, else the one of a `types` GUID that is not
+/// labelled for another role. More than one candidate is an error.
+fn select_partition<'a>(
+    table: &'a [gpt::Partition],
+    label: &str,
+    types: &[&str],
+) -> Result<Option<&'a gpt::Partition>, String> {
+    let unique = |found: Vec<&'a gpt::Partition>, what: &str| match found[..] {
+        [] => Ok(None),
+        [one] => Ok(Some(one)),
+        _ => Err(format!("{} partitions match {what}", found.len())),
+    };
+    let labelled = table.iter().filter(|p| p.label == label).collect();
+    if let Some(part) = unique(labelled, label)? {
+        return Ok(Some(part));
+    }
+    let typed = table
+        .iter()
+        .filter(|p| types.contains(&p.type_guid.as_str()) && !LABELS.contains(&p.label.as_str()))
+        .collect();
+    unique(typed, label)
+}
+
+/// Kernel partition device name (`vdb1`, `nvme0n1p1`) for a partition number.
+fn partition_node(dir: &str, number: u32) -> Option<String> {
+    fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let n = fs::read_to_string(entry.path().join("partition")).ok()?;
+        (n.trim().parse() == Ok(number)).then(|| entry.file_name().to_string_lossy().into_owned())
+    })
+}
+
+fn read_sysfs_u64(path: &str) -> u64 {
+    let raw = fs::read_to_string(path).or_panic(format_args!("read {path}"));
+    raw.trim()
+        .parse()
+        .or_panic(format_args!("parse {path}: {:?}", raw.trim()))
 }
 
 #[cfg(test)]
@@ -258,34 +808,225 @@ mod tests {
     use rstest::{fixture, rstest};
     use tempfile::TempDir;
 
-    const PARAMS: &str = "root_hash=abc123,salt=def456,data_blocks=96512,\
-                          data_block_size=4096,hash_block_size=4096";
+    const ROOT_HASH: &str = "4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11";
+    const PARAMS: &str =
+        "root_hash=4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11,\
+                          salt=def456,data_blocks=96512,data_block_size=4096,hash_block_size=4096";
 
     // === parse_verity_params ===
 
     #[test]
     fn test_parse_verity_params_valid() {
         let p = parse_verity_params("coco", PARAMS);
-        assert_eq!(p.root_hash, "abc123");
+        assert_eq!(p.root_hash, ROOT_HASH);
         assert_eq!(p.salt, "def456");
         assert_eq!(p.data_blocks, 96512);
         assert_eq!(p.data_block_size, 4096);
         assert_eq!(p.hash_block_size, 4096);
+        assert_eq!(p.hash_alg, HashAlg::Sha256);
+        assert_eq!(p.fec, None);
+        assert_eq!(p.fstype, FsType::Erofs);
+    }
+
+    #[test]
+    fn test_parse_verity_params_uppercase_hex() {
+        let upper = ROOT_HASH.to_ascii_uppercase();
+        let p = parse_verity_params(
+            "coco",
+            &format!(
+                "root_hash={upper},salt=DEF456,data_blocks=8,data_block_size=4096,hash_block_size=4096"
+            ),
+        );
+        assert_eq!(p.root_hash, ROOT_HASH);
+        assert_eq!(p.salt, "def456");
+    }
+
+    #[rstest]
+    #[case::erofs("erofs", FsType::Erofs, None)]
+    #[case::squashfs("squashfs", FsType::Squashfs, None)]
+    #[case::ext4("ext4", FsType::Ext4, Some("noload"))]
+    fn test_parse_verity_params_fstype(
+        #[case] value: &str,
+        #[case] expected: FsType,
+        #[case] options: Option<&str>,
+    ) {
+        let p = parse_verity_params("coco", &format!("{PARAMS},fstype={value}"));
+        assert_eq!(p.fstype, expected);
+        assert_eq!(p.fstype.name(), value);
+        assert_eq!(p.fstype.options(), options);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown verity_params key")]
+    fn test_parse_verity_params_rejects_unknown_keys() {
+        parse_verity_params("coco", &format!("{PARAMS},extra=ignored"));
+    }
+
+    #[rstest]
+    #[case::sha256("sha256", HashAlg::Sha256, ROOT_HASH.to_owned())]
+    #[case::sha512("sha512", HashAlg::Sha512, ROOT_HASH.repeat(2))]
+    #[case::blake2b("blake2b", HashAlg::Blake2b, ROOT_HASH.to_owned())]
+    fn test_parse_verity_params_hash_alg(
+        #[case] alg: &str,
+        #[case] expected: HashAlg,
+        #[case] root: String,
+    ) {
+        let p = parse_verity_params(
+            "coco",
+            &format!(
+                "root_hash={root},salt=-,data_blocks=8,data_block_size=4096,\
+                 hash_block_size=4096,hash_alg={alg}"
+            ),
+        );
+        assert_eq!(p.hash_alg, expected);
+    }
+
+    #[test]
+    fn test_parse_verity_params_fec() {
+        let p = parse_verity_params("coco", &format!("{PARAMS},fec_roots=2,fec_offset=3117056"));
+        assert_eq!(
+            p.fec,
+            Some(Fec {
+                roots: 2,
+                partition: false,
+                offset: 3117056
+            })
+        );
+        let p = parse_verity_params(
+            "coco",
+            &format!("{PARAMS},fec_roots=24,fec_device=partition"),
+        );
+        assert_eq!(
+            p.fec,
+            Some(Fec {
+                roots: 24,
+                partition: true,
+                offset: 0
+            })
+        );
+    }
+
+    #[rstest]
+    #[case::unknown_alg("hash_alg=md5")]
+    #[case::alg_case("hash_alg=SHA256")]
+    #[case::root_hash_length("hash_alg=sha512")]
+    #[case::fec_roots_low("fec_roots=1,fec_device=partition")]
+    #[case::fec_roots_high("fec_roots=25,fec_device=partition")]
+    #[case::fec_offset_unaligned("fec_roots=2,fec_device=partition,fec_offset=512")]
+    #[case::fec_overlaps_tree("fec_roots=2,fec_offset=4096")]
+    #[case::fec_without_roots("fec_device=partition")]
+    #[case::bad_fec_device("fec_roots=2,fec_device=vdb4")]
+    #[case::unknown_fstype("fstype=vfat")]
+    #[case::fstype_case("fstype=EROFS")]
+    #[case::no_equals("readonly")]
+    #[case::hash_block_size_odd("hash_block_size=1000")]
+    #[case::data_block_size_tiny("data_block_size=256")]
+    #[should_panic]
+    fn test_parse_verity_params_rejects(#[case] extra: &str) {
+        parse_verity_params("coco", &format!("{PARAMS},{extra}"));
+    }
+
+    #[rstest]
+    #[case::single_level(8, 4096, 1)]
+    #[case::one_block(1, 4096, 0)]
+    #[case::veritysetup(96512, 4096, 761)]
+    #[case::small_hash_blocks(96512, 1024, 3016 + 95 + 3 + 1)]
+    fn test_hash_tree_blocks(#[case] data_blocks: u64, #[case] hbs: u64, #[case] expected: u64) {
+        let p = parse_verity_params(
+            "coco",
+            &format!(
+                "root_hash={ROOT_HASH},salt=-,data_blocks={data_blocks},\
+                 data_block_size=4096,hash_block_size={hbs}"
+            ),
+        );
+        assert_eq!(hash_tree_blocks(&p), expected);
+    }
+
+    #[test]
+    fn test_parse_verity_params_sig() {
+        assert_eq!(parse_verity_params("coco", PARAMS).sig, None);
+        let p = parse_verity_params("coco", &format!("{PARAMS},sig=MAMCAQA="));
+        assert_eq!(
+            p.sig,
+            Some(RootHashSig::Inline(vec![0x30, 0x03, 0x02, 0x01, 0x00]))
+        );
+        let p = parse_verity_params("coco", &format!("{PARAMS},sig=partition"));
+        assert_eq!(p.sig, Some(RootHashSig::Partition));
+    }
+
+    #[rstest]
+    #[case::bad_alphabet("sig=not*base64")]
+    #[case::empty("sig=")]
+    #[should_panic]
+    fn test_parse_verity_params_invalid_sig(#[case] sig: &str) {
+        parse_verity_params("coco", &format!("{PARAMS},{sig}"));
+    }
+
+    #[rstest]
+    #[case::padded("aGVsbG8=", Some(b"hello".to_vec()))]
+    #[case::unpadded("aGVsbG8", Some(b"hello".to_vec()))]
+    #[case::full("YWJj", Some(b"abc".to_vec()))]
+    #[case::symbols("+/8=", Some(vec![0xfb, 0xff]))]
+    #[case::empty("", Some(vec![]))]
+    #[case::dangling_sextet("YWJjZ", None)]
+    #[case::too_much_padding("YQ===", None)]
+    #[case::invalid_char("YW-j", None)]
+    fn test_base64_decode(#[case] input: &str, #[case] expected: Option<Vec<u8>>) {
+        assert_eq!(base64_decode(input), expected);
+    }
+
+    #[rstest]
+    #[case::short_form(&[0x30, 0x02, 0xaa, 0xbb, 0, 0], Some(4))]
+    #[case::long_form(&[0x30, 0x81, 0x01, 0xaa, 0], Some(4))]
+    #[case::truncated(&[0x30, 0x05, 0xaa], None)]
+    #[case::not_sequence(&[0x04, 0x01, 0xaa], None)]
+    #[case::zero_padding(&[0, 0, 0, 0], None)]
+    #[case::indefinite(&[0x30, 0x80, 0, 0], None)]
+    #[case::empty(&[], None)]
+    fn test_der_len(#[case] raw: &[u8], #[case] expected: Option<usize>) {
+        assert_eq!(der_len(raw), expected);
+    }
+
+    #[test]
+    fn test_read_sig_partition() {
+        let tmp = TempDir::new().unwrap();
+        let part = tmp.path().join("vdb3");
+        let mut raw = vec![0x30, 0x03, 0x02, 0x01, 0x00];
+        raw.resize(512, 0);
+        fs::write(&part, raw).unwrap();
+        assert_eq!(
+            read_sig_partition("coco", part.to_str()),
+            [0x30, 0x03, 0x02, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_read_sig_partition_missing() {
+        read_sig_partition("coco", None);
     }
 
     #[test]
-    fn test_parse_verity_params_ignores_unknown_keys() {
-        let p = parse_verity_params("coco", &format!("{PARAMS},extra=ignored"));
-        assert_eq!(p.root_hash, "abc123");
+    #[cfg_attr(
+        miri,
+        ignore = "keyring syscalls are foreign functions miri cannot emulate"
+    )]
+    fn test_add_user_key() {
+        let key = add_user_key("nvrc:test", b"payload");
+        assert!(key > 0);
+        invalidate_key(key);
     }
 
     #[rstest]
     #[case::missing_root_hash("salt=def,data_blocks=1,data_block_size=4096,hash_block_size=4096")]
     #[case::zero_data_blocks(
-        "root_hash=a,salt=b,data_blocks=0,data_block_size=4096,hash_block_size=4096"
+        "root_hash=4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11,salt=b,data_blocks=0,data_block_size=4096,hash_block_size=4096"
     )]
     #[case::non_numeric(
-        "root_hash=a,salt=b,data_blocks=lots,data_block_size=4096,hash_block_size=4096"
+        "root_hash=4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11,salt=b,data_blocks=lots,data_block_size=4096,hash_block_size=4096"
+    )]
+    #[case::root_hash_not_hex(
+        "root_hash=4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a1g,salt=b,data_blocks=1,data_block_size=4096,hash_block_size=4096"
     )]
     #[should_panic]
     fn test_parse_verity_params_invalid(#[case] params: &str) {
@@ -311,32 +1052,104 @@ mod tests {
         assert_eq!(names, expected);
     }
 
-    // === verity_args ===
+    // === verity_table ===
+
+    /// The table `dmsetup table extension-coco` is expected to show after
+    /// `veritysetup open --no-superblock --hash sha256 --data-block-size 4096
+    ///  --hash-block-size 4096 --data-blocks 96512 --salt def456
+    ///  /dev/vdb1 extension-coco /dev/vdb2 <root>` with vdb1=253:17, vdb2=253:18.
+    const VERITYSETUP_TABLE: &str = "0 772096 verity 1 253:17 253:18 4096 4096 96512 0 sha256 \
+         4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11 def456";
+
+    /// 4 MiB hash partition: the 761-block tree plus room for parity.
+    const HASH_SIZE: u64 = 4 << 20;
+
+    #[test]
+    fn test_verity_table_matches_veritysetup() {
+        let p = parse_verity_params("coco", PARAMS);
+        let table = verity_table("253:17", "253:18", None, HASH_SIZE, &p, None);
+        assert_eq!(table.line().as_bytes(), VERITYSETUP_TABLE.as_bytes());
+    }
 
+    /// Same, with `--root-hash-signature`: veritysetup loads the signature as
+    /// a user key and adds the key description as an optional argument.
     #[test]
-    fn test_verity_args_order() {
+    fn test_verity_table_with_signature() {
         let p = parse_verity_params("coco", PARAMS);
-        let args = verity_args("extension-coco", "/dev/vdb1", "/dev/vdb2", &p);
+        let table = verity_table(
+            "253:17",
+            "253:18",
+            None,
+            HASH_SIZE,
+            &p,
+            Some("nvrc:extension-coco"),
+        );
+        assert_eq!(
+            table.line(),
+            format!("{VERITYSETUP_TABLE} 2 root_hash_sig_key_desc nvrc:extension-coco")
+        );
+    }
+
+    /// `--fec-device /dev/vdb2 --fec-offset 3117056 --fec-roots 2`: parity
+    /// right behind the 761-block hash tree, covering data plus tree.
+    #[test]
+    fn test_verity_table_with_fec() {
+        let p = parse_verity_params("coco", &format!("{PARAMS},fec_roots=2,fec_offset=3117056"));
+        let table = verity_table("253:17", "253:18", Some("253:18"), HASH_SIZE, &p, None);
+        assert_eq!(
+            table.line(),
+            format!(
+                "{VERITYSETUP_TABLE} 8 use_fec_from_device 253:18 fec_blocks 97273 \
+                 fec_start 761 fec_roots 2"
+            )
+        );
+    }
+
+    /// `--fec-offset 3145728`: veritysetup covers everything on the hash
+    /// partition before the parity, padding included, not just the tree.
+    #[test]
+    fn test_verity_table_with_padded_fec_offset() {
+        let p = parse_verity_params("coco", &format!("{PARAMS},fec_roots=2,fec_offset=3145728"));
+        let table = verity_table("253:17", "253:18", Some("253:18"), HASH_SIZE, &p, None);
+        assert!(table
+            .line()
+            .ends_with(" 8 use_fec_from_device 253:18 fec_blocks 97280 fec_start 768 fec_roots 2"));
+    }
+
+    /// `--fec-device /dev/vdb4`: parity on its own partition covers the whole
+    /// hash partition.
+    #[test]
+    fn test_verity_table_with_fec_partition() {
+        let p = parse_verity_params(
+            "coco",
+            &format!("{PARAMS},fec_roots=2,fec_device=partition"),
+        );
+        let table = verity_table("253:17", "253:18", Some("253:20"), HASH_SIZE, &p, Some("k"));
+        assert!(table.line().ends_with(
+            " 10 use_fec_from_device 253:20 fec_blocks 97536 fec_start 0 fec_roots 2 \
+             root_hash_sig_key_desc k"
+        ));
+    }
+
+    #[test]
+    fn test_verity_table_hash_alg() {
+        let p = parse_verity_params("coco", &format!("{PARAMS},hash_alg=blake2b"));
+        let table = verity_table("253:17", "253:18", None, HASH_SIZE, &p, None);
+        assert!(table.line().contains(" 0 blake2b-256 "));
+    }
+
+    #[test]
+    fn test_verity_table_empty_salt() {
+        let p = parse_verity_params(
+            "coco",
+            &format!(
+                "root_hash={ROOT_HASH},salt=,data_blocks=8,data_block_size=4096,hash_block_size=4096"
+            ),
+        );
+        let table = verity_table("253:17", "253:18", None, HASH_SIZE, &p, None);
         assert_eq!(
-            args,
-            vec![
-                "open",
-                "--no-superblock",
-                "--hash",
-                "sha256",
-                "--data-block-size",
-                "4096",
-                "--hash-block-size",
-                "4096",
-                "--data-blocks",
-                "96512",
-                "--salt",
-                "def456",
-                "/dev/vdb1",
-                "extension-coco",
-                "/dev/vdb2",
-                "abc123",
-            ]
+            table.line(),
+            format!("0 64 verity 1 253:17 253:18 4096 4096 8 0 sha256 {ROOT_HASH} -")
         );
     }
 
@@ -428,7 +1241,7 @@ mod tests {
         if !parse_extensions(&cmdline).is_empty() || !discover_extensions(SYS_BLOCK).is_empty() {
             return;
         }
-        mount_all();
+        assert!(mount_all().is_empty());
     }
 
     // === plan_mounts (fail-closed reconciliation) ===
@@ -476,39 +1289,146 @@ mod tests {
 
     // === find_partitions ===
 
-    fn write_partition(sys_block: &TempDir, dev: &str, part: &str, number: &str) {
-        let dir = sys_block.path().join(dev).join(part);
+    /// 8 x 4 KiB data blocks: a 64-sector data partition and a one-block tree.
+    fn small_params() -> VerityParams {
+        parse_verity_params(
+            "coco",
+            &format!(
+                "root_hash={ROOT_HASH},salt=-,data_blocks=8,data_block_size=4096,hash_block_size=4096"
+            ),
+        )
+    }
+
+    const DATA: (&str, &str, u64, u64) = (gpt::LINUX_DATA, "data", 34, 97);
+    const HASH: (&str, &str, u64, u64) = (gpt::ROOT_VERITY_X86_64, "verity", 98, 105);
+
+    fn write_partition(sys_block: &TempDir, part: &str, number: usize, start: u64, size: u64) {
+        let dir = sys_block.path().join("vdb").join(part);
         fs::create_dir_all(&dir).unwrap();
         fs::write(dir.join("partition"), format!("{number}\n")).unwrap();
+        fs::write(dir.join("start"), format!("{start}\n")).unwrap();
+        fs::write(dir.join("size"), format!("{size}\n")).unwrap();
+    }
+
+    /// Extensions are cold-plugged as virtio-blk devices (vdX): `vdb` with a
+    /// 512-byte-sector GPT in `dev` and matching sysfs entries in `sys_block`.
+    fn fake_disk(parts: &[(&str, &str, u64, u64)]) -> (TempDir, TempDir) {
+        let sys_block = TempDir::new().unwrap();
+        let dev = TempDir::new().unwrap();
+        fs::write(dev.path().join("vdb"), gpt::image(512, parts, 2048)).unwrap();
+        let queue = sys_block.path().join("vdb/queue");
+        fs::create_dir_all(&queue).unwrap();
+        fs::write(queue.join("logical_block_size"), "512\n").unwrap();
+        for (i, &(_, _, first, last)) in parts.iter().enumerate() {
+            let part = format!("vdb{}", i + 1);
+            write_partition(&sys_block, &part, i + 1, first, last - first + 1);
+        }
+        (sys_block, dev)
+    }
+
+    fn find(disk: &(TempDir, TempDir), p: &VerityParams) -> Partitions {
+        let (sys_block, dev) = disk;
+        find_partitions(
+            sys_block.path().to_str().unwrap(),
+            dev.path().to_str().unwrap(),
+            "vdb",
+            p,
+        )
+    }
+
+    fn node(disk: &(TempDir, TempDir), part: &str) -> String {
+        format!("{}/{part}", disk.1.path().display())
     }
 
     #[test]
     fn test_find_partitions() {
-        // Extensions are cold-plugged as virtio-blk devices (vdX).
-        let sys_block = TempDir::new().unwrap();
-        write_partition(&sys_block, "vdb", "vdb1", "1");
-        write_partition(&sys_block, "vdb", "vdb2", "2");
+        let disk = fake_disk(&[DATA, HASH]);
         // a non-partition sysfs attribute alongside partitions must be ignored
-        fs::write(sys_block.path().join("vdb").join("size"), "100\n").unwrap();
+        fs::write(disk.0.path().join("vdb").join("size"), "2048\n").unwrap();
 
-        let (data, hash) = find_partitions(sys_block.path().to_str().unwrap(), "vdb");
-        assert_eq!(data, "/dev/vdb1");
-        assert_eq!(hash, "/dev/vdb2");
+        let parts = find(&disk, &small_params());
+        assert_eq!(parts.data, node(&disk, "vdb1"));
+        assert_eq!(parts.hash, node(&disk, "vdb2"));
+        assert_eq!(parts.sig, None);
+        assert_eq!(parts.fec, None);
     }
 
     #[test]
-    #[should_panic]
-    fn test_find_partitions_missing_hash_panics() {
-        let sys_block = TempDir::new().unwrap();
-        write_partition(&sys_block, "vdb", "vdb1", "1");
-        find_partitions(sys_block.path().to_str().unwrap(), "vdb");
+    fn test_find_partitions_by_type_guid() {
+        // No labels, hash first: roles come from the type GUIDs, not order.
+        let disk = fake_disk(&[(HASH.0, "", 98, 105), (DATA.0, "", 34, 97)]);
+        let parts = find(&disk, &small_params());
+        assert_eq!(parts.data, node(&disk, "vdb2"));
+        assert_eq!(parts.hash, node(&disk, "vdb1"));
     }
 
     #[test]
-    #[should_panic]
-    fn test_find_partitions_missing_data_panics() {
-        let sys_block = TempDir::new().unwrap();
-        write_partition(&sys_block, "vdb", "vdb2", "2");
-        find_partitions(sys_block.path().to_str().unwrap(), "vdb");
+    fn test_find_partitions_with_signature_and_fec() {
+        // `fec` shares the Linux data type GUID: its label keeps it out of
+        // the data role.
+        let disk = fake_disk(&[
+            DATA,
+            HASH,
+            (gpt::LINUX_DATA, "verity-sig", 106, 113),
+            (gpt::LINUX_DATA, "fec", 114, 121),
+        ]);
+        let parts = find(&disk, &small_params());
+        assert_eq!(parts.data, node(&disk, "vdb1"));
+        assert_eq!(parts.sig, Some(node(&disk, "vdb3")));
+        assert_eq!(parts.fec, Some(node(&disk, "vdb4")));
+    }
+
+    #[rstest]
+    #[case::missing_hash(&[DATA])]
+    #[case::missing_data(&[HASH])]
+    #[case::ambiguous_label(&[DATA, HASH, (gpt::LINUX_DATA, "data", 106, 169)])]
+    #[case::ambiguous_type(&[(DATA.0, "", 34, 97), HASH, (DATA.0, "", 106, 169)])]
+    #[case::data_size_mismatch(&[(DATA.0, "data", 34, 98), (HASH.0, "verity", 99, 106)])]
+    #[case::hash_too_small(&[DATA, (HASH.0, "verity", 98, 100)])]
+    #[should_panic(expected = "extension device vdb")]
+    fn test_find_partitions_invalid_layout(#[case] parts: &[(&str, &str, u64, u64)]) {
+        find(&fake_disk(parts), &small_params());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match GPT partition 2")]
+    fn test_find_partitions_kernel_disagrees() {
+        let disk = fake_disk(&[DATA, HASH]);
+        write_partition(&disk.0, "vdb2", 2, 99, 8);
+        find(&disk, &small_params());
+    }
+
+    #[test]
+    #[should_panic(expected = "partition table")]
+    fn test_find_partitions_without_gpt() {
+        let disk = fake_disk(&[DATA, HASH]);
+        fs::write(disk.1.path().join("vdb"), vec![0u8; 65536]).unwrap();
+        find(&disk, &small_params());
+    }
+
+    #[rstest]
+    #[case::label_wins(&[(DATA.0, "", 1, 1), (HASH.0, "data", 2, 2)], "data", 2)]
+    #[case::type_fallback(&[(DATA.0, "", 1, 1), (HASH.0, "", 2, 2)], "data", 1)]
+    #[case::role_label_excluded(&[(DATA.0, "fec", 1, 1)], "data", 0)]
+    fn test_select_partition(
+        #[case] table: &[(&str, &str, u64, u64)],
+        #[case] label: &str,
+        #[case] expected: u32,
+    ) {
+        let table: Vec<gpt::Partition> = table
+            .iter()
+            .enumerate()
+            .map(
+                |(i, &(type_guid, label, first_lba, last_lba))| gpt::Partition {
+                    number: i as u32 + 1,
+                    type_guid: type_guid.to_owned(),
+                    label: label.to_owned(),
+                    first_lba,
+                    last_lba,
+                },
+            )
+            .collect();
+        let found = select_partition(&table, label, DATA_TYPES).unwrap();
+        assert_eq!(found.map_or(0, |p| p.number), expected);
     }
 }
@@ -50,7 +50,7 @@ fn sha256() -> std::io::Result<String> {
     fs::read(SELF_EXE).map(|data| hex_encode(&Sha256::digest(&data)))
 }
 
-fn hex_encode(bytes: &[u8]) -> String {
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
     bytes
         .iter()
         .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Hugepage reservation from `nvrc.hugepages.<size>=<count>`.
+//!
+//! `<count>` is either a total (`nvrc.hugepages.1G=8`), which the kernel
+//! spreads over NUMA nodes, or per-node counts (`nvrc.hugepages.2M=0:512,1:512`).
+//! Pages are reserved before the GPU drivers or any daemon fragment memory, and the
+//! kernel's answer is read back: it allocates what it can and reports success.
+//! A shortfall warns, or panics with `nvrc.hugepages.strict=on`.
+
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SYS: &str = "/sys";
+
+/// One reservation: `count` pages of `size_kb`, on `node` or system-wide.
+#[derive(Debug, PartialEq)]
+pub struct Hugepages {
+    pub size_kb: u64,
+    pub node: Option<u32>,
+    pub count: u64,
+}
+
+/// `2M`, `1G`, `2048kB`, `64K` as kB.
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.strip_suffix('B').unwrap_or(size);
+    let (digits, scale) = match size.char_indices().last()? {
+        (i, 'k' | 'K') => (&size[..i], 1),
+        (i, 'M') => (&size[..i], 1 << 10),
+        (i, 'G') => (&size[..i], 1 << 20),
+        _ => return None,
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .filter(|&kb| kb > 0)
+}
+
+/// Parse the `<size>` suffix and `<count>` value of `nvrc.hugepages.<size>`.
+pub fn parse(size: &str, value: &str) -> Result<Vec<Hugepages>, String> {
+    let param = format!("nvrc.hugepages.{size}");
+    let size_kb = parse_size(size).ok_or_else(|| format!("{param}: invalid size"))?;
+    let count = |c: &str| {
+        c.parse::<u64>()
+            .map_err(|e| format!("{param}: invalid count {c:?}: {e}"))
+    };
+    if !value.contains(':') {
+        return Ok(vec![Hugepages {
+            size_kb,
+            node: None,
+            count: count(value)?,
+        }]);
+    }
+    value
+        .split(',')
+        .map(|pair| {
+            let (node, n) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("{param}: expected <node>:<count>, got {pair:?}"))?;
+            let node = node
+                .parse()
+                .map_err(|e| format!("{param}: invalid node {node:?}: {e}"))?;
+            Ok(Hugepages {
+                size_kb,
+                node: Some(node),
+                count: count(n)?,
+            })
+        })
+        .collect()
+}
+
+fn nr_hugepages(sys: &Path, request: &Hugepages) -> PathBuf {
+    let pool = format!("hugepages/hugepages-{}kB/nr_hugepages", request.size_kb);
+    match request.node {
+        None => sys.join("kernel/mm").join(pool),
+        Some(node) => sys
+            .join(format!("devices/system/node/node{node}"))
+            .join(pool),
+    }
+}
+
+/// Reserve every request, last one wins for a repeated pool.
+pub fn reserve(requests: &[Hugepages], strict: bool) {
+    reserve_at(Path::new(SYS), requests, strict)
+}
+
+fn reserve_at(sys: &Path, requests: &[Hugepages], strict: bool) {
+    for request in requests {
+        let path = nr_hugepages(sys, request);
+        // An unsupported size or a missing node is a cmdline error, not a shortfall.
+        if !path.exists() {
+            panic!(
+                "hugepages: no {} (unsupported size or node)",
+                path.display()
+            );
+        }
+        fs::write(&path, format!("{}\n", request.count))
+            .unwrap_or_else(|e| panic!("hugepages: write {}: {e}", path.display()));
+        let allocated = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or_else(|| panic!("hugepages: read back {}", path.display()));
+        check(request, allocated, strict);
+    }
+}
+
+fn check(request: &Hugepages, allocated: u64, strict: bool) {
+    let pool = match request.node {
+        Some(node) => format!("{}kB on node{node}", request.size_kb),
+        None => format!("{}kB", request.size_kb),
+    };
+    if allocated >= request.count {
+        info!("hugepages: {allocated} x {pool}");
+        return;
+    }
+    let msg = format!(
+        "hugepages: {allocated} of {} x {pool} allocated",
+        request.count
+    );
+    if strict {
+        panic!("{msg}");
+    }
+    warn!("{msg}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use tempfile::TempDir;
+
+    fn pages(size_kb: u64, node: Option<u32>, count: u64) -> Hugepages {
+        Hugepages {
+            size_kb,
+            node,
+            count,
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("2M"), Some(2048));
+        assert_eq!(parse_size("1G"), Some(1 << 20));
+        assert_eq!(parse_size("2048kB"), Some(2048));
+        assert_eq!(parse_size("64K"), Some(64));
+        assert_eq!(parse_size("2"), None);
+        assert_eq!(parse_size("0M"), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("99999999999999999G"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1G", "8").unwrap(), [pages(1 << 20, None, 8)]);
+        assert_eq!(
+            parse("2M", "0:512,1:256").unwrap(),
+            [pages(2048, Some(0), 512), pages(2048, Some(1), 256)]
+        );
+        assert!(parse("3X", "1").unwrap_err().contains("invalid size"));
+        assert!(parse("2M", "-1").unwrap_err().contains("invalid count"));
+        assert!(parse("2M", "0:1,2").unwrap_err().contains("<node>:<count>"));
+        assert!(parse("2M", "a:1").unwrap_err().contains("invalid node"));
+    }
+
+    #[test]
+    fn test_nr_hugepages() {
+        let sys = Path::new("/sys");
+        assert_eq!(
+            nr_hugepages(sys, &pages(2048, None, 1)),
+            Path::new("/sys/kernel/mm/hugepages/hugepages-2048kB/nr_hugepages")
+        );
+        assert_eq!(
+            nr_hugepages(sys, &pages(1 << 20, Some(1), 1)),
+            Path::new("/sys/devices/system/node/node1/hugepages/hugepages-1048576kB/nr_hugepages")
+        );
+    }
+
+    #[test]
+    fn test_reserve_at() {
+        let sys = TempDir::new().unwrap();
+        let requests = [pages(2048, None, 16), pages(2048, Some(0), 4)];
+        for request in &requests {
+            let path = nr_hugepages(sys.path(), request);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "0\n").unwrap();
+        }
+        reserve_at(sys.path(), &requests, true);
+        for request in &requests {
+            let written = fs::read_to_string(nr_hugepages(sys.path(), request)).unwrap();
+            assert_eq!(written, format!("{}\n", request.count));
+        }
+    }
+
+    #[test]
+    fn test_reserve_at_unsupported_size() {
+        let sys = TempDir::new().unwrap();
+        let result = panic::catch_unwind(|| reserve_at(sys.path(), &[pages(64, None, 1)], false));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_shortfall() {
+        check(&pages(2048, None, 8), 8, true);
+        // Best effort only warns
+        check(&pages(2048, None, 8), 3, false);
+        let result = panic::catch_unwind(|| check(&pages(2048, Some(1), 8), 3, true));
+        assert!(result.is_err());
+    }
+}
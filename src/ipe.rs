@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Load and activate the IPE (Integrity Policy Enforcement) policy.
+//!
+//! The policy is PKCS#7-signed and shipped either in the base rootfs or in
+//! exactly one extension. The kernel verifies the signature, so NVRC cannot
+//! append rules for the extensions it just mounted; instead it renders the
+//! rules each extension needs and refuses a policy that does not carry them.
+//! The image builder knows the extension root hashes and emits the same lines.
+//!
+//! An extension's root hash covers the policy it hosts, so that policy cannot
+//! name it. The hosting extension must instead be opened with a root hash
+//! signature and the policy must allow signed dm-verity devices.
+//!
+//! Runs right before kata-agent starts: once active, anything not on a
+//! trusted dm-verity device can no longer be executed.
+
+use log::info;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::guest_extension_image::{Extension, MOUNT_BASE};
+use crate::lockdown::policy_violation;
+
+const IPE: &str = "/sys/kernel/security/ipe";
+const BASE_POLICY: &str = "/etc/ipe/policy.p7s";
+/// Policy location relative to an extension mount.
+const EXTENSION_POLICY: &str = "etc/ipe/policy.p7s";
+
+/// Load, check and activate the policy; failures are policy violations
+/// (fatal on confidential builds).
+pub fn activate(extensions: &[Extension]) {
+    let result = find_policy(Path::new(BASE_POLICY), Path::new(MOUNT_BASE), extensions).and_then(
+        |(policy, host)| activate_at(Path::new(IPE), &policy, &required_rules(extensions, host)),
+    );
+    if let Err(e) = result {
+        policy_violation(format_args!("ipe: {e}"));
+    }
+}
+
+/// The single signed policy on the system, base rootfs or one extension,
+/// and the extension hosting it.
+fn find_policy<'a>(
+    base: &Path,
+    mount_base: &Path,
+    extensions: &'a [Extension],
+) -> Result<(PathBuf, Option<&'a Extension>), String> {
+    let mut found: Vec<(PathBuf, Option<&Extension>)> = extensions
+        .iter()
+        .map(|e| (mount_base.join(&e.name).join(EXTENSION_POLICY), Some(e)))
+        .chain([(base.to_path_buf(), None)])
+        .filter(|(p, _)| p.exists())
+        .collect();
+    match found.len() {
+        0 => Err(format!("no signed policy at {}", base.display())),
+        1 => {
+            let (policy, host) = found.remove(0);
+            match host {
+                Some(e) if !e.signed => Err(format!(
+                    "{} is in extension {}, whose root hash is not signed",
+                    policy.display(),
+                    e.name
+                )),
+                _ => Ok((policy, host)),
+            }
+        }
+        _ => Err(format!(
+            "ambiguous policies: {:?}",
+            found.iter().map(|(p, _)| p).collect::<Vec<_>>()
+        )),
+    }
+}
+
+/// One execute-allow rule per extension, pinned to its dm-verity root hash,
+/// except for the extension hosting the policy: it is allowed as a signed
+/// dm-verity device instead.
+fn required_rules(extensions: &[Extension], host: Option<&Extension>) -> Vec<String> {
+    let mut rules: Vec<String> = extensions
+        .iter()
+        .filter(|e| host.is_none_or(|h| h.name != e.name))
+        .map(|e| {
+            format!(
+                "op=EXECUTE dmverity_roothash={}:{} action=ALLOW",
+                e.hash_alg, e.root_hash
+            )
+        })
+        .collect();
+    if host.is_some() {
+        rules.push("op=EXECUTE dmverity_signature=TRUE action=ALLOW".to_owned());
+    }
+    rules
+}
+
+fn activate_at(ipe: &Path, policy: &Path, rules: &[String]) -> Result<(), String> {
+    if !ipe.exists() {
+        return Err(format!("{} missing", ipe.display()));
+    }
+    let signed = fs::read(policy).map_err(|e| format!("read {}: {e}", policy.display()))?;
+
+    let policies = ipe.join("policies");
+    let before = list_policies(&policies)?;
+    fs::write(ipe.join("new_policy"), signed)
+        .map_err(|e| format!("load {}: {e}", policy.display()))?;
+    let name = new_policy(&before, &list_policies(&policies)?)?;
+    let version = enable(&policies.join(&name), &name, rules)?;
+
+    info!(
+        "ipe: active policy {name} version {version} from {}",
+        policy.display()
+    );
+    Ok(())
+}
+
+/// Check the loaded policy carries `rules`, activate it and confirm the kernel
+/// reports it active at the version it declares. Returns that version.
+fn enable(dir: &Path, name: &str, rules: &[String]) -> Result<String, String> {
+    let text = read(&dir.join("policy"))?;
+    check_rules(&text, rules)?;
+
+    fs::write(dir.join("active"), "1").map_err(|e| format!("activate {name}: {e}"))?;
+    if read(&dir.join("active"))? != "1" {
+        return Err(format!("policy {name} not active after activation"));
+    }
+    let version = read(&dir.join("version"))?;
+    let declared = declared_version(&text);
+    if declared != Some(version.as_str()) {
+        return Err(format!(
+            "policy {name}: active version {version}, policy declares {declared:?}"
+        ));
+    }
+    Ok(version)
+}
+
+fn list_policies(dir: &Path) -> Result<BTreeSet<String>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("read_dir {}: {e}", dir.display()))?;
+    Ok(entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect())
+}
+
+/// The kernel creates `policies/<policy_name>` on a successful load; the name
+/// lives inside the signed blob, so diff the directory instead of parsing it.
+fn new_policy(before: &BTreeSet<String>, after: &BTreeSet<String>) -> Result<String, String> {
+    let mut added = after.difference(before);
+    match (added.next(), added.next()) {
+        (Some(name), None) => Ok(name.clone()),
+        (None, _) => Err("no policy appeared after load".to_owned()),
+        (Some(_), Some(_)) => Err("more than one policy appeared after load".to_owned()),
+    }
+}
+
+/// Every rendered rule must appear as a line of the policy, modulo spacing.
+fn check_rules(policy: &str, rules: &[String]) -> Result<(), String> {
+    let lines: BTreeSet<String> = policy.lines().map(normalize).collect();
+    let missing: Vec<&str> = rules
+        .iter()
+        .filter(|r| !lines.contains(&normalize(r)))
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("policy lacks extension rules: {missing:?}"))
+    }
+}
+
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `policy_name=nvrc policy_version=0.0.1` -> `0.0.1`, from the header: the
+/// first line that is neither blank nor a `#` comment.
+fn declared_version(policy: &str) -> Option<&str> {
+    policy
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))?
+        .split_whitespace()
+        .find_map(|kv| kv.strip_prefix("policy_version="))
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_owned())
+        .map_err(|e| format!("read {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use tempfile::TempDir;
+
+    const HASH: &str = "4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11";
+
+    fn ext(name: &str) -> Extension {
+        Extension {
+            name: name.to_owned(),
+            root_hash: HASH.to_owned(),
+            hash_alg: "sha256",
+            signed: false,
+            manifest: None,
+        }
+    }
+
+    fn policy_text(rules: &[String]) -> String {
+        let mut text = "policy_name=nvrc policy_version=0.0.2\nDEFAULT action=DENY\n\
+                        op=EXECUTE boot_verified=TRUE action=ALLOW\n"
+            .to_owned();
+        for r in rules {
+            text.push_str(r);
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn test_required_rules() {
+        assert_eq!(
+            required_rules(&[ext("gpu")], None),
+            [format!(
+                "op=EXECUTE dmverity_roothash=sha256:{HASH} action=ALLOW"
+            )]
+        );
+        let blake = Extension {
+            hash_alg: "blake2b-256",
+            ..ext("gpu")
+        };
+        assert_eq!(
+            required_rules(&[blake], None),
+            [format!(
+                "op=EXECUTE dmverity_roothash=blake2b-256:{HASH} action=ALLOW"
+            )]
+        );
+        assert!(required_rules(&[], None).is_empty());
+    }
+
+    #[test]
+    fn test_find_policy_base() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("policy.p7s");
+        fs::write(&base, "p7s").unwrap();
+        let extensions = [ext("gpu")];
+        let (found, host) = find_policy(&base, tmp.path(), &extensions).unwrap();
+        assert_eq!(found, base);
+        assert!(host.is_none());
+    }
+
+    #[test]
+    fn test_find_policy_extension() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("gpu/etc/ipe");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("policy.p7s"), "p7s").unwrap();
+        let none = tmp.path().join("none");
+        let signed = [Extension {
+            signed: true,
+            ..ext("gpu")
+        }];
+        let (found, host) = find_policy(&none, tmp.path(), &signed).unwrap();
+        assert_eq!(found, dir.join("policy.p7s"));
+        assert_eq!(host.unwrap().name, "gpu");
+
+        // Nothing but a signature can vouch for the extension hosting the policy
+        let err = find_policy(&none, tmp.path(), &[ext("gpu")]).unwrap_err();
+        assert!(err.contains("not signed"), "{err}");
+    }
+
+    #[test]
+    fn test_required_rules_extension_hosted() {
+        let tools = Extension {
+            root_hash: "ab".repeat(32),
+            ..ext("tools")
+        };
+        let gpu = Extension {
+            signed: true,
+            ..ext("gpu")
+        };
+        let extensions = [gpu, tools];
+        let rules = required_rules(&extensions, Some(&extensions[0]));
+        assert_eq!(
+            rules,
+            [
+                format!(
+                    "op=EXECUTE dmverity_roothash=sha256:{} action=ALLOW",
+                    "ab".repeat(32)
+                ),
+                "op=EXECUTE dmverity_signature=TRUE action=ALLOW".to_owned(),
+            ]
+        );
+        // A policy built without the host's own root hash passes
+        assert!(check_rules(&policy_text(&rules), &rules).is_ok());
+        assert!(!rules.iter().any(|r| r.contains(HASH)));
+    }
+
+    #[test]
+    fn test_find_policy_none_or_ambiguous() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("policy.p7s");
+        assert!(find_policy(&base, tmp.path(), &[]).is_err());
+
+        fs::write(&base, "p7s").unwrap();
+        let dir = tmp.path().join("gpu/etc/ipe");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("policy.p7s"), "p7s").unwrap();
+        let err = find_policy(&base, tmp.path(), &[ext("gpu")]).unwrap_err();
+        assert!(err.contains("ambiguous"));
+    }
+
+    #[test]
+    fn test_new_policy() {
+        let before: BTreeSet<String> = ["boot".to_owned()].into();
+        let after: BTreeSet<String> = ["boot".to_owned(), "nvrc".to_owned()].into();
+        assert_eq!(new_policy(&before, &after).unwrap(), "nvrc");
+        assert!(new_policy(&before, &before).is_err());
+        assert!(new_policy(&BTreeSet::new(), &after).is_err());
+    }
+
+    #[test]
+    fn test_check_rules() {
+        let rules = required_rules(&[ext("gpu")], None);
+        assert!(check_rules(&policy_text(&rules), &rules).is_ok());
+        // spacing differences are tolerated
+        let spaced = policy_text(&[rules[0].replace(' ', "  ")]);
+        assert!(check_rules(&spaced, &rules).is_ok());
+        let err = check_rules(&policy_text(&[]), &rules).unwrap_err();
+        assert!(err.contains(HASH));
+    }
+
+    #[test]
+    fn test_declared_version() {
+        assert_eq!(declared_version(&policy_text(&[])), Some("0.0.2"));
+        assert_eq!(declared_version("DEFAULT action=DENY"), None);
+        assert_eq!(declared_version(""), None);
+        let commented =
+            "# NVRC policy\n\n  policy_name=nvrc policy_version=0.0.3\nDEFAULT action=DENY";
+        assert_eq!(declared_version(commented), Some("0.0.3"));
+        assert_eq!(
+            declared_version("# policy_version=9\nDEFAULT action=DENY"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_activate_at_missing_interface() {
+        let tmp = TempDir::new().unwrap();
+        let err = activate_at(&tmp.path().join("ipe"), Path::new("/nonexistent"), &[]).unwrap_err();
+        assert!(err.contains("missing"));
+    }
+
+    #[test]
+    fn test_activate_at_policy_not_loaded() {
+        // A plain directory accepts the write but never materializes the
+        // policy, as when the kernel rejects the signature.
+        let tmp = TempDir::new().unwrap();
+        let ipe = tmp.path().join("ipe");
+        fs::create_dir_all(ipe.join("policies/boot")).unwrap();
+        let policy = tmp.path().join("policy.p7s");
+        fs::write(&policy, "p7s").unwrap();
+        let err = activate_at(&ipe, &policy, &[]).unwrap_err();
+        assert_eq!(err, "no policy appeared after load");
+    }
+
+    fn fake_policy_dir(text: &str, version: &str) -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("policy"), text).unwrap();
+        fs::write(tmp.path().join("version"), format!("{version}\n")).unwrap();
+        fs::write(tmp.path().join("active"), "0\n").unwrap();
+        tmp
+    }
+
+    #[test]
+    fn test_enable() {
+        let rules = required_rules(&[ext("gpu")], None);
+        let dir = fake_policy_dir(&policy_text(&rules), "0.0.2");
+        assert_eq!(enable(dir.path(), "nvrc", &rules).unwrap(), "0.0.2");
+        assert_eq!(read(&dir.path().join("active")).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_enable_missing_rules_not_activated() {
+        let rules = required_rules(&[ext("gpu")], None);
+        let dir = fake_policy_dir(&policy_text(&[]), "0.0.2");
+        assert!(enable(dir.path(), "nvrc", &rules).is_err());
+        assert_eq!(read(&dir.path().join("active")).unwrap(), "0");
+    }
+
+    #[test]
+    fn test_enable_version_mismatch() {
+        let dir = fake_policy_dir(&policy_text(&[]), "0.0.1");
+        let err = enable(dir.path(), "nvrc", &[]).unwrap_err();
+        assert!(err.contains("active version 0.0.1"));
+    }
+
+    #[test]
+    fn test_activate_without_ipe() {
+        // Dev hosts have no IPE: warns, or panics on confidential builds.
+        let result = panic::catch_unwind(|| activate(&[]));
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+}
@@ -1,7 +1,11 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright (c) NVIDIA CORPORATION
 
-use crate::gpu_extension;
+use crate::consumer;
+use crate::execute;
+use crate::landlock::{self, Rule};
+use crate::macros::ResultExt;
+use crate::syslog;
 use log::debug;
 use nix::unistd::{fork, ForkResult};
 use rlimit::{setrlimit, Resource};
@@ -13,11 +17,6 @@ use std::time::Duration;
 
 const KATA_AGENT_PATH: &str = "/usr/bin/kata-agent";
 
-/// Env var kata-agent reads to pick its attestation-agent. Set to
-/// [`gpu_extension::ATTESTER_VARIANT_NVIDIA`] when the GPU extension is present, unset
-/// otherwise. Contract shared with kata-agent (`src/agent/src/main.rs`).
-const ATTESTER_VARIANT_ENV: &str = "KATA_ATTESTER_VARIANT";
-
 /// Syslog polling runs indefinitely in production—VM lifetime measured in hours/days,
 /// not the 136 years this represents. Using u32::MAX avoids overflow concerns.
 pub const SYSLOG_POLL_FOREVER: u32 = u32::MAX;
@@ -40,28 +39,27 @@ fn agent_setup() {
     debug!("kata-agent RLIMIT_NOFILE: {:?}", lim);
 }
 
-/// Build the kata-agent command, injecting the attester-variant env var when
-/// set. Split from [`exec_agent`] so the env wiring is unit-testable. GPU libs
-/// resolve via the loader cache ([`gpu_extension::setup`]), not `LD_LIBRARY_PATH`.
-fn agent_command(cmd: &str, attester_variant: Option<&str>) -> Command {
-    let mut command = Command::new(cmd);
-    if let Some(variant) = attester_variant {
-        command.env(ATTESTER_VARIANT_ENV, variant);
-    }
+/// Build the kata-agent command: the fixed [`execute::ENV`] plus the env
+/// extensions contribute (e.g. the attester variant). Split from
+/// [`exec_agent`] so the env wiring is unit-testable. Extension libs resolve
+/// via the loader cache ([`consumer::setup`]), not `LD_LIBRARY_PATH`.
+fn agent_command(cmd: &str, env: &[(String, String)]) -> Command {
+    let mut command = execute::command(cmd);
+    command.envs(env.iter().map(|(k, v)| (k, v)));
     command
 }
 
 /// exec() replaces this process with kata-agent, so it only returns on failure.
 /// We want kata-agent to become PID 1's child for proper process hierarchy.
-fn exec_agent(cmd: &str, attester_variant: Option<&str>) {
-    let err = agent_command(cmd, attester_variant).exec();
+fn exec_agent(cmd: &str, env: &[(String, String)]) {
+    let err = agent_command(cmd, env).exec();
     panic!("exec {cmd} failed: {err}");
 }
 
 /// Path parameter enables testing with /bin/true instead of real kata-agent
-fn kata_agent(path: &str, attester_variant: Option<&str>) {
+fn kata_agent(path: &str, env: &[(String, String)]) {
     agent_setup();
-    exec_agent(path, attester_variant);
+    exec_agent(path, env);
 }
 
 /// Drains `/dev/log` (bound in `main()` before fork) into `/run/syslog.log`.
@@ -77,6 +75,30 @@ fn syslog_loop(timeout_secs: u32) {
     }
 }
 
+/// The syslog child lives as long as the VM but only ever touches the
+/// `/dev/log` socket and the log file; everything else is denied.
+fn syslog_rules() -> [Rule<'static>; 2] {
+    [
+        Rule {
+            path: "/dev/log",
+            access: landlock::READ | landlock::ACCESS_FS_WRITE_FILE,
+        },
+        Rule {
+            path: syslog::SYSLOG_FILE_PATH,
+            access: landlock::READ | landlock::ACCESS_FS_WRITE_FILE,
+        },
+    ]
+}
+
+/// Confine the syslog child before it starts draining. The log file is opened
+/// first so it exists for its rule and the fd survives the restriction.
+fn syslog_sandbox() {
+    syslog::logfile().or_panic("open syslog file");
+    landlock::ruleset("syslog", &syslog_rules())
+        .restrict_self()
+        .or_panic("landlock syslog");
+}
+
 /// Parent execs kata-agent (becoming it), child stays as syslog poller.
 /// This way kata-agent inherits our PID and becomes the main guest process.
 /// Timeout parameter allows tests to verify the fork/syslog logic exits cleanly
@@ -85,12 +107,14 @@ pub fn fork_agent(timeout_secs: u32) {
     // 1. We are PID 1 with no other threads (single-threaded process)
     // 2. Parent immediately execs kata-agent (no shared state issues)
     // 3. Child only calls async-signal-safe functions (syslog::try_poll, sleep)
+    //    after building its Landlock ruleset, which is safe single-threaded
     // 4. No locks or mutexes exist that could deadlock in child
     match unsafe { fork() }.expect("fork agent") {
         ForkResult::Parent { .. } => {
-            kata_agent(KATA_AGENT_PATH, gpu_extension::attester_variant());
+            kata_agent(KATA_AGENT_PATH, &consumer::agent_env());
         }
         ForkResult::Child => {
+            syslog_sandbox();
             syslog_loop(timeout_secs);
         }
     }
@@ -99,6 +123,7 @@ pub fn fork_agent(timeout_secs: u32) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consumer::ATTESTER_VARIANT_ENV;
     use crate::test_utils::require_root;
     use nix::sys::wait::{waitpid, WaitStatus};
     use serial_test::serial;
@@ -155,7 +180,7 @@ mod tests {
     fn test_exec_agent_not_found() {
         // exec_agent with nonexistent command panics (doesn't exec)
         let result = panic::catch_unwind(|| {
-            exec_agent("/nonexistent/command", None);
+            exec_agent("/nonexistent/command", &[]);
         });
         assert!(result.is_err());
     }
@@ -163,21 +188,38 @@ mod tests {
     #[test]
     fn test_agent_command_injects_attester_variant() {
         use std::ffi::OsStr;
-        let cmd = agent_command("/bin/true", Some(gpu_extension::ATTESTER_VARIANT_NVIDIA));
-        let found = cmd.get_envs().any(|(k, v)| {
-            k == OsStr::new(ATTESTER_VARIANT_ENV)
-                && v == Some(OsStr::new(gpu_extension::ATTESTER_VARIANT_NVIDIA))
-        });
+        let env = [(ATTESTER_VARIANT_ENV.to_owned(), "nvidia".to_owned())];
+        let cmd = agent_command("/bin/true", &env);
+        let found = cmd
+            .get_envs()
+            .any(|(k, v)| k == OsStr::new(ATTESTER_VARIANT_ENV) && v == Some(OsStr::new("nvidia")));
         assert!(
             found,
             "expected {ATTESTER_VARIANT_ENV} to be set on the agent command"
         );
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore = "miri cannot emulate process spawn")]
+    fn test_agent_command_environment_is_fixed() {
+        let env = [(ATTESTER_VARIANT_ENV.to_owned(), "nvidia".to_owned())];
+        let output = agent_command("/usr/bin/env", &env).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut vars: Vec<&str> = stdout.lines().collect();
+        vars.sort();
+        let mut expected: Vec<String> = execute::ENV
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .chain([format!("{ATTESTER_VARIANT_ENV}=nvidia")])
+            .collect();
+        expected.sort();
+        assert_eq!(vars, expected);
+    }
+
     #[test]
     fn test_agent_command_no_variant_leaves_env_unset() {
         use std::ffi::OsStr;
-        let cmd = agent_command("/bin/true", None);
+        let cmd = agent_command("/bin/true", &[]);
         let found = cmd
             .get_envs()
             .any(|(k, _)| k == OsStr::new(ATTESTER_VARIANT_ENV));
@@ -207,12 +249,48 @@ mod tests {
             ForkResult::Child => {
                 set_test_panic_hook();
                 // Setup succeeds, exec panics
-                kata_agent("/nonexistent/agent", None);
+                kata_agent("/nonexistent/agent", &[]);
                 std::process::exit(0); // Won't reach here
             }
         }
     }
 
+    #[test]
+    fn test_syslog_rules_only_log_paths() {
+        let paths: Vec<_> = syslog_rules().iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/dev/log", syslog::SYSLOG_FILE_PATH]);
+        assert!(syslog_rules()
+            .iter()
+            .all(|r| r.access & landlock::EXECUTE == 0));
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "fork/landlock syscalls are foreign functions miri cannot emulate"
+    )]
+    fn test_syslog_sandbox_denies_rootfs() {
+        require_root();
+        if landlock::abi() < 1 {
+            return;
+        }
+
+        // SAFETY: the child only sandboxes itself, probes and exits.
+        match unsafe { fork() }.expect("fork") {
+            ForkResult::Parent { child } => {
+                let status = waitpid(child, None).expect("waitpid");
+                assert_eq!(status, WaitStatus::Exited(child, 0));
+            }
+            ForkResult::Child => {
+                set_test_panic_hook();
+                syslog_sandbox();
+                let code = i32::from(fs::read("/etc/passwd").is_ok());
+                // SAFETY: immediate exit without running the harness' atexit.
+                unsafe { libc::_exit(code) };
+            }
+        }
+    }
+
     #[test]
     #[cfg_attr(
         miri,
@@ -1,7 +1,13 @@
 use log::{debug, warn};
 use std::fs;
 
+use crate::gpudirect;
+use crate::hugepages;
+use crate::modprobe;
+use crate::mount;
+use crate::net;
 use crate::nvrc::NVRC;
+use crate::sysctl;
 
 /// Kernel parameters use various boolean representations (on/off, true/false, 1/0, yes/no).
 /// Normalize them to a single bool to simplify downstream logic.
@@ -40,15 +46,25 @@ impl NVRC {
                 "nvrc.log" => nvrc_log(v, self)?,
                 "nvrc.uvm.persistence.mode" => uvm_persistenced_mode(v, self),
                 "nvrc.dcgm" => nvrc_dcgm(v, self),
+                "nvrc.graphics" => nvrc_graphics(v, self),
+                "nvrc.landlock.daemons" => nvrc_landlock_daemons(v, self),
 
                 "nvrc.smi.srs" => nvidia_smi_srs(v, self),
                 "nvrc.smi.lgc" => nvidia_smi_lgc(v, self)?,
                 "nvrc.smi.lmc" => nvidia_smi_lmc(v, self)?,
                 "nvrc.smi.pl" => nvidia_smi_pl(v, self)?,
+                _ if k.starts_with("nvrc.sysctl.") => nvrc_sysctl(k, v, self)?,
+                _ if k.starts_with("nvrc.mount.") => nvrc_mount(k, v, self)?,
+                "nvrc.hugepages.strict" => nvrc_hugepages_strict(v, self),
+                _ if k.starts_with("nvrc.hugepages.") => nvrc_hugepages(k, v, self)?,
+                _ if k.starts_with("nvrc.module.") => nvrc_module(k, v, self)?,
+                "nvrc.net.allow" => nvrc_net_allow(v, self),
+                _ if k.starts_with("nvrc.net.") => nvrc_net(k, v, self)?,
+                "nvrc.gpudirect" => nvrc_gpudirect(v, self)?,
                 _ => {}
             }
         }
-        Ok(())
+        hugetlbfs_mount(self)
     }
 }
 
@@ -60,6 +76,14 @@ fn nvrc_dcgm(value: &str, ctx: &mut NVRC) {
     debug!("nvrc.dcgm: {dcgm}");
 }
 
+/// Graphics sub-mode of GPU mode: nvidia-modeset, nvidia-drm and a CDI spec
+/// with the DRM nodes. Off by default—compute needs none of it.
+fn nvrc_graphics(value: &str, ctx: &mut NVRC) {
+    let graphics = parse_boolean(value);
+    ctx.graphics = Some(graphics);
+    debug!("nvrc.graphics: {graphics}");
+}
+
 /// Control log verbosity at runtime. Defaults to off to minimize noise.
 /// Enabling devkmsg allows kernel log output even in minimal init environments.
 fn nvrc_log(value: &str, _ctx: &mut NVRC) -> Result<(), String> {
@@ -118,6 +142,115 @@ fn nvidia_smi_pl(value: &str, ctx: &mut NVRC) -> Result<(), String> {
     Ok(())
 }
 
+/// Landlock-confine daemons to the rootfs read-only plus their own state dirs.
+/// Off by default—a daemon touching an undeclared path fails at runtime.
+fn nvrc_landlock_daemons(value: &str, ctx: &mut NVRC) {
+    let enabled = parse_boolean(value);
+    ctx.landlock_daemons = Some(enabled);
+    debug!("nvrc.landlock.daemons: {enabled}");
+}
+
+/// Override one entry of the sysctl hardening profile. Unknown keys are rejected
+/// rather than ignored so a typo cannot silently leave a knob at its default.
+fn nvrc_sysctl(param: &str, value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    let key = &param["nvrc.sysctl.".len()..];
+    sysctl::check_override(key, value)?;
+    ctx.sysctl_overrides
+        .push((key.to_owned(), value.to_owned()));
+    debug!("{param}: {value}");
+    Ok(())
+}
+
+/// Enable a mode-specific or on-request mount, `<value>` replacing its mount
+/// data (e.g. `nvrc.mount.hugetlbfs=pagesize=1G`). Unknown names are rejected.
+fn nvrc_mount(param: &str, value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    let name = &param["nvrc.mount.".len()..];
+    mount::check_enable(name)?;
+    ctx.mount_enables.push((name.to_owned(), value.to_owned()));
+    debug!("{param}: {value}");
+    Ok(())
+}
+
+/// Reserve hugepages of one size, in total or per NUMA node. See
+/// [`hugetlbfs_mount`] for the mount that comes with it.
+fn nvrc_hugepages(param: &str, value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    let size = &param["nvrc.hugepages.".len()..];
+    let requests = hugepages::parse(size, value)?;
+    ctx.hugepages.extend(requests);
+    debug!("{param}: {value}");
+    Ok(())
+}
+
+/// Mount hugetlbfs for the reserved size unless `nvrc.mount.hugetlbfs` says
+/// otherwise. There is one mount, so with several sizes reserved the cmdline
+/// has to pick one.
+fn hugetlbfs_mount(ctx: &mut NVRC) -> Result<(), String> {
+    if ctx.mount_enables.iter().any(|(k, _)| k == "hugetlbfs") {
+        return Ok(());
+    }
+    let mut sizes: Vec<u64> = ctx.hugepages.iter().map(|h| h.size_kb).collect();
+    sizes.dedup();
+    match sizes[..] {
+        [] => Ok(()),
+        [size_kb] => {
+            ctx.mount_enables
+                .push(("hugetlbfs".to_owned(), format!("pagesize={size_kb}K")));
+            Ok(())
+        }
+        _ => Err(
+            "nvrc.hugepages: several sizes reserved, choose the hugetlbfs one with \
+             nvrc.mount.hugetlbfs=pagesize=<size>"
+                .to_owned(),
+        ),
+    }
+}
+
+/// Fail the boot, rather than warn, when the kernel allocates fewer hugepages
+/// than requested.
+fn nvrc_hugepages_strict(value: &str, ctx: &mut NVRC) {
+    let strict = parse_boolean(value);
+    ctx.hugepages_strict = Some(strict);
+    debug!("nvrc.hugepages.strict: {strict}");
+}
+
+/// Bring an interface up with an address and routes, e.g. the IPoIB or
+/// management interface FM and nvlsm need. See [`net`] for the settings.
+fn nvrc_net(param: &str, value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    let ifname = &param["nvrc.net.".len()..];
+    ctx.net.push(net::parse(ifname, value)?);
+    debug!("{param}: {value}");
+    Ok(())
+}
+
+/// Refuse to boot when an interface outside this list (and `lo`) shows up,
+/// e.g. a virtio-net device the VMM hot-plugged on its own.
+fn nvrc_net_allow(value: &str, ctx: &mut NVRC) {
+    let allow: Vec<String> = value
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect();
+    debug!("nvrc.net.allow: {allow:?}");
+    ctx.net_allow = Some(allow);
+}
+
+/// GPUDirect features loaded after the GPU driver. See [`gpudirect`].
+fn nvrc_gpudirect(value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    ctx.gpudirect = gpudirect::parse(value)?;
+    debug!("nvrc.gpudirect: {:?}", ctx.gpudirect);
+    Ok(())
+}
+
+/// Pass a parameter to a module NVRC loads, e.g.
+/// `nvrc.module.nvidia.NVreg_EnableGpuFirmware=0`. Replaces NVRC's own default
+/// for the same parameter.
+fn nvrc_module(param: &str, value: &str, ctx: &mut NVRC) -> Result<(), String> {
+    let (module, name) = modprobe::check_param(&param["nvrc.module.".len()..], value)?;
+    ctx.module_params.push((module, name, value.to_owned()));
+    debug!("{param}: {value}");
+    Ok(())
+}
+
 /// UVM persistence mode keeps unified memory state across CUDA context teardowns.
 /// Reduces initialization overhead for short-lived CUDA applications.
 fn uvm_persistenced_mode(value: &str, ctx: &mut NVRC) {
@@ -284,6 +417,15 @@ mod tests {
         assert_eq!(c.dcgm_enabled, Some(false));
     }
 
+    #[test]
+    fn test_nvrc_graphics() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some("nvrc.graphics=on"));
+        assert_eq!(c.graphics, Some(true));
+        c.process_kernel_params(Some("nvrc.graphics=off"));
+        assert_eq!(c.graphics, Some(false));
+    }
+
     #[test]
     fn test_nvidia_smi_srs() {
         let mut c = NVRC::default();
@@ -309,6 +451,164 @@ mod tests {
         assert_eq!(c.uvm_persistence_mode, Some(true));
     }
 
+    #[test]
+    fn test_nvrc_landlock_daemons() {
+        let mut c = NVRC::default();
+        assert_eq!(c.landlock_daemons, None);
+
+        c.process_kernel_params(Some("nvrc.landlock.daemons=on"));
+        assert_eq!(c.landlock_daemons, Some(true));
+
+        nvrc_landlock_daemons("off", &mut c);
+        assert_eq!(c.landlock_daemons, Some(false));
+    }
+
+    #[test]
+    fn test_nvrc_sysctl_override() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some(
+            "nvrc.sysctl.fs.protected_fifos=1 nvrc.sysctl.fs.protected_regular=0",
+        ));
+        assert_eq!(
+            c.sysctl_overrides,
+            [
+                ("fs.protected_fifos".to_owned(), "1".to_owned()),
+                ("fs.protected_regular".to_owned(), "0".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nvrc_sysctl_unknown_key_is_err() {
+        let err = NVRC::default()
+            .try_process_kernel_params(Some("nvrc.sysctl.kernel.core_pattern=1"))
+            .unwrap_err();
+        assert!(err.contains("unknown key"));
+    }
+
+    #[test]
+    fn test_nvrc_sysctl_mandatory_locked_when_confidential() {
+        let result =
+            NVRC::default().try_process_kernel_params(Some("nvrc.sysctl.kernel.kptr_restrict=0"));
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+
+    #[test]
+    fn test_nvrc_mount() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some("nvrc.mount.hugetlbfs=pagesize=1G nvrc.mount.shm="));
+        assert_eq!(
+            c.mount_enables,
+            [
+                ("hugetlbfs".to_owned(), "pagesize=1G".to_owned()),
+                ("shm".to_owned(), String::new()),
+            ]
+        );
+        let err = NVRC::default()
+            .try_process_kernel_params(Some("nvrc.mount.proc=hidepid=2"))
+            .unwrap_err();
+        assert!(err.contains("before the cmdline"));
+    }
+
+    #[test]
+    fn test_nvrc_hugepages() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some(
+            "nvrc.hugepages.1G=0:2,1:2 nvrc.mount.hugetlbfs=pagesize=2M nvrc.hugepages.2M=64 nvrc.hugepages.strict=on",
+        ));
+        assert_eq!(c.hugepages.len(), 3);
+        assert_eq!(c.hugepages[2].count, 64);
+        assert_eq!(c.hugepages_strict, Some(true));
+        // The explicit mount picks one of the sizes
+        assert_eq!(
+            c.mount_enables,
+            [("hugetlbfs".to_owned(), "pagesize=2M".to_owned())]
+        );
+        assert!(NVRC::default()
+            .try_process_kernel_params(Some("nvrc.hugepages.3X=1"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_nvrc_hugepages_mount() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some("nvrc.hugepages.1G=0:2,1:2 nvrc.hugepages.1G=8"));
+        assert_eq!(
+            c.mount_enables,
+            [("hugetlbfs".to_owned(), "pagesize=1048576K".to_owned())]
+        );
+
+        let err = NVRC::default()
+            .try_process_kernel_params(Some("nvrc.hugepages.1G=8 nvrc.hugepages.2M=512"))
+            .unwrap_err();
+        assert!(err.contains("nvrc.mount.hugetlbfs"), "{err}");
+    }
+
+    #[test]
+    fn test_nvrc_net() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some(
+            "nvrc.net.eth0=up,addr=10.0.0.2/24,route=default@10.0.0.1 nvrc.net.ib0=mtu=4092",
+        ));
+        assert_eq!(c.net.len(), 2);
+        assert_eq!(c.net[0].name, "eth0");
+        assert_eq!(c.net[0].settings.len(), 3);
+        assert_eq!(c.net[1].settings, [net::Setting::Mtu(4092)]);
+        assert!(NVRC::default()
+            .try_process_kernel_params(Some("nvrc.net.eth0=promisc"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_nvrc_net_allow() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some("nvrc.net.allow=eth0,ib0"));
+        assert_eq!(c.net_allow, Some(vec!["eth0".to_owned(), "ib0".to_owned()]));
+        assert!(c.net.is_empty());
+        // Empty means nothing but lo
+        c.process_kernel_params(Some("nvrc.net.allow="));
+        assert_eq!(c.net_allow, Some(vec![]));
+    }
+
+    #[test]
+    fn test_nvrc_gpudirect() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some("nvrc.gpudirect=rdma,gdrcopy"));
+        assert_eq!(
+            c.gpudirect,
+            [gpudirect::Feature::Rdma, gpudirect::Feature::Gdrcopy]
+        );
+        assert!(NVRC::default()
+            .try_process_kernel_params(Some("nvrc.gpudirect=rdma,nvme"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_nvrc_module() {
+        let mut c = NVRC::default();
+        c.process_kernel_params(Some(
+            "nvrc.module.nvidia.NVreg_EnableGpuFirmware=0 nvrc.module.nvidia-uvm.uvm_perf_prefetch_enable=1",
+        ));
+        assert_eq!(
+            c.module_params,
+            [
+                (
+                    "nvidia".to_owned(),
+                    "NVreg_EnableGpuFirmware".to_owned(),
+                    "0".to_owned()
+                ),
+                (
+                    "nvidia_uvm".to_owned(),
+                    "uvm_perf_prefetch_enable".to_owned(),
+                    "1".to_owned()
+                ),
+            ]
+        );
+        assert!(NVRC::default()
+            .try_process_kernel_params(Some("nvrc.module.nvidia=1"))
+            .is_err());
+    }
+
     #[test]
     fn test_parse_boolean() {
         assert!(parse_boolean("on"));
@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Landlock filesystem sandboxing for NVRC's long-lived children.
+//!
+//! Processes that outlive init (the syslog drain child, and optionally each
+//! daemon) are confined to the paths they declare. Rulesets are built in the
+//! parent and only applied in the child, so the child side is two syscalls
+//! and safe to run between fork and exec.
+//!
+//! Kernels without Landlock degrade to running unconfined with a warning:
+//! the ABI version gates which rights are handled, and a right the kernel
+//! does not know about is simply never restricted.
+
+use log::{info, warn};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::macros::ResultExt;
+
+pub const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+pub const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+pub const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+pub const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+pub const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+pub const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+pub const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+pub const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+pub const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+pub const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+pub const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+pub const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+pub const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+/// ABI 2
+pub const ACCESS_FS_REFER: u64 = 1 << 13;
+/// ABI 3
+pub const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+/// ABI 5
+pub const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+/// Read files and list directories.
+pub const READ: u64 = ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+/// Modify, create and remove entries (no device nodes).
+pub const WRITE: u64 = ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_SYM;
+/// Execute files.
+pub const EXECUTE: u64 = ACCESS_FS_EXECUTE;
+/// Open device nodes read/write and issue ioctls on them (`/dev/nvidia*`).
+pub const DEVICE: u64 = ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_IOCTL_DEV;
+
+/// The only rights Landlock accepts on a non-directory rule.
+const FILE_ACCESS: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+/// `struct landlock_ruleset_attr`, truncated to the ABI 1 field: the kernel
+/// accepts a shorter struct and treats the missing net/scope fields as zero.
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+/// `struct landlock_path_beneath_attr` (packed in the UAPI header).
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// A path and the rights granted on it and everything beneath it.
+pub struct Rule<'a> {
+    pub path: &'a str,
+    pub access: u64,
+}
+
+/// A ruleset built in the parent, ready to be applied by a child.
+/// `fd` is `None` when the kernel has no Landlock support.
+pub struct Ruleset {
+    fd: Option<OwnedFd>,
+}
+
+impl Ruleset {
+    /// Raw ruleset fd for [`restrict`] inside a `pre_exec` hook, which must
+    /// not allocate or borrow across the fork.
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.fd.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// Confine the calling process. Irreversible and inherited across exec.
+    pub fn restrict_self(&self) -> io::Result<()> {
+        restrict(self.raw_fd())
+    }
+}
+
+/// Landlock ABI version of the running kernel, 0 when unsupported.
+pub fn abi() -> i32 {
+    // SAFETY: the version query takes no attribute pointer and has no side effects.
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    version.max(0) as i32
+}
+
+/// Filesystem rights handled by each ABI; newer kernels only add bits.
+fn handled_access(abi: i32) -> u64 {
+    let mut access = (ACCESS_FS_MAKE_SYM << 1) - 1;
+    if abi >= 2 {
+        access |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_FS_TRUNCATE;
+    }
+    if abi >= 5 {
+        access |= ACCESS_FS_IOCTL_DEV;
+    }
+    access
+}
+
+/// Build a ruleset granting `rules` and denying everything else, and record it
+/// in the boot log under `name`.
+pub fn ruleset(name: &str, rules: &[Rule]) -> Ruleset {
+    build(abi(), name, rules)
+}
+
+fn build(abi: i32, name: &str, rules: &[Rule]) -> Ruleset {
+    if abi < 1 {
+        warn!("landlock unsupported by kernel: {name} runs unconfined");
+        return Ruleset { fd: None };
+    }
+
+    let handled = handled_access(abi);
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    // SAFETY: attr is a valid, initialized landlock_ruleset_attr prefix.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0u32,
+        )
+    };
+    if fd < 0 {
+        panic!(
+            "landlock_create_ruleset {name}: {}",
+            io::Error::last_os_error()
+        );
+    }
+    // SAFETY: the syscall returned a fresh fd that nothing else owns.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+    let mut granted = Vec::new();
+    for rule in rules {
+        let Some(access) = add_rule(&fd, rule, handled) else {
+            warn!("landlock {name}: {} missing, access denied", rule.path);
+            continue;
+        };
+        granted.push(format!("{}={}", rule.path, describe(access)));
+    }
+
+    info!("landlock abi={abi} {name}: {}", granted.join(" "));
+    Ruleset { fd: Some(fd) }
+}
+
+/// Grant `rule`, masked to what the kernel handles and what the path's type
+/// allows. Returns the granted rights, or `None` if the path does not exist:
+/// an absent path is simply never reachable, which fails safe.
+fn add_rule(ruleset: &OwnedFd, rule: &Rule, handled: u64) -> Option<u64> {
+    let meta = fs::metadata(rule.path).ok()?;
+    let mut access = rule.access & handled;
+    if !meta.is_dir() {
+        access &= FILE_ACCESS;
+    }
+
+    let path = CString::new(rule.path).or_panic(format_args!("landlock path {}", rule.path));
+    // SAFETY: path is NUL-terminated; O_PATH opens no file content.
+    let parent = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if parent < 0 {
+        panic!("open {}: {}", rule.path, io::Error::last_os_error());
+    }
+    // SAFETY: open returned a fresh fd that nothing else owns.
+    let parent = unsafe { OwnedFd::from_raw_fd(parent) };
+
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: parent.as_raw_fd(),
+    };
+    // SAFETY: both fds are open and attr is a valid landlock_path_beneath_attr.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.as_raw_fd(),
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0u32,
+        )
+    };
+    if ret < 0 {
+        panic!(
+            "landlock_add_rule {}: {}",
+            rule.path,
+            io::Error::last_os_error()
+        );
+    }
+    Some(access)
+}
+
+/// Apply a ruleset fd to the calling process; `None` (no kernel support) is a
+/// no-op. Only raw syscalls, so it is safe between fork and exec.
+pub fn restrict(fd: Option<RawFd>) -> io::Result<()> {
+    let Some(fd) = fd else {
+        return Ok(());
+    };
+    // SAFETY: prctl and landlock_restrict_self only act on the calling thread.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::syscall(libc::SYS_landlock_restrict_self, fd, 0u32) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Compact `r`/`w`/`x`/`d` summary of granted rights for the boot log.
+fn describe(access: u64) -> String {
+    [
+        (READ, 'r'),
+        (WRITE | ACCESS_FS_MAKE_CHAR | ACCESS_FS_MAKE_BLOCK, 'w'),
+        (EXECUTE, 'x'),
+        (ACCESS_FS_IOCTL_DEV, 'd'),
+    ]
+    .iter()
+    .filter(|(bits, _)| access & bits != 0)
+    .map(|(_, c)| *c)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_handled_access_abi1() {
+        assert_eq!(handled_access(1), 0x1fff);
+    }
+
+    #[test]
+    fn test_handled_access_grows_with_abi() {
+        assert_eq!(handled_access(2), 0x1fff | ACCESS_FS_REFER);
+        assert_eq!(
+            handled_access(3),
+            0x1fff | ACCESS_FS_REFER | ACCESS_FS_TRUNCATE
+        );
+        assert_eq!(handled_access(4), handled_access(3));
+        assert_eq!(handled_access(5), 0xffff);
+        assert_eq!(handled_access(7), 0xffff);
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(READ), "r");
+        assert_eq!(describe(READ | WRITE), "rw");
+        assert_eq!(describe(READ | EXECUTE), "rx");
+        assert_eq!(describe(DEVICE), "rwd");
+        assert_eq!(describe(0), "");
+    }
+
+    #[test]
+    fn test_build_unsupported_abi_is_unconfined() {
+        let ruleset = build(
+            0,
+            "test",
+            &[Rule {
+                path: "/",
+                access: READ,
+            }],
+        );
+        assert!(ruleset.raw_fd().is_none());
+        ruleset.restrict_self().unwrap(); // no-op
+    }
+
+    #[test]
+    fn test_restrict_none_is_noop() {
+        restrict(None).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "landlock syscalls are foreign functions miri cannot emulate"
+    )]
+    fn test_add_rule_missing_path_is_skipped() {
+        if abi() < 1 {
+            return;
+        }
+        let ruleset = build(
+            abi(),
+            "test",
+            &[Rule {
+                path: "/nonexistent/landlock/path",
+                access: READ,
+            }],
+        );
+        assert!(ruleset.raw_fd().is_some());
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "landlock syscalls are foreign functions miri cannot emulate"
+    )]
+    fn test_add_rule_masks_dir_rights_on_files() {
+        if abi() < 1 {
+            return;
+        }
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("log");
+        fs::write(&file, "").unwrap();
+        let ruleset = build(abi(), "test", &[]);
+        let granted = add_rule(
+            ruleset.fd.as_ref().unwrap(),
+            &Rule {
+                path: file.to_str().unwrap(),
+                access: READ | WRITE,
+            },
+            handled_access(abi()),
+        )
+        .unwrap();
+        // READ_DIR and the MAKE_*/REMOVE_* rights would make add_rule EINVAL.
+        assert_eq!(granted & !FILE_ACCESS, 0);
+        assert_ne!(granted & ACCESS_FS_WRITE_FILE, 0);
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "fork/landlock syscalls are foreign functions miri cannot emulate"
+    )]
+    fn test_restrict_self_confines_child() {
+        if abi() < 1 {
+            return;
+        }
+        let allowed = TempDir::new().unwrap();
+        let denied = TempDir::new().unwrap();
+        fs::write(allowed.path().join("ok"), "ok").unwrap();
+        fs::write(denied.path().join("secret"), "secret").unwrap();
+
+        let ruleset = ruleset(
+            "test",
+            &[Rule {
+                path: allowed.path().to_str().unwrap(),
+                access: READ,
+            }],
+        );
+        let ok = allowed.path().join("ok");
+        let secret = denied.path().join("secret");
+
+        // SAFETY: the child only makes syscalls and exits via _exit.
+        match unsafe { fork() }.expect("fork") {
+            ForkResult::Parent { child } => {
+                let status = waitpid(child, None).expect("waitpid");
+                assert_eq!(status, WaitStatus::Exited(child, 0));
+            }
+            ForkResult::Child => {
+                let code = match ruleset.restrict_self() {
+                    Err(_) => 1,
+                    Ok(()) if fs::read(&ok).is_err() => 2,
+                    Ok(()) if fs::read(&secret).is_ok() => 3,
+                    Ok(()) => 0,
+                };
+                // SAFETY: immediate exit without running the harness' atexit.
+                unsafe { libc::_exit(code) };
+            }
+        }
+    }
+}
@@ -0,0 +1,865 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Build the glibc loader cache (`ld.so.cache`, new format) natively, so the
+//! boot path does not depend on `/sbin/ldconfig`.
+//!
+//! Mirrors `ldconfig -X -f <conf>` as Debian's glibc builds it: the conf's
+//! dirs (following `include` globs), each dir's `glibc-hwcaps/<subdir>`s, then
+//! the builtin multiarch, `/lib` and `/usr/lib` dirs, deduplicated by inode.
+//! Only native 64-bit ELF libraries are cached; ldconfig would also list
+//! foreign-ABI ones, which the loader skips anyway.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 20] = b"glibc-ld.so.cache1.1";
+const EXTENSION_MAGIC: u32 = 0xeaa4_2174;
+const EXTENSION_GENERATOR: u32 = 0;
+const EXTENSION_GLIBC_HWCAPS: u32 = 1;
+const GENERATOR: &str = concat!("NVRC ", env!("CARGO_PKG_VERSION"));
+const HEADER_LEN: usize = 48;
+const ENTRY_LEN: usize = 24;
+/// `cache_file_new.flags`: little-endian.
+const FLAGS_LE: u8 = 2;
+/// `hwcap` marker for `glibc-hwcaps` entries; the low bits index the subdir
+/// names in the hwcaps extension section.
+const HWCAP_EXTENSION: u64 = 1 << 62;
+
+/// Relative `include` patterns resolve against the including conf's dir.
+const CONF_DIR: &str = "/etc";
+const HWCAPS_DIR: &str = "glibc-hwcaps";
+/// Include loops are a broken base image, not something to recurse into.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+#[cfg(target_arch = "x86_64")]
+const MACHINE: u16 = 62; // EM_X86_64
+/// `FLAG_ELF_LIBC6 | FLAG_X8664_LIB64`.
+#[cfg(target_arch = "x86_64")]
+const FLAGS: i32 = 0x0303;
+/// The loader's trusted dirs, searched after every configured one.
+#[cfg(target_arch = "x86_64")]
+pub const SYSTEM_DIRS: &[&str] = &[
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib",
+    "/usr/lib",
+];
+
+#[cfg(target_arch = "aarch64")]
+const MACHINE: u16 = 183; // EM_AARCH64
+/// `FLAG_ELF_LIBC6 | FLAG_AARCH64_LIB64`.
+#[cfg(target_arch = "aarch64")]
+const FLAGS: i32 = 0x0a03;
+#[cfg(target_arch = "aarch64")]
+pub const SYSTEM_DIRS: &[&str] = &[
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/lib",
+    "/usr/lib",
+];
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const DT_NULL: u64 = 0;
+const DT_STRTAB: u64 = 5;
+const DT_SONAME: u64 = 14;
+/// Upper bound on program headers and `.dynamic` read per file.
+const MAX_READ: u64 = 64 * 1024;
+const NAME_MAX: usize = 256;
+
+/// A dir to scan; `hwcaps` names the `glibc-hwcaps` subdir it is.
+struct Dir {
+    path: String,
+    hwcaps: Option<String>,
+}
+
+/// `key` is what the loader looks up (a soname, or a `.so` dev link's own
+/// name), `value` the path it resolves to.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    key: String,
+    value: String,
+    hwcaps: Option<String>,
+}
+
+/// The cache for ld.so conf text `conf`, as `ldconfig -X -f` would write it.
+pub fn generate(conf: &str) -> Vec<u8> {
+    generate_in(Path::new("/"), conf)
+}
+
+/// `root` is a sysroot (as `ldconfig -r`): files are read below it, paths in
+/// the cache stay relative to it.
+fn generate_in(root: &Path, conf: &str) -> Vec<u8> {
+    let mut entries: Vec<Entry> = dirs(root, conf)
+        .iter()
+        .flat_map(|dir| scan(root, dir))
+        .collect();
+    // Stable: among equal keys the earlier dir stays first and wins lookups.
+    entries.sort_by(compare);
+    encode(&entries)
+}
+
+fn at(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// Dirs to scan in order, missing ones dropped and repeats (by inode, so
+/// `/lib` and `/usr/lib` on merged-usr count once) keeping the first path.
+fn dirs(root: &Path, conf: &str) -> Vec<Dir> {
+    let mut listed = Vec::new();
+    parse_conf(root, conf, CONF_DIR, 0, &mut listed);
+    listed.extend(SYSTEM_DIRS.iter().map(|d| d.to_string()));
+
+    let mut seen = Vec::new();
+    let mut dirs = Vec::new();
+    let mut add = |dir: Dir| {
+        let Ok(meta) = fs::metadata(at(root, &dir.path)) else {
+            return false;
+        };
+        if !meta.is_dir() || seen.contains(&(meta.dev(), meta.ino())) {
+            return false;
+        }
+        seen.push((meta.dev(), meta.ino()));
+        dirs.push(dir);
+        true
+    };
+    for path in listed {
+        if !add(Dir {
+            path: path.clone(),
+            hwcaps: None,
+        }) {
+            continue;
+        }
+        let hwcaps = at(root, &format!("{path}/{HWCAPS_DIR}"));
+        let mut subdirs: Vec<String> = fs::read_dir(hwcaps)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| e.path().is_dir())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .collect();
+        subdirs.sort();
+        for name in subdirs {
+            add(Dir {
+                path: format!("{path}/{HWCAPS_DIR}/{name}"),
+                hwcaps: Some(name),
+            });
+        }
+    }
+    dirs
+}
+
+/// Dir lines, `include <glob>...` and `#` comments; `hwcap` lines are
+/// obsolete and ignored, as are relative dirs.
+fn parse_conf(root: &Path, conf: &str, conf_dir: &str, depth: usize, dirs: &mut Vec<String>) {
+    for line in conf.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("include") => {
+                if depth == MAX_INCLUDE_DEPTH {
+                    warn!("ld.so conf: includes nested deeper than {MAX_INCLUDE_DEPTH}, ignored");
+                    continue;
+                }
+                for pattern in words {
+                    let pattern = if pattern.starts_with('/') {
+                        pattern.to_owned()
+                    } else {
+                        format!("{conf_dir}/{pattern}")
+                    };
+                    for file in glob(root, &pattern) {
+                        let Ok(text) = fs::read_to_string(at(root, &file)) else {
+                            continue;
+                        };
+                        let dir = file.rsplit_once('/').map_or("/", |(dir, _)| dir);
+                        parse_conf(root, &text, dir, depth + 1, dirs);
+                    }
+                }
+            }
+            Some(word) if word.eq_ignore_ascii_case("hwcap") => {}
+            Some(_) if line.starts_with('/') => {
+                let dir = line.trim_end_matches('/');
+                dirs.push(if dir.is_empty() { "/" } else { dir }.to_owned());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Expand `*`/`?` in the last component of `pattern`, sorted like glob(3);
+/// as there, wildcards do not match a leading dot.
+fn glob(root: &Path, pattern: &str) -> Vec<String> {
+    let (dir, name) = pattern.rsplit_once('/').unwrap_or(("", pattern));
+    if !name.contains(['*', '?']) {
+        return vec![pattern.to_owned()];
+    }
+    let mut matches: Vec<String> = fs::read_dir(at(root, dir))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|f| !f.starts_with('.') && wildcard(name.as_bytes(), f.as_bytes()))
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|f| format!("{dir}/{f}")).collect()
+}
+
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| wildcard(rest, &name[i..])),
+        Some((p, rest)) => name
+            .split_first()
+            .is_some_and(|(n, name)| (*p == b'?' || p == n) && wildcard(rest, name)),
+    }
+}
+
+/// The libraries in `dir`, one per key. A symlink keeps its own name as key
+/// when it is the soname link or a `.so` dev link; any other name is cached
+/// under its soname. Within a dir a file beats a link, then the newest name.
+fn scan(root: &Path, dir: &Dir) -> Vec<Entry> {
+    struct Lib {
+        key: String,
+        name: String,
+        link: bool,
+    }
+
+    let Ok(read) = fs::read_dir(at(root, &dir.path)) else {
+        return Vec::new();
+    };
+    let mut libs: Vec<Lib> = Vec::new();
+    for entry in read.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !(name.starts_with("lib") || name.starts_with("ld-")) || !name.contains(".so") {
+            continue;
+        }
+        // Follows links; dangling ones, dirs and fifos are skipped unopened.
+        let path = entry.path();
+        if !fs::metadata(&path).is_ok_and(|m| m.is_file()) {
+            continue;
+        }
+        let Some(soname) = File::open(&path).ok().and_then(|f| soname(&f)) else {
+            continue;
+        };
+        let soname = soname.unwrap_or_else(|| name.clone());
+        let link = entry.file_type().is_ok_and(|t| t.is_symlink())
+            && (name == soname || (name.ends_with(".so") && soname.starts_with(&name)));
+        let key = if link { name.clone() } else { soname };
+        match libs.iter_mut().find(|l| l.key == key) {
+            Some(l) => {
+                if (!link && l.link) || (link == l.link && libcmp(&l.name, &name).is_lt()) {
+                    l.name = name;
+                    l.link = link;
+                }
+            }
+            None => libs.push(Lib { key, name, link }),
+        }
+    }
+
+    libs.into_iter()
+        .map(|l| {
+            // Outside glibc-hwcaps the loader goes through the soname link, so
+            // an update that renames the file keeps resolving.
+            let file = if dir.hwcaps.is_some() {
+                &l.name
+            } else {
+                &l.key
+            };
+            Entry {
+                value: format!("{}/{file}", dir.path),
+                key: l.key,
+                hwcaps: dir.hwcaps.clone(),
+            }
+        })
+        .collect()
+}
+
+fn read(file: &File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    let mut buf = vec![0; usize::try_from(len).ok()?];
+    file.read_exact_at(&mut buf, offset).ok()?;
+    Some(buf)
+}
+
+fn u16_at(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+/// SONAME of a native 64-bit little-endian ELF with a dynamic segment. `None`
+/// when `file` is no such library (linker scripts, other ABIs, truncated),
+/// `Some(None)` when it has no SONAME.
+fn soname(file: &File) -> Option<Option<String>> {
+    let ehdr = read(file, 0, 64)?;
+    if ehdr[..4] != *b"\x7fELF" || ehdr[4] != 2 || ehdr[5] != 1 || u16_at(&ehdr, 18) != MACHINE {
+        return None;
+    }
+    let phentsize = u64::from(u16_at(&ehdr, 54));
+    let phnum = u64::from(u16_at(&ehdr, 56));
+    if phentsize < 56 || phentsize * phnum > MAX_READ {
+        return None;
+    }
+    let phdrs = read(file, u64_at(&ehdr, 32), phentsize * phnum)?;
+
+    // Like ld.so, map dynamic addresses through the first PT_LOAD.
+    let mut load_bias = None;
+    let mut dynamic = None;
+    for ph in phdrs.chunks_exact(phentsize as usize) {
+        match u32_at(ph, 0) {
+            PT_LOAD if load_bias.is_none() => {
+                load_bias = Some(u64_at(ph, 16).wrapping_sub(u64_at(ph, 8)));
+            }
+            PT_DYNAMIC => dynamic = Some((u64_at(ph, 8), u64_at(ph, 32))),
+            _ => {}
+        }
+    }
+    let (offset, size) = dynamic?;
+    let dynamic = read(file, offset, size.min(MAX_READ))?;
+
+    let mut strtab = None;
+    let mut name = None;
+    for d in dynamic.chunks_exact(16) {
+        match u64_at(d, 0) {
+            DT_NULL => break,
+            DT_STRTAB => strtab = Some(u64_at(d, 8)),
+            DT_SONAME => name = Some(u64_at(d, 8)),
+            _ => {}
+        }
+    }
+    let Some(name) = name else {
+        return Some(None);
+    };
+    let offset = strtab?.checked_sub(load_bias?)?.checked_add(name)?;
+    let mut buf = [0; NAME_MAX];
+    let n = file.read_at(&mut buf, offset).ok()?;
+    let end = buf[..n].iter().position(|b| *b == 0)?;
+    let soname = std::str::from_utf8(&buf[..end]).ok()?;
+    (!soname.is_empty()).then(|| Some(soname.to_owned()))
+}
+
+/// Cache order: keys descending by [`libcmp`], as the loader bisects; for a
+/// key, `glibc-hwcaps` entries first, by subdir name.
+fn compare(a: &Entry, b: &Entry) -> Ordering {
+    libcmp(&b.key, &a.key).then_with(|| match (&a.hwcaps, &b.hwcaps) {
+        (Some(x), Some(y)) => x.cmp(y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    })
+}
+
+/// glibc's `_dl_cache_libcmp`: digit runs compare as numbers and sort after
+/// any other character, so `libfoo.so.10` follows `libfoo.so.9`.
+fn libcmp(a: &str, b: &str) -> Ordering {
+    fn number(s: &[u8], i: &mut usize) -> u64 {
+        let mut n = 0u64;
+        while let Some(d) = s.get(*i).filter(|c| c.is_ascii_digit()) {
+            n = n.saturating_mul(10).saturating_add(u64::from(d - b'0'));
+            *i += 1;
+        }
+        n
+    }
+
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while let Some(&c) = a.get(i) {
+        let d = b.get(j).copied();
+        let d_digit = d.is_some_and(|d| d.is_ascii_digit());
+        if c.is_ascii_digit() {
+            if !d_digit {
+                return Ordering::Greater;
+            }
+            let (x, y) = (number(a, &mut i), number(b, &mut j));
+            if x != y {
+                return x.cmp(&y);
+            }
+        } else if d_digit {
+            return Ordering::Less;
+        } else if d != Some(c) {
+            return c.cmp(&d.unwrap_or(0));
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    if j < b.len() {
+        Ordering::Less
+    } else {
+        Ordering::Equal
+    }
+}
+
+/// Interned NUL-terminated strings; offsets count from the file start.
+struct Strings {
+    base: usize,
+    bytes: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl Strings {
+    fn add(&mut self, s: &str) -> u32 {
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = (self.base + self.bytes.len()) as u32;
+        self.bytes.extend_from_slice(s.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(s.to_owned(), offset);
+        offset
+    }
+}
+
+/// Offsets and sizes are `u32` on disk; a cache never nears 4 GiB.
+fn put_u32(out: &mut Vec<u8>, v: usize) {
+    out.extend_from_slice(&(v as u32).to_le_bytes());
+}
+
+/// Header, entries, string table, then the extension sections (generator
+/// and, when used, the `glibc-hwcaps` subdir names).
+fn encode(entries: &[Entry]) -> Vec<u8> {
+    let mut hwcaps: Vec<&str> = entries.iter().filter_map(|e| e.hwcaps.as_deref()).collect();
+    hwcaps.sort();
+    hwcaps.dedup();
+
+    let mut strings = Strings {
+        base: HEADER_LEN + ENTRY_LEN * entries.len(),
+        bytes: Vec::new(),
+        offsets: HashMap::new(),
+    };
+    let mut libs = Vec::with_capacity(ENTRY_LEN * entries.len());
+    for e in entries {
+        let hwcap = match &e.hwcaps {
+            Some(h) => HWCAP_EXTENSION | hwcaps.iter().position(|x| x == h).unwrap() as u64,
+            None => 0,
+        };
+        libs.extend_from_slice(&FLAGS.to_le_bytes());
+        libs.extend_from_slice(&strings.add(&e.key).to_le_bytes());
+        libs.extend_from_slice(&strings.add(&e.value).to_le_bytes());
+        libs.extend_from_slice(&0u32.to_le_bytes()); // osversion, unused
+        libs.extend_from_slice(&hwcap.to_le_bytes());
+    }
+    let hwcap_names: Vec<u32> = hwcaps.iter().map(|h| strings.add(h)).collect();
+
+    let extension = (strings.base + strings.bytes.len()).next_multiple_of(4);
+    let sections = if hwcaps.is_empty() { 1 } else { 2 };
+    let hwcaps_at = extension + 8 + 16 * sections;
+    let generator_at = hwcaps_at + 4 * hwcaps.len();
+
+    let mut out = Vec::with_capacity(generator_at + GENERATOR.len());
+    out.extend_from_slice(MAGIC);
+    put_u32(&mut out, entries.len());
+    put_u32(&mut out, strings.bytes.len());
+    out.extend_from_slice(&[FLAGS_LE, 0, 0, 0]);
+    put_u32(&mut out, extension);
+    out.extend_from_slice(&[0; 12]);
+    out.extend_from_slice(&libs);
+    out.extend_from_slice(&strings.bytes);
+    out.resize(extension, 0);
+
+    put_u32(&mut out, EXTENSION_MAGIC as usize);
+    put_u32(&mut out, sections);
+    for (tag, offset, size) in [
+        (EXTENSION_GENERATOR, generator_at, GENERATOR.len()),
+        (EXTENSION_GLIBC_HWCAPS, hwcaps_at, 4 * hwcaps.len()),
+    ]
+    .into_iter()
+    .take(sections)
+    {
+        put_u32(&mut out, tag as usize);
+        put_u32(&mut out, 0); // flags
+        put_u32(&mut out, offset);
+        put_u32(&mut out, size);
+    }
+    for name in hwcap_names {
+        out.extend_from_slice(&name.to_le_bytes());
+    }
+    out.extend_from_slice(GENERATOR.as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// Entries as `ldconfig -p` shows them: flags, key, value, hwcaps subdir.
+    fn decode(cache: &[u8]) -> Vec<(i32, String, String, Option<String>)> {
+        assert_eq!(&cache[..20], MAGIC);
+        let string = |at: u32| {
+            let at = at as usize;
+            let end = cache[at..].iter().position(|b| *b == 0).unwrap();
+            String::from_utf8(cache[at..at + end].to_vec()).unwrap()
+        };
+        let extension = u32_at(cache, 32) as usize;
+        assert_eq!(u32_at(cache, extension), EXTENSION_MAGIC);
+        let mut hwcaps = Vec::new();
+        for s in 0..u32_at(cache, extension + 4) as usize {
+            let section = extension + 8 + 16 * s;
+            if u32_at(cache, section) == EXTENSION_GLIBC_HWCAPS {
+                let (at, size) = (
+                    u32_at(cache, section + 8) as usize,
+                    u32_at(cache, section + 12) as usize,
+                );
+                hwcaps = (at..at + size)
+                    .step_by(4)
+                    .map(|i| string(u32_at(cache, i)))
+                    .collect();
+            }
+        }
+        (0..u32_at(cache, 20) as usize)
+            .map(|i| {
+                let e = HEADER_LEN + ENTRY_LEN * i;
+                let hwcap = u64_at(cache, e + 16);
+                let subdir = (hwcap & HWCAP_EXTENSION != 0)
+                    .then(|| hwcaps[(hwcap & 0xffff_ffff) as usize].clone());
+                (
+                    u32_at(cache, e) as i32,
+                    string(u32_at(cache, e + 4)),
+                    string(u32_at(cache, e + 8)),
+                    subdir,
+                )
+            })
+            .collect()
+    }
+
+    /// Minimal ELF64 DSO: one PT_LOAD over the file, a PT_DYNAMIC with
+    /// DT_SONAME (if any), DT_STRTAB and DT_STRSZ.
+    fn elf(soname: Option<&str>, machine: u16) -> Vec<u8> {
+        let mut strtab = vec![0];
+        if let Some(s) = soname {
+            strtab.extend_from_slice(s.as_bytes());
+            strtab.push(0);
+        }
+        let dyn_at = 64 + 2 * 56;
+        let mut dynamic: Vec<(u64, u64)> = soname.map(|_| (DT_SONAME, 1)).into_iter().collect();
+        let str_at = (dyn_at + 16 * (dynamic.len() + 3)) as u64;
+        dynamic.extend([(DT_STRTAB, str_at), (10, strtab.len() as u64), (DT_NULL, 0)]);
+        let dyn_len = 16 * dynamic.len() as u64;
+        let total = str_at + strtab.len() as u64;
+
+        let mut out = b"\x7fELF\x02\x01\x01".to_vec();
+        out.resize(16, 0);
+        for v in [3u16, machine] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&1u32.to_le_bytes());
+        for v in [0u64, 64, 0] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        for v in [64u16, 56, 2, 64, 0, 0] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        for (ty, offset, len, align) in [
+            (PT_LOAD, 0u64, total, 0x1000u64),
+            (PT_DYNAMIC, dyn_at as u64, dyn_len, 8),
+        ] {
+            out.extend_from_slice(&ty.to_le_bytes());
+            out.extend_from_slice(&4u32.to_le_bytes());
+            for v in [offset, offset, offset, len, len, align] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        for (tag, val) in dynamic {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+        out.extend_from_slice(&strtab);
+        out
+    }
+
+    fn write(root: &Path, path: &str, data: &[u8]) {
+        let path = at(root, path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    fn lib(root: &Path, path: &str, soname: &str) {
+        write(root, path, &elf(Some(soname), MACHINE));
+    }
+
+    fn link(root: &Path, path: &str, target: &str) {
+        symlink(target, at(root, path)).unwrap();
+    }
+
+    const MULTIARCH: &str = "/usr/lib/x86_64-linux-gnu";
+    const EXTENSION: &str = "/run/kata-extensions/gpu/usr/lib/x86_64-linux-gnu";
+    /// What `consumer::ldso_conf` writes for the `gpu` extension.
+    const CONF: &str = "include /etc/ld.so.conf.d/*.conf\n\
+                        /run/kata-extensions/gpu/usr/lib/x86_64-linux-gnu\n";
+
+    /// A merged-usr Debian rootfs with a `gpu` extension. Each odd entry
+    /// exercises an ldconfig rule; see `testdata/ld.so.cache`.
+    fn rootfs() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let t = MULTIARCH;
+        fs::create_dir_all(at(root, t)).unwrap();
+        link(root, "/lib", "usr/lib");
+
+        let confd = "/etc/ld.so.conf.d";
+        write(
+            root,
+            &format!("{confd}/libc.conf"),
+            b"# libc default configuration\n/usr/local/lib\n",
+        );
+        write(
+            root,
+            &format!("{confd}/x86_64-linux-gnu.conf"),
+            b"# Multiarch support\n/usr/local/lib/x86_64-linux-gnu\n/lib/x86_64-linux-gnu\n/usr/lib/x86_64-linux-gnu\n",
+        );
+        write(
+            root,
+            &format!("{confd}/zz-extra.conf"),
+            b"/opt/extra/lib/ # trailing slash and comment\ninclude extra.d/*.conf\n",
+        );
+        write(root, &format!("{confd}/extra.d/one.conf"), b"/opt/one\n");
+        write(root, &format!("{confd}/ignored.txt"), b"/opt/ignored\n");
+        lib(root, "/opt/one/libone.so.1", "libone.so.1");
+        lib(root, "/opt/ignored/libignored.so.1", "libignored.so.1");
+        lib(root, "/opt/extra/lib/libextra.so.3", "libextra.so.3");
+
+        lib(root, &format!("{t}/libc.so.6"), "libc.so.6");
+        write(
+            root,
+            &format!("{t}/libc.so"),
+            b"/* GNU ld script */\nGROUP ( libc.so.6 )\n",
+        );
+        lib(root, &format!("{t}/libz.so.1.2.13"), "libz.so.1");
+        link(root, &format!("{t}/libz.so.1"), "libz.so.1.2.13");
+        link(root, &format!("{t}/libz.so"), "libz.so.1");
+        lib(root, &format!("{t}/libbar.so.1.0"), "libbar.so.1");
+        link(root, &format!("{t}/libbar.so"), "libbar.so.1.0");
+        link(root, &format!("{t}/libalias.so.1"), "libz.so.1.2.13");
+        lib(root, &format!("{t}/libq.so"), "libq.so.1");
+        link(root, &format!("{t}/libqq.so"), "libq.so");
+        lib(root, &format!("{t}/libfoo.so.9"), "libfoo.so.9");
+        lib(root, &format!("{t}/libfoo.so.10"), "libfoo.so.10");
+        lib(root, &format!("{t}/libfoo2.so.1"), "libfoo2.so.1");
+        write(root, &format!("{t}/libnosoname.so"), &elf(None, MACHINE));
+        lib(root, &format!("{t}/notalib.so.1"), "notalib.so.1");
+        lib(
+            root,
+            &format!("{t}/ld-linux-x86-64.so.2"),
+            "ld-linux-x86-64.so.2",
+        );
+        write(
+            root,
+            &format!("{t}/libforeign.so.1"),
+            &elf(Some("libforeign.so.1"), 40),
+        );
+        write(root, &format!("{t}/libdir.so.1/x"), b"");
+        link(root, &format!("{t}/libdangling.so.1"), "missing.so.1");
+        lib(
+            root,
+            &format!("{t}/glibc-hwcaps/x86-64-v3/libz.so.1.2.13"),
+            "libz.so.1",
+        );
+        lib(
+            root,
+            &format!("{t}/glibc-hwcaps/x86-64-v2/libfoo.so.10"),
+            "libfoo.so.10",
+        );
+        lib(root, "/usr/lib/libsys.so.2", "libsys.so.2");
+
+        let e = EXTENSION;
+        lib(root, &format!("{e}/libcuda.so.550.54"), "libcuda.so.1");
+        link(root, &format!("{e}/libcuda.so.1"), "libcuda.so.550.54");
+        lib(
+            root,
+            &format!("{e}/libnvidia-ml.so.550.54"),
+            "libnvidia-ml.so.1",
+        );
+        lib(root, &format!("{e}/libz.so.1"), "libz.so.1");
+        lib(root, &format!("{e}/libfoo.so"), "libfoo.so.10");
+        link(root, &format!("{e}/libfoo.so.10"), "libfoo.so");
+        tmp
+    }
+
+    /// `ldconfig -X -r <rootfs> -f /run/ld.so.conf -C /run/ld.so.cache` over
+    /// [`rootfs`] with [`CONF`], glibc 2.36 (Debian 12).
+    #[cfg(target_arch = "x86_64")]
+    const LDCONFIG_CACHE: &[u8] = include_bytes!("testdata/ld.so.cache");
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_generate_matches_ldconfig() {
+        let root = rootfs();
+        assert_eq!(
+            decode(&generate_in(root.path(), CONF)),
+            decode(LDCONFIG_CACHE)
+        );
+    }
+
+    #[test]
+    fn test_generate_layout() {
+        let root = rootfs();
+        let cache = generate_in(root.path(), CONF);
+        assert_eq!(cache[28], FLAGS_LE);
+        let entries = decode(&cache);
+        assert!(entries.iter().all(|(flags, ..)| *flags == FLAGS));
+        // Entries must stay bisectable by the loader.
+        assert!(entries.windows(2).all(|w| libcmp(&w[0].1, &w[1].1).is_ge()));
+        assert!(cache.ends_with(GENERATOR.as_bytes()));
+        assert_eq!(u32_at(&cache, 32) % 4, 0);
+    }
+
+    #[test]
+    fn test_generate_empty() {
+        let tmp = TempDir::new().unwrap();
+        let cache = generate_in(tmp.path(), "");
+        assert!(decode(&cache).is_empty());
+        // Generator only, no hwcaps section.
+        assert_eq!(u32_at(&cache, u32_at(&cache, 32) as usize + 4), 1);
+    }
+
+    #[test]
+    fn test_dirs() {
+        let root = rootfs();
+        let paths: Vec<(String, Option<String>)> = dirs(root.path(), CONF)
+            .into_iter()
+            .map(|d| (d.path, d.hwcaps))
+            .collect();
+        let plain = |p: &str| (p.to_owned(), None);
+        let hwcaps = |name: &str| {
+            (
+                format!("/lib/x86_64-linux-gnu/glibc-hwcaps/{name}"),
+                Some(name.to_owned()),
+            )
+        };
+        assert_eq!(
+            paths,
+            [
+                plain("/lib/x86_64-linux-gnu"),
+                hwcaps("x86-64-v2"),
+                hwcaps("x86-64-v3"),
+                plain("/opt/extra/lib"),
+                plain("/opt/one"),
+                plain(EXTENSION),
+                plain("/lib"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_conf() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        write(root, "/etc/a.d/1.conf", b"/one\n");
+        write(root, "/etc/a.d/2.conf", b"include ../b.conf\n");
+        write(root, "/etc/a.d/.hidden.conf", b"/hidden\n");
+        write(root, "/etc/b.conf", b"  /b//  \n");
+        write(root, "/etc/loop.conf", b"include /etc/loop.conf\n/loop\n");
+
+        let mut dirs = Vec::new();
+        parse_conf(
+            root,
+            "# comment\nhwcap 1 nosegneg\nrelative/dir\n/\ninclude a.d/*.conf /etc/missing.conf\n/last # x\n",
+            CONF_DIR,
+            0,
+            &mut dirs,
+        );
+        assert_eq!(dirs, ["/", "/one", "/b", "/last"]);
+
+        let mut dirs = Vec::new();
+        parse_conf(root, "include /etc/loop.conf", CONF_DIR, 0, &mut dirs);
+        assert_eq!(dirs.len(), MAX_INCLUDE_DEPTH);
+    }
+
+    #[rstest]
+    #[case("*.conf", "x86_64-linux-gnu.conf", true)]
+    #[case("*.conf", "ignored.txt", false)]
+    #[case("lib?.conf", "libc.conf", true)]
+    #[case("lib?.conf", "libcc.conf", false)]
+    #[case("*", "", true)]
+    #[case("a*b*c", "aXbYc", true)]
+    #[case("a*b*c", "aXbY", false)]
+    fn test_wildcard(#[case] pattern: &str, #[case] name: &str, #[case] matches: bool) {
+        assert_eq!(wildcard(pattern.as_bytes(), name.as_bytes()), matches);
+    }
+
+    #[rstest]
+    #[case("libfoo.so.10", "libfoo.so.9", Ordering::Greater)]
+    #[case("libfoo.so.1", "libfoo.so.1", Ordering::Equal)]
+    #[case("libfoo2.so.1", "libfoo.so.10", Ordering::Greater)]
+    #[case("libz.so", "libz.so.1", Ordering::Less)]
+    #[case("libz.so.1", "libz.so", Ordering::Greater)]
+    #[case("libc.so.6", "libz.so.1", Ordering::Less)]
+    #[case("lib007.so", "lib7.so", Ordering::Equal)]
+    #[case("lib99999999999999999999999.so", "lib1.so", Ordering::Greater)]
+    fn test_libcmp(#[case] a: &str, #[case] b: &str, #[case] expected: Ordering) {
+        assert_eq!(libcmp(a, b), expected);
+        assert_eq!(libcmp(b, a), expected.reverse());
+    }
+
+    #[test]
+    fn test_soname() {
+        let tmp = TempDir::new().unwrap();
+        let check = |data: &[u8]| {
+            let path = tmp.path().join("lib.so");
+            fs::write(&path, data).unwrap();
+            soname(&File::open(&path).unwrap())
+        };
+        assert_eq!(
+            check(&elf(Some("libz.so.1"), MACHINE)),
+            Some(Some("libz.so.1".to_owned()))
+        );
+        assert_eq!(check(&elf(None, MACHINE)), Some(None));
+        assert_eq!(check(&elf(Some("libz.so.1"), 40)), None);
+        assert_eq!(check(b"/* GNU ld script */"), None);
+        assert_eq!(check(b""), None);
+
+        let full = elf(Some("libz.so.1"), MACHINE);
+        assert_eq!(check(&full[..full.len() - 3]), None, "unterminated soname");
+        assert_eq!(check(&full[..100]), None, "truncated phdrs");
+        let mut big_endian = full.clone();
+        big_endian[5] = 2;
+        assert_eq!(check(&big_endian), None);
+    }
+
+    #[test]
+    fn test_scan_prefers_file_then_newest() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path();
+        let dir = "/lib/glibc-hwcaps/v3";
+        let scan_v3 = || {
+            scan(
+                root,
+                &Dir {
+                    path: dir.to_owned(),
+                    hwcaps: Some("v3".to_owned()),
+                },
+            )
+        };
+        let cached = |file: &str| {
+            vec![Entry {
+                key: "libfoo.so.1".to_owned(),
+                value: format!("{dir}/{file}"),
+                hwcaps: Some("v3".to_owned()),
+            }]
+        };
+        lib(root, &format!("{dir}/libfoo.so.1.0"), "libfoo.so.1");
+        lib(root, &format!("{dir}/libfoo.so.1.10"), "libfoo.so.1");
+        lib(root, &format!("{dir}/libfoo.so.1.9"), "libfoo.so.1");
+        // The soname link loses to any file.
+        link(root, &format!("{dir}/libfoo.so.1"), "libfoo.so.1.0");
+        assert_eq!(scan_v3(), cached("libfoo.so.1.10"));
+        // A link under another name counts as a file, and is newest.
+        link(root, &format!("{dir}/libfoo.so.1.99"), "libfoo.so.1.0");
+        assert_eq!(scan_v3(), cached("libfoo.so.1.99"));
+    }
+}
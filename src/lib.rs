@@ -7,24 +7,39 @@
 //! The main binary uses these modules internally.
 
 pub mod config;
+pub mod consumer;
 pub mod daemon;
+pub mod dm;
 pub mod execute;
+pub mod gpt;
+pub mod gpu_bind;
 pub mod gpu_extension;
+pub mod gpudirect;
+pub mod graphics;
 pub mod guest_extension_image;
 pub mod hash;
+pub mod hugepages;
+pub mod ipe;
 pub mod kata_agent;
 pub mod kernel_params;
 pub mod kmsg;
+pub mod landlock;
+pub mod ldcache;
+pub mod loadpin;
 pub mod lockdown;
 #[macro_use]
 pub mod macros;
+pub mod manifest;
 pub mod mode;
 pub mod modprobe;
 pub mod mount;
+pub mod net;
 pub mod nvrc;
 pub mod smi;
+pub mod sysctl;
 pub mod syslog;
 pub mod toolkit;
+pub mod version;
 
 #[cfg(test)]
 pub mod test_utils;
@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Trust extension dm-verity devices in LoadPin.
+//!
+//! LoadPin pins kernel file loads (modules, firmware) to the first superblock
+//! they come from, i.e. the base rootfs. Extensions are separate superblocks,
+//! so modules and firmware shipped in `/run/kata-extensions/gpu` are denied
+//! unless their dm-verity root digest is on LoadPin's trusted list
+//! (`CONFIG_SECURITY_LOADPIN_VERITY`). The digests come from the measured
+//! cmdline via [`guest_extension_image::mount_all`].
+//!
+//! The kernel accepts the list exactly once per boot, so this must run before
+//! anything else could set it and before the first load from an extension.
+//!
+//! LoadPin reads the list itself (READING_POLICY) and pins that read like any
+//! other, so the list ships on the base rootfs: by the time this runs the
+//! rootfs may already be pinned by a module load, and a list on `/run` would
+//! be denied, or, read first, would pin the tmpfs instead. The image build
+//! writes it from the extensions it was built with; an extension on the
+//! cmdline that is missing from it cannot load modules or firmware.
+//!
+//! [`guest_extension_image::mount_all`]: crate::guest_extension_image::mount_all
+
+use log::info;
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::guest_extension_image::Extension;
+use crate::lockdown::policy_violation;
+use crate::macros::ResultExt;
+
+const DM_VERITY: &str = "/sys/kernel/security/loadpin/dm-verity";
+/// Trusted digest list on the base rootfs, handed to the kernel by fd.
+const DIGESTS_FILE: &str = "/etc/loadpin/trusted-verity-digests";
+/// First line the kernel requires before the digests.
+const HEADER: &str = "# LOADPIN_TRUSTED_VERITY_ROOT_DIGESTS";
+/// Hex lengths of the verity digests extensions use: 32 and 64 bytes.
+const DIGEST_HEX_LENS: &[usize] = &[64, 128];
+
+// LOADPIN_IOC_SET_TRUSTED_VERITY_DIGESTS: _IOW('L', 0x00, unsigned int),
+// argument is a pointer to the fd of the digest list file.
+nix::ioctl_write_ptr!(set_trusted_verity_digests, b'L', 0x00, libc::c_uint);
+
+/// Hand the shipped digest list to LoadPin once extensions are mounted, after
+/// checking it covers every one of them. No-op without extensions: the base
+/// rootfs is pinned the usual way.
+pub fn trust(extensions: &[Extension]) {
+    let digests: Vec<&str> = extensions.iter().map(|e| e.root_hash.as_str()).collect();
+    if trust_at(Path::new(DM_VERITY), Path::new(DIGESTS_FILE), &digests) {
+        for e in extensions {
+            info!(
+                "loadpin: trusted extension {} root_hash={}",
+                e.name, e.root_hash
+            );
+        }
+    }
+}
+
+/// Returns whether LoadPin accepted the list.
+fn trust_at(interface: &Path, digests_file: &Path, digests: &[&str]) -> bool {
+    if digests.is_empty() {
+        return false;
+    }
+    if !interface.exists() {
+        policy_violation(format_args!(
+            "{} missing: LoadPin will deny modules and firmware from extensions",
+            interface.display()
+        ));
+        return false;
+    }
+    let Ok(content) = fs::read_to_string(digests_file) else {
+        policy_violation(format_args!(
+            "{} missing: LoadPin will deny modules and firmware from extensions",
+            digests_file.display()
+        ));
+        return false;
+    };
+    let listed =
+        digest_list(&content).unwrap_or_else(|e| panic!("{}: {e}", digests_file.display()));
+    for digest in digests {
+        if !listed.iter().any(|l| l.eq_ignore_ascii_case(digest)) {
+            policy_violation(format_args!(
+                "verity root digest {digest} not in {}: LoadPin will deny its modules and firmware",
+                digests_file.display()
+            ));
+        }
+    }
+
+    let list = File::open(digests_file).or_panic(format_args!("open {}", digests_file.display()));
+    let ctl = File::open(interface).or_panic(format_args!("open {}", interface.display()));
+
+    let fd = list.as_raw_fd() as libc::c_uint;
+    // SAFETY: both fds are open for the duration of the call; fd is a valid
+    // pointer to an unsigned int as the ioctl expects.
+    unsafe { set_trusted_verity_digests(ctl.as_raw_fd(), &fd) }
+        .or_panic("LoadPin rejected trusted verity digests");
+    true
+}
+
+/// The digests of a list file: the header line, then one whole hex digest
+/// per line. Anything else is rejected here rather than by a terse kernel
+/// EPROTO.
+fn digest_list(content: &str) -> Result<Vec<&str>, String> {
+    let mut lines = content.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(format!("first line is not {HEADER:?}"));
+    }
+    lines
+        .map(|digest| {
+            let whole = DIGEST_HEX_LENS.contains(&digest.len())
+                && digest.bytes().all(|b| b.is_ascii_hexdigit());
+            whole
+                .then_some(digest)
+                .ok_or_else(|| format!("invalid verity root digest {digest:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use tempfile::TempDir;
+
+    const DIGEST: &str = "4392b1b4a37bb1d9a2e6ea8a64b3ff7b0c6e6c7c4e58c1f9bb4e0b8a0b6f6a11";
+
+    fn list(digests: &[&str]) -> String {
+        let mut list = format!("{HEADER}\n");
+        for digest in digests {
+            list.push_str(digest);
+            list.push('\n');
+        }
+        list
+    }
+
+    #[test]
+    fn test_digest_list() {
+        let sha512 = "ab".repeat(64);
+        let content = list(&[DIGEST, &sha512]);
+        assert_eq!(digest_list(&content).unwrap(), [DIGEST, sha512.as_str()]);
+        assert!(digest_list(&list(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_digest_list_rejects_partial_digests() {
+        for digest in ["not-hex", "", "abcdef", &DIGEST[1..], &format!("{DIGEST}0")] {
+            assert!(digest_list(&list(&[digest])).is_err(), "{digest:?}");
+        }
+        assert!(digest_list(DIGEST).unwrap_err().contains(HEADER));
+    }
+
+    #[test]
+    fn test_trust_without_extensions_is_noop() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("digests");
+        assert!(!trust_at(&tmp.path().join("dm-verity"), &file, &[]));
+        trust(&[]);
+    }
+
+    #[test]
+    fn test_trust_missing_interface() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("digests");
+        fs::write(&file, list(&[DIGEST])).unwrap();
+        let result = panic::catch_unwind(|| {
+            assert!(!trust_at(&tmp.path().join("dm-verity"), &file, &[DIGEST]));
+        });
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+
+    #[test]
+    fn test_trust_missing_list() {
+        let tmp = TempDir::new().unwrap();
+        let interface = tmp.path().join("dm-verity");
+        fs::write(&interface, "").unwrap();
+        let result = panic::catch_unwind(|| {
+            assert!(!trust_at(
+                &interface,
+                &tmp.path().join("digests"),
+                &[DIGEST]
+            ));
+        });
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+
+    #[test]
+    fn test_trust_pinned_root() {
+        // The root is pinned and read-only: the shipped list is handed to the
+        // kernel as is, and nothing is written anywhere. A regular file stands
+        // in for securityfs, so the ioctl fails (ENOTTY), which must be fatal
+        // rather than silently leaving LoadPin unfed.
+        let root = TempDir::new().unwrap();
+        let interface = root.path().join("dm-verity");
+        fs::write(&interface, "").unwrap();
+        let file = root.path().join("digests");
+        fs::write(&file, list(&[DIGEST])).unwrap();
+        let before = fs::read_dir(root.path()).unwrap().count();
+
+        let result = panic::catch_unwind(|| trust_at(&interface, &file, &[&DIGEST.to_uppercase()]));
+        let err = result.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("LoadPin rejected"), "{msg}");
+        assert_eq!(fs::read_to_string(&file).unwrap(), list(&[DIGEST]));
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), before);
+    }
+
+    #[test]
+    fn test_trust_unlisted_extension() {
+        let tmp = TempDir::new().unwrap();
+        let interface = tmp.path().join("dm-verity");
+        fs::write(&interface, "").unwrap();
+        let file = tmp.path().join("digests");
+        fs::write(&file, list(&[DIGEST])).unwrap();
+        let other = "cd".repeat(32);
+        let err = panic::catch_unwind(|| trust_at(&interface, &file, &[&other])).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        // Without confidential the violation only warns and the ioctl fails
+        match cfg!(feature = "confidential") {
+            true => assert!(msg.contains("not in"), "{msg}"),
+            false => assert!(msg.starts_with("LoadPin rejected"), "{msg}"),
+        }
+    }
+}
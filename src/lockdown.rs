@@ -5,13 +5,37 @@
 //!
 //! In production, panic triggers VM power-off. For tests, the shutdown
 //! action is configurable via `set_panic_hook_with()`.
+//!
+//! [`audit`] checks that the kernel is as hardened as the image promises:
+//! a guest booted with a tampered cmdline (say, without `lockdown=`) still
+//! boots the same binaries, so NVRC is the last place to notice.
+//!
+//! [`check_loader`] refuses a rootfs where the dynamic loader could be told to
+//! inject code into the processes NVRC starts.
 
 use crate::macros::ResultExt;
 use nix::sys::reboot::{reboot, RebootMode};
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::unistd::sync;
+use std::fmt::Display;
 use std::fs;
 use std::io::Write;
 use std::panic;
+use std::path::Path;
+
+const SECURITYFS: &str = "/sys/kernel/security";
+const MODULES_DISABLED: &str = "/proc/sys/kernel/modules_disabled";
+const SIG_ENFORCE: &str = "/sys/module/module/parameters/sig_enforce";
+const ETC: &str = "/etc";
+const LD_SO_PRELOAD: &str = "/etc/ld.so.preload";
+
+/// Lockdown modes that keep root from modifying the running kernel.
+const LOCKDOWN_MODES: &[&str] = &["integrity", "confidentiality"];
+
+/// LSMs the guest kernel is built and booted with (ARCHITECTURE.md §"Kernel
+/// Hardening" and §"Integrity Policy Enforcement"). `lsm=` on the cmdline
+/// can drop any of them, so they are checked, not assumed.
+const REQUIRED_LSMS: &[&str] = &["lockdown", "loadpin", "safesetid", "ipe"];
 
 /// Default shutdown action: power off the VM.
 fn power_off() {
@@ -49,8 +73,116 @@ pub(crate) fn set_panic_hook_with<F: Fn() + Send + Sync + 'static>(shutdown: F)
 /// that blocks potential kernel-level attacks via malicious modules.
 /// This is a one-way operation: once set, it cannot be undone without reboot.
 pub fn disable_modules_loading() {
-    const PATH: &str = "/proc/sys/kernel/modules_disabled";
-    fs::write(PATH, b"1\n").or_panic(format_args!("disable module loading {PATH}"));
+    disable_modules_loading_at(MODULES_DISABLED)
+}
+
+fn disable_modules_loading_at(path: &str) {
+    fs::write(path, b"1\n").or_panic(format_args!("disable module loading {path}"));
+    let state = fs::read_to_string(path).or_panic(format_args!("read {path}"));
+    if state.trim() != "1" {
+        panic!(
+            "{path} reads {:?} after disabling module loading",
+            state.trim()
+        );
+    }
+}
+
+/// A kernel weaker than the image's security policy. Fatal on confidential
+/// builds, where the attestation story depends on it; a warning otherwise so
+/// development kernels still boot.
+pub fn policy_violation(msg: impl Display) {
+    if cfg!(feature = "confidential") {
+        panic!("security policy violation: {msg}");
+    }
+    warn!("security policy violation: {msg}");
+}
+
+/// Verify lockdown mode, the LSM stack and module signature enforcement, and
+/// log the module loading state.
+pub fn audit() {
+    for violation in audit_at(
+        Path::new(SECURITYFS),
+        Path::new(MODULES_DISABLED),
+        Path::new(SIG_ENFORCE),
+    ) {
+        policy_violation(violation);
+    }
+}
+
+fn audit_at(securityfs: &Path, modules_disabled: &Path, sig_enforce: &Path) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    match read_trimmed(&securityfs.join("lockdown")) {
+        Some(modes) => {
+            let active = active_lockdown(&modes);
+            info!("lockdown: {}", active.unwrap_or("?"));
+            if !active.is_some_and(|m| LOCKDOWN_MODES.contains(&m)) {
+                violations.push(format!(
+                    "lockdown mode {modes:?}, expected integrity or confidentiality"
+                ));
+            }
+        }
+        None => violations.push("lockdown LSM not available".to_owned()),
+    }
+
+    match read_trimmed(&securityfs.join("lsm")) {
+        Some(lsms) => {
+            info!("lsm: {lsms}");
+            let active: Vec<&str> = lsms.split(',').collect();
+            for lsm in REQUIRED_LSMS.iter().filter(|l| !active.contains(l)) {
+                violations.push(format!("LSM {lsm} not active"));
+            }
+        }
+        None => violations.push("active LSM list not available".to_owned()),
+    }
+
+    match read_trimmed(sig_enforce) {
+        Some(v) if v == "Y" => info!("module sig_enforce: {v}"),
+        Some(v) => violations.push(format!("module sig_enforce is {v}, expected Y")),
+        None => violations.push("module signature enforcement not available".to_owned()),
+    }
+
+    // Expected 0 here: GPU drivers still have to load. Logged, not enforced;
+    // disable_modules_loading() checks the final state.
+    match read_trimmed(modules_disabled) {
+        Some(v) => info!("modules_disabled: {v}"),
+        None => violations.push("modules_disabled not available".to_owned()),
+    }
+
+    violations
+}
+
+/// The loader maps every library in `/etc/ld.so.preload` into each process
+/// NVRC starts, kata-agent included, and none of them can object. Refuse to
+/// go on with one present; an `/etc` that could grow one is a policy
+/// violation, like the root remount that should have prevented it. Runs after
+/// every extension bind, right before the first process is started.
+pub fn check_loader() {
+    check_loader_at(Path::new(LD_SO_PRELOAD), Path::new(ETC))
+}
+
+fn check_loader_at(preload: &Path, etc: &Path) {
+    // symlink_metadata: a dangling link is as much a preload as a file.
+    if preload.symlink_metadata().is_ok() {
+        panic!("{} present, refusing to start processes", preload.display());
+    }
+    let st = statvfs(etc).or_panic(format_args!("statvfs {}", etc.display()));
+    if !st.flags().contains(FsFlags::ST_RDONLY) {
+        policy_violation(format_args!("{} is on a writable mount", etc.display()));
+        return;
+    }
+    info!("loader: no preload, {} read-only", etc.display());
+}
+
+/// `none [integrity] confidentiality` -> `integrity`
+fn active_lockdown(modes: &str) -> Option<&str> {
+    modes
+        .split_whitespace()
+        .find_map(|m| m.strip_prefix('[')?.strip_suffix(']'))
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
 }
 
 #[cfg(test)]
@@ -59,6 +191,165 @@ mod tests {
     use crate::test_utils::require_root;
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// Hardened kernel state; tests weaken one aspect at a time.
+    struct Fake {
+        tmp: TempDir,
+    }
+
+    impl Fake {
+        fn new() -> Self {
+            let tmp = TempDir::new().unwrap();
+            fs::create_dir(tmp.path().join("security")).unwrap();
+            let fake = Fake { tmp };
+            fake.write("security/lockdown", "none [integrity] confidentiality\n");
+            fake.write(
+                "security/lsm",
+                "lockdown,capability,landlock,yama,loadpin,safesetid,ipe\n",
+            );
+            fake.write("sig_enforce", "Y\n");
+            fake.write("modules_disabled", "0\n");
+            fake
+        }
+
+        fn write(&self, name: &str, content: &str) {
+            fs::write(self.tmp.path().join(name), content).unwrap();
+        }
+
+        fn audit(&self) -> Vec<String> {
+            let p = self.tmp.path();
+            audit_at(
+                &p.join("security"),
+                &p.join("modules_disabled"),
+                &p.join("sig_enforce"),
+            )
+        }
+    }
+
+    #[test]
+    fn test_audit_hardened_kernel() {
+        assert!(Fake::new().audit().is_empty());
+    }
+
+    #[test]
+    fn test_audit_confidentiality_lockdown() {
+        let fake = Fake::new();
+        fake.write("security/lockdown", "none integrity [confidentiality]\n");
+        assert!(fake.audit().is_empty());
+    }
+
+    #[test]
+    fn test_audit_lockdown_none() {
+        let fake = Fake::new();
+        fake.write("security/lockdown", "[none] integrity confidentiality\n");
+        let violations = fake.audit();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("lockdown mode"));
+    }
+
+    #[test]
+    fn test_audit_missing_lsms() {
+        let fake = Fake::new();
+        fake.write("security/lsm", "capability,landlock,yama\n");
+        let violations = fake.audit();
+        assert_eq!(violations.len(), 4);
+        assert!(violations.iter().any(|v| v.contains("ipe")));
+        assert!(violations.iter().any(|v| v.contains("loadpin")));
+    }
+
+    #[test]
+    fn test_audit_sig_enforce_off() {
+        let fake = Fake::new();
+        fake.write("sig_enforce", "N\n");
+        assert_eq!(fake.audit(), ["module sig_enforce is N, expected Y"]);
+    }
+
+    #[test]
+    fn test_audit_no_securityfs() {
+        let fake = Fake::new();
+        fs::remove_dir_all(fake.tmp.path().join("security")).unwrap();
+        fs::remove_file(fake.tmp.path().join("modules_disabled")).unwrap();
+        assert_eq!(fake.audit().len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "present, refusing to start processes")]
+    fn test_check_loader_preload_present() {
+        let tmp = TempDir::new().unwrap();
+        let preload = tmp.path().join("ld.so.preload");
+        std::os::unix::fs::symlink("/nonexistent/libevil.so", &preload).unwrap();
+        check_loader_at(&preload, tmp.path());
+    }
+
+    #[test]
+    fn test_check_loader_writable_etc() {
+        // Warns, or panics on confidential builds, like a failed root remount.
+        let tmp = TempDir::new().unwrap();
+        let result =
+            panic::catch_unwind(|| check_loader_at(&tmp.path().join("ld.so.preload"), tmp.path()));
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_check_loader_read_only_etc() {
+        use nix::mount::{mount, umount, MsFlags};
+        require_root();
+        let tmp = TempDir::new().unwrap();
+        let etc = tmp.path();
+        mount(
+            Some("tmpfs"),
+            etc,
+            Some("tmpfs"),
+            MsFlags::MS_RDONLY,
+            None::<&str>,
+        )
+        .unwrap();
+        let result = panic::catch_unwind(|| check_loader_at(&etc.join("ld.so.preload"), etc));
+        umount(etc).unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_active_lockdown() {
+        assert_eq!(active_lockdown("[none] integrity"), Some("none"));
+        assert_eq!(
+            active_lockdown("none integrity [confidentiality]"),
+            Some("confidentiality")
+        );
+        assert_eq!(active_lockdown(""), None);
+    }
+
+    #[test]
+    fn test_audit_host_warns_without_confidential() {
+        // Dev hosts are rarely locked down; without `confidential` that must
+        // only warn.
+        if !cfg!(feature = "confidential") {
+            audit();
+        }
+    }
+
+    #[test]
+    fn test_policy_violation() {
+        let result = panic::catch_unwind(|| policy_violation("test"));
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+
+    #[test]
+    fn test_disable_modules_loading_verifies_read_back() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("modules_disabled");
+        disable_modules_loading_at(path.to_str().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+
+        // /dev/null swallows the write, like a kernel refusing it
+        let result = panic::catch_unwind(|| disable_modules_loading_at("/dev/null"));
+        assert!(result.is_err());
+    }
 
     #[test]
     #[ignore] // Permanently disables module loading until reboot - run with --include-ignored on CI
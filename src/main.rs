@@ -2,26 +2,40 @@
 // Copyright (c) NVIDIA CORPORATION
 
 mod config;
+mod consumer;
 mod daemon;
+mod dm;
 mod execute;
+mod gpt;
+mod gpu_bind;
 mod gpu_extension;
+mod gpudirect;
+mod graphics;
 mod guest_extension_image;
 mod hash;
+mod hugepages;
 mod infiniband;
 mod init;
+mod ipe;
 mod kata_agent;
 mod kernel_params;
 mod kmsg;
+mod landlock;
+mod ldcache;
+mod loadpin;
 mod lockdown;
 mod macros;
+mod manifest;
 mod mode;
 mod modprobe;
 mod mount;
 mod net;
 mod nvrc;
 mod smi;
+mod sysctl;
 mod syslog;
 mod toolkit;
+mod version;
 
 pub use macros::ResultExt;
 
@@ -43,14 +57,27 @@ use toolkit::nvidia_ctk_cdi;
 /// On bare metal HGX systems (GPUs + NVSwitches), also starts
 /// the fabric manager via the appropriate NVSwitch mode.
 fn mode_gpu(init: &mut NVRC, nvswitch: Option<&str>) {
+    gpu_bind::prepare();
     modprobe::load("nvidia");
+    gpu_bind::verify();
     modprobe::load("nvidia-uvm");
+    // Before any of them runs; NVL4's fabric manager is checked in mode_nvl4.
+    let mut binaries = vec!["/bin/nvidia-smi", "/bin/nvidia-persistenced"];
+    if nvswitch == Some("nvl5") {
+        binaries.push("/bin/nv-fabricmanager");
+    }
+    version::check(&binaries);
 
     match nvswitch {
         Some("nvl4") => mode_nvl4(init, FABRIC_MODE_FULL),
         Some("nvl5") => mode_nvl5(init, FABRIC_MODE_FULL),
         _ => {}
     }
+    gpudirect::setup(&init.gpudirect);
+    let graphics = init.graphics == Some(true);
+    if graphics {
+        graphics::setup();
+    }
 
     init.nvidia_persistenced();
 
@@ -60,7 +87,7 @@ fn mode_gpu(init: &mut NVRC, nvswitch: Option<&str>) {
 
     init.nv_hostengine();
     init.dcgm_exporter();
-    nvidia_ctk_cdi();
+    nvidia_ctk_cdi(graphics);
     init.nvidia_smi_srs();
     init.health_checks();
 }
@@ -70,6 +97,7 @@ fn mode_gpu(init: &mut NVRC, nvswitch: Option<&str>) {
 /// Loads NVIDIA driver and starts fabric manager. GPUs are assigned to service VM.
 fn mode_nvl4(init: &mut NVRC, fabric_mode: u8) {
     modprobe::load("nvidia");
+    version::check(&["/bin/nv-fabricmanager"]);
     init.nv_fabricmanager(fabric_mode, "greedy");
     init.health_checks();
 }
@@ -105,15 +133,34 @@ fn main() {
     kmsg::kernlog_setup();
     syslog::poll();
     init.process_kernel_params(None);
+    modprobe::set_params(init.module_params.clone());
     hash::self_exe();
+    lockdown::audit();
 
     // Before disable_modules_loading() so dm-verity/erofs modules can still load.
-    guest_extension_image::mount_all();
+    let extensions = guest_extension_image::mount_all();
+    // Before any modprobe or firmware bind reads from an extension superblock.
+    loadpin::trust(&extensions);
 
-    // Expose gpu-extension libs/firmware before any driver load. No-op if absent.
-    gpu_extension::setup();
+    // Expose extension libs/firmware before any driver load. No-op without extensions.
+    consumer::setup(&extensions);
+    // After every bind (one could bring in /etc), before anything is spawned.
+    lockdown::check_loader();
 
+    hugepages::reserve(&init.hugepages, init.hugepages_strict == Some(true));
     let detected = mode::detect();
+    let mut extra = net::modules(&init.net);
+    if detected.mode == "gpu" {
+        extra.extend(gpudirect::modules(&init.gpudirect));
+        if init.graphics == Some(true) {
+            extra.extend(graphics::MODULES);
+        }
+    }
+    modprobe::set_mode(detected.mode, detected.nvswitch, &extra);
+    mount::setup_mode(detected.mode, &init.mount_enables);
+    // Loads the IPoIB drivers it needs; before FM, nvlsm or any other daemon
+    // binds to these addresses.
+    net::setup(&init.net);
     match detected.mode {
         "cpu" => info!("executing cpu mode"),
         "gpu" => mode_gpu(&mut init, detected.nvswitch),
@@ -122,6 +169,11 @@ fn main() {
         unknown => panic!("unknown mode: {unknown}"),
     }
 
+    sysctl::harden(&init.sysctl_overrides);
+    modprobe::verify();
     lockdown::disable_modules_loading();
+    ipe::activate(&extensions);
+    mount::audit();
+    net::audit(init.net_allow.as_deref());
     kata_agent::fork_agent(POLL_FOREVER);
 }
@@ -0,0 +1,531 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Extension manifest (`components.toml`): what an extension ships and where.
+//!
+//! Consumers ([`crate::consumer`]: firmware binds, the loader cache, modprobe,
+//! the attester variant handed to kata-agent) read paths from here instead of assuming a
+//! layout. The manifest lives on the verity-protected extension, so it is as
+//! trusted as the binaries it describes; its sha256 is logged so an operator
+//! can match a boot against the image build.
+//!
+//! In the tables below only the TOML subset the image builder emits is
+//! understood: `key = value` with strings, string arrays, integers or
+//! booleans, one per line. Anything else there is an error, and so are
+//! unknown keys, so a typo cannot drop a path. Other tables belong to other
+//! readers (attestation-agent reads `[process.*]`) and are skipped without
+//! parsing their values, multi-line ones included.
+//!
+//! ```toml
+//! [firmware]
+//! dirs = ["lib/firmware/nvidia"]  # below lib/firmware/ only
+//!
+//! [libraries]
+//! dirs = ["usr/lib/x86_64-linux-gnu"]
+//!
+//! [modules]
+//! dirname = "."          # modprobe --dirname, relative to the extension
+//! prefixes = ["nvidia"]  # modules loaded from there
+//!
+//! [modules.sha256]       # optional pins, checked before each load
+//! nvidia = "<sha256 of nvidia.ko as shipped>"
+//!
+//! [binaries]
+//! paths = ["bin/nvidia-smi", "bin/nvidia-cdi-hook"]
+//!
+//! [process.variants.nvidia]
+//! # attestation-agent settings; NVRC only records the variant name
+//! ```
+
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::hash::hex_encode;
+use crate::macros::ResultExt;
+
+/// Manifest location relative to an extension mount.
+pub const FILE: &str = "components.toml";
+
+const VARIANTS: &str = "process.variants.";
+/// The tables NVRC reads; everything else is skipped.
+const TABLES: &[&str] = &[
+    "firmware",
+    "libraries",
+    "modules",
+    "modules.sha256",
+    "binaries",
+];
+/// Firmware dirs must be below this, the only base-image path they may cover.
+const FIRMWARE_BASE: &str = "lib/firmware";
+
+/// Declared contents of one extension. Paths are relative to its mount.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub firmware_dirs: Vec<String>,
+    pub library_dirs: Vec<String>,
+    pub module_dirname: Option<String>,
+    pub module_prefixes: Vec<String>,
+    /// `[modules.sha256]` pins as (module name, lowercase hex digest).
+    pub module_sha256: Vec<(String, String)>,
+    pub binaries: Vec<String>,
+    /// `[process.variants.<name>]` tables, in file order.
+    pub attester_variants: Vec<String>,
+    /// sha256 of the file as read.
+    pub digest: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Str(String),
+    Array(Vec<String>),
+    Other,
+}
+
+/// Load `<root>/components.toml`. `None` when the extension ships none; a
+/// manifest that does not parse is fatal.
+pub fn load(root: &str) -> Option<Manifest> {
+    let path = format!("{root}/{FILE}");
+    if !Path::new(&path).exists() {
+        return None;
+    }
+    let raw = fs::read(&path).or_panic(format_args!("read {path}"));
+    let text = std::str::from_utf8(&raw).or_panic(format_args!("{path}: not UTF-8"));
+    let mut manifest = parse(text).or_panic(format_args!("parse {path}"));
+    manifest.digest = hex_encode(&Sha256::digest(&raw));
+    info!("extension manifest {path} sha256={}", manifest.digest);
+    Some(manifest)
+}
+
+fn parse(text: &str) -> Result<Manifest, String> {
+    let mut m = Manifest::default();
+    let mut table = String::new();
+    let mut skip = Skip::default();
+
+    for (n, raw) in text.lines().enumerate() {
+        let n = n + 1;
+        if skip.open() {
+            skip.scan(raw);
+            continue;
+        }
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("[[") {
+            // Arrays of tables only appear in other readers' tables
+            table = header
+                .strip_suffix("]]")
+                .map(str::trim)
+                .filter(|t| valid_key(t) && !TABLES.contains(t))
+                .ok_or_else(|| format!("line {n}: invalid table header"))?
+                .to_owned();
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            table = header
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|t| valid_key(t))
+                .ok_or_else(|| format!("line {n}: invalid table header"))?
+                .to_owned();
+            if let Some(variant) = table.strip_prefix(VARIANTS).filter(|v| !v.contains('.')) {
+                if m.attester_variants.iter().any(|v| v == variant) {
+                    return Err(format!("line {n}: duplicate variant {variant}"));
+                }
+                m.attester_variants.push(variant.to_owned());
+            }
+            continue;
+        }
+        if !TABLES.contains(&table.as_str()) {
+            skip.scan(raw);
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {n}: expected key = value"))?;
+        let key = key.trim();
+        if !valid_key(key) {
+            return Err(format!("line {n}: invalid key {key:?}"));
+        }
+        let value = parse_value(value.trim()).map_err(|e| format!("line {n}: {e}"))?;
+
+        let field = match (table.as_str(), key) {
+            ("firmware", "dirs") => &mut m.firmware_dirs,
+            ("libraries", "dirs") => &mut m.library_dirs,
+            ("modules", "prefixes") => &mut m.module_prefixes,
+            ("binaries", "paths") => &mut m.binaries,
+            ("modules.sha256", module) => {
+                let Value::Str(digest) = value else {
+                    return Err(format!(
+                        "line {n}: modules.sha256.{module} must be a string"
+                    ));
+                };
+                if !is_sha256(&digest) {
+                    return Err(format!("line {n}: modules.sha256.{module}: not a sha256"));
+                }
+                m.module_sha256
+                    .push((module.replace('-', "_"), digest.to_ascii_lowercase()));
+                continue;
+            }
+            ("modules", "dirname") => {
+                let Value::Str(dir) = value else {
+                    return Err(format!("line {n}: modules.dirname must be a string"));
+                };
+                if dir != "." {
+                    check_path(&dir).map_err(|e| format!("line {n}: {e}"))?;
+                }
+                m.module_dirname = Some(dir);
+                continue;
+            }
+            _ => return Err(format!("line {n}: unknown key {table}.{key}")),
+        };
+        let Value::Array(items) = value else {
+            return Err(format!("line {n}: {table}.{key} must be an array"));
+        };
+        if key != "prefixes" {
+            for item in &items {
+                check_path(item).map_err(|e| format!("line {n}: {e}"))?;
+                if table == "firmware" {
+                    check_firmware_dir(item).map_err(|e| format!("line {n}: {e}"))?;
+                }
+            }
+        }
+        field.extend(items);
+    }
+    if skip.open() {
+        return Err("unterminated value at end of file".to_owned());
+    }
+    Ok(m)
+}
+
+/// Tracks a skipped value across lines: open brackets and braces, or the
+/// delimiter of a multi-line string.
+#[derive(Default)]
+struct Skip {
+    depth: usize,
+    string: Option<&'static str>,
+}
+
+impl Skip {
+    fn open(&self) -> bool {
+        self.depth > 0 || self.string.is_some()
+    }
+
+    /// Follow one raw line: strings (basic, literal, multi-line) and comments
+    /// hide brackets, anything else opens or closes them.
+    fn scan(&mut self, line: &str) {
+        let b = line.as_bytes();
+        let mut i = 0;
+        while i < b.len() {
+            // Bytes, not str slices: `i` may sit inside a UTF-8 sequence
+            let rest = &b[i..];
+            if let Some(delim) = self.string {
+                let Some(end) = rest
+                    .windows(delim.len())
+                    .position(|w| w == delim.as_bytes())
+                else {
+                    return;
+                };
+                i += end + delim.len();
+                self.string = None;
+                continue;
+            }
+            if let Some(delim) = ["\"\"\"", "'''"]
+                .into_iter()
+                .find(|d| rest.starts_with(d.as_bytes()))
+            {
+                self.string = Some(delim);
+                i += delim.len();
+                continue;
+            }
+            match b[i] {
+                b'"' => {
+                    i += 1;
+                    while i < b.len() && b[i] != b'"' {
+                        i += if b[i] == b'\\' { 2 } else { 1 };
+                    }
+                }
+                b'\'' => {
+                    i += 1;
+                    while i < b.len() && b[i] != b'\'' {
+                        i += 1;
+                    }
+                }
+                b'#' => return,
+                b'[' | b'{' => self.depth += 1,
+                b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Paths stay inside the extension: relative, no `..`, no `.`.
+fn check_path(path: &str) -> Result<(), String> {
+    let p = Path::new(path);
+    if path.is_empty() || !p.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!(
+            "path {path:?} must be relative and inside the extension"
+        ));
+    }
+    Ok(())
+}
+
+/// Firmware dirs are bound over the same path in the base image, so they
+/// must stay below `lib/firmware` rather than shadow `etc` or `usr/lib`.
+fn check_firmware_dir(dir: &str) -> Result<(), String> {
+    let p = Path::new(dir);
+    if !p.starts_with(FIRMWARE_BASE) || p == Path::new(FIRMWARE_BASE) {
+        return Err(format!(
+            "firmware dir {dir:?} must be below {FIRMWARE_BASE}/"
+        ));
+    }
+    Ok(())
+}
+
+/// 64 hex digits.
+pub fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Bare or dotted keys: `dirs`, `process.variants.nvidia`.
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.split('.').all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        })
+}
+
+/// Drop a trailing `# comment` that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            '#' if !in_str => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    if raw.starts_with('"') {
+        let (s, rest) = parse_string(raw)?;
+        return if rest.trim().is_empty() {
+            Ok(Value::Str(s))
+        } else {
+            Err("trailing characters after string".to_owned())
+        };
+    }
+    if let Some(inner) = raw.strip_prefix('[') {
+        let mut rest = inner
+            .strip_suffix(']')
+            .ok_or("unterminated array")?
+            .trim_start();
+        let mut items = Vec::new();
+        while !rest.is_empty() {
+            let (s, tail) = parse_string(rest)?;
+            items.push(s);
+            let tail = tail.trim_start();
+            rest = match tail.strip_prefix(',') {
+                Some(next) => next.trim_start(),
+                None if tail.is_empty() => tail,
+                None => return Err("expected , between array items".to_owned()),
+            };
+        }
+        return Ok(Value::Array(items));
+    }
+    if raw == "true" || raw == "false" || raw.parse::<i64>().is_ok() {
+        return Ok(Value::Other);
+    }
+    Err(format!("unsupported value {raw:?}"))
+}
+
+/// A basic `"..."` string at the start of `raw`; returns it and the rest.
+fn parse_string(raw: &str) -> Result<(String, &str), String> {
+    let body = raw.strip_prefix('"').ok_or("expected string")?;
+    let mut out = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &body[i + 1..])),
+            '\\' => match chars.next().map(|(_, e)| e) {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                e => return Err(format!("unsupported escape {e:?}")),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    const GPU: &str = r#"
+# GPU extension
+[firmware]
+dirs = ["lib/firmware/nvidia"]
+
+[libraries]
+dirs = ["usr/lib/x86_64-linux-gnu", "usr/lib/nvidia"] # trailing comment
+
+[modules]
+dirname = "."
+prefixes = ["nvidia"]
+
+[binaries]
+paths = [ "bin/nvidia-smi", "bin/nvidia-cdi-hook", ]
+
+[modules.sha256]
+nvidia-uvm = "ABCDEF0123456789abcdef0123456789abcdef0123456789abcdef0123456789"
+
+[process.variants.nvidia]
+command = "/usr/local/bin/attestation-agent"
+args = ["--attester", "nvidia # not a comment"]
+retries = 3
+"#;
+
+    #[test]
+    fn test_parse() {
+        let m = parse(GPU).unwrap();
+        assert_eq!(m.firmware_dirs, ["lib/firmware/nvidia"]);
+        assert_eq!(
+            m.library_dirs,
+            ["usr/lib/x86_64-linux-gnu", "usr/lib/nvidia"]
+        );
+        assert_eq!(m.module_dirname.as_deref(), Some("."));
+        assert_eq!(m.module_prefixes, ["nvidia"]);
+        assert_eq!(
+            m.module_sha256,
+            [(
+                "nvidia_uvm".to_owned(),
+                "abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789".to_owned()
+            )]
+        );
+        assert_eq!(m.binaries, ["bin/nvidia-smi", "bin/nvidia-cdi-hook"]);
+        assert_eq!(m.attester_variants, ["nvidia"]);
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(parse("").unwrap(), Manifest::default());
+        assert_eq!(parse("# nothing\n\n").unwrap(), Manifest::default());
+    }
+
+    #[rstest]
+    #[case::unknown_key("[firmware]\npaths = [\"x\"]", "unknown key firmware.paths")]
+    #[case::absolute("[libraries]\ndirs = [\"/usr/lib\"]", "inside the extension")]
+    #[case::dotdot("[binaries]\npaths = [\"../bin/sh\"]", "inside the extension")]
+    #[case::dirname_escape("[modules]\ndirname = \"..\"", "inside the extension")]
+    #[case::not_array("[firmware]\ndirs = \"lib/firmware\"", "must be an array")]
+    #[case::firmware_etc("[firmware]\ndirs = [\"etc\"]", "below lib/firmware/")]
+    #[case::firmware_usr_lib("[firmware]\ndirs = [\"usr/lib\"]", "below lib/firmware/")]
+    #[case::firmware_base("[firmware]\ndirs = [\"lib/firmware\"]", "below lib/firmware/")]
+    #[case::firmware_lookalike("[firmware]\ndirs = [\"lib/firmwarex\"]", "below lib/firmware/")]
+    #[case::pin_short("[modules.sha256]\nnvidia = \"abc\"", "not a sha256")]
+    #[case::pin_array("[modules.sha256]\nnvidia = [\"abc\"]", "must be a string")]
+    #[case::dirname_array("[modules]\ndirname = [\".\"]", "must be a string")]
+    #[case::inline_table("[firmware]\nenv = { A = \"b\" }", "unsupported value")]
+    #[case::multiline_array("[firmware]\ndirs = [\n\"x\"]", "unterminated array")]
+    #[case::unterminated_string("[firmware]\ndirs = [\"x]", "unterminated string")]
+    #[case::missing_comma("[firmware]\ndirs = [\"a\" \"b\"]", "expected ,")]
+    #[case::bad_header("[firmware", "invalid table header")]
+    #[case::no_equals("[firmware]\ndirs", "expected key = value")]
+    #[case::array_of_owned_tables("[[firmware]]", "invalid table header")]
+    #[case::unterminated_skipped("[process]\nargs = [\n\"a\",", "unterminated value")]
+    #[case::duplicate_variant(
+        "[process.variants.nvidia]\n[process.variants.nvidia]",
+        "duplicate variant"
+    )]
+    fn test_parse_rejects(#[case] text: &str, #[case] err: &str) {
+        let e = parse(text).unwrap_err();
+        assert!(e.contains(err), "{e}");
+    }
+
+    #[test]
+    fn test_parse_string_escapes() {
+        assert_eq!(
+            parse_string(r#""a\"b\\c" rest"#).unwrap(),
+            ("a\"b\\c".to_owned(), " rest")
+        );
+        assert!(parse_string(r#""\x""#).is_err());
+    }
+
+    /// Whatever other readers keep in their tables is valid TOML we skip,
+    /// even where it looks like a header or would not parse here.
+    #[test]
+    fn test_parse_skips_other_tables() {
+        let text = r#"
+[process.variants.nvidia]
+env = { RUST_LOG = "info", PATH = "/bin" }
+args = [
+    "--attester",
+    ["nested"],
+]
+timeout = 1.5
+path = '/opt/aa # not a comment'
+name = "é\"[é"
+script = """
+[firmware]
+dirs = ["etc"]
+"""
+literal = '''
+[binaries]
+'''
+
+[[process.hooks]]
+name = 'check'
+
+[binaries]
+paths = ["bin/nvidia-smi"]
+"#;
+        let m = parse(text).unwrap();
+        assert_eq!(m.attester_variants, ["nvidia"]);
+        assert!(m.firmware_dirs.is_empty());
+        assert_eq!(m.binaries, ["bin/nvidia-smi"]);
+    }
+
+    #[test]
+    fn test_nested_variant_tables_not_variants() {
+        let m = parse("[process.variants.nvidia.env]\n[process.variants]").unwrap();
+        assert!(m.attester_variants.is_empty());
+    }
+
+    #[test]
+    fn test_load() {
+        let tmp = TempDir::new().unwrap();
+        let root = tmp.path().to_str().unwrap();
+        assert_eq!(load(root), None);
+
+        fs::write(tmp.path().join(FILE), GPU).unwrap();
+        let m = load(root).unwrap();
+        assert_eq!(m.digest, hex_encode(&Sha256::digest(GPU.as_bytes())));
+        assert_eq!(m.attester_variants, ["nvidia"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "parse")]
+    fn test_load_invalid_panics() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join(FILE), "[firmware]\ndirs = [\"/lib\"]\n").unwrap();
+        load(tmp.path().to_str().unwrap());
+    }
+}
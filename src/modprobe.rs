@@ -1,45 +1,347 @@
-use std::fs;
+//! In-process kernel module loader.
+//!
+//! Resolves a module and its dependencies from `modules.dep` under the base
+//! image or the extension that provides it, and loads each `.ko` with
+//! `finit_module`. Parameters come from `nvrc.module.<name>.<param>=<value>`
+//! on top of NVRC's own defaults. Modules already in `/sys/module` (loaded or
+//! built in) are skipped.
+//!
+//! Only modules on the allow-list of the detected mode may be inserted,
+//! dependencies included, so a new dependency is caught rather than loaded
+//! silently. A `.ko` pinned with `nvrc.module.<name>.sha256=<hex>` or by its
+//! extension's `[modules.sha256]` must match before it reaches the kernel,
+//! which catches a swapped file even when signing is misconfigured. Before
+//! module loading is disabled, [`verify`] checks `/proc/modules` holds exactly
+//! what was expected.
+//!
+//! `/etc/modprobe.d` is not read: its options, blacklists and softdeps do not
+//! apply, and NVRC warns when the image ships any.
 
-use crate::execute::foreground;
-use crate::gpu_extension;
+use log::{info, warn};
+use nix::errno::Errno;
+use nix::kmod::{finit_module, ModuleInitFlags};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Mutex, Once};
 
-const MODPROBE: &str = "/sbin/modprobe";
+use crate::consumer;
+use crate::gpu_bind;
+use crate::hash::hex_encode;
+use crate::lockdown;
+use crate::manifest;
 
-/// Load a kernel module. Disables NVLink for single-GPU nvidia; NVIDIA modules
-/// come from the `gpu` extension (`--dirname`) when present.
+const OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+const PROC_MODULES: &str = "/proc/modules";
+const SYS_MODULE: &str = "/sys/module";
+const PCI_DEVICES: &str = "/sys/bus/pci/devices";
+const MODPROBE_D: &str = "/etc/modprobe.d";
+/// Let the kernel decompress `.ko.xz`/`.ko.zst`/`.ko.gz` (not in nix yet).
+const MODULE_INIT_COMPRESSED_FILE: libc::c_uint = 4;
+
+/// The cmdline parameter that pins a module instead of being passed to it.
+const PIN: &str = "sha256";
+
+/// Extension payload filesystems ([`crate::guest_extension_image`]), needed
+/// before the mode is known; ext4's dependencies come along. The kernel
+/// autoloads device-mapper and the verity target (Reed-Solomon for FEC) when
+/// the extensions are opened, if they are not built in.
+const BOOT_MODULES: &[&str] = &[
+    "erofs",
+    "squashfs",
+    "ext4",
+    "jbd2",
+    "mbcache",
+    "crc16",
+    "dm_mod",
+    "dm_verity",
+    "dm_bufio",
+    "reed_solomon",
+];
+const NVIDIA_MODULES: &[&str] = &["nvidia", "nvidia_uvm"];
+/// CX7 bridges on NVL5 systems, with mlx5's dependencies in the guest kernel.
+pub const CX7_MODULES: &[&str] = &[
+    "ib_umad",
+    "mlx5_ib",
+    "ib_uverbs",
+    "ib_core",
+    "mlx5_core",
+    "mlxfw",
+    "psample",
+    "tls",
+    "pci_hyperv_intf",
+];
+
+/// `nvrc.module.<name>.<param>=<value>`, recorded once by [`set_params`].
+static PARAMS: OnceCell<Vec<(String, String, String)>> = OnceCell::new();
+
+/// The allow-list of the detected mode, recorded once by [`set_mode`].
+static MODE_MODULES: OnceCell<Vec<&'static str>> = OnceCell::new();
+
+/// Modules [`load`] inserted, for [`verify`].
+static INSERTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// The `/etc/modprobe.d` warning, once per boot.
+static MODPROBE_D_WARNING: Once = Once::new();
+
+/// Record the cmdline module parameters for every later [`load`].
+pub fn set_params(params: Vec<(String, String, String)>) {
+    let _ = PARAMS.set(params);
+}
+
+/// Modules each mode may insert on top of [`BOOT_MODULES`]. HGX systems with
+/// NVL5 switches run GPU mode with the CX7 bridges as well.
+fn mode_modules(mode: &str, nvswitch: Option<&str>) -> Vec<&'static str> {
+    let mut modules = match mode {
+        "gpu" => NVIDIA_MODULES.to_vec(),
+        "servicevm-nvl4" => vec!["nvidia"],
+        "servicevm-nvl5" => CX7_MODULES.to_vec(),
+        _ => Vec::new(),
+    };
+    if mode == "gpu" && nvswitch == Some("nvl5") {
+        modules.extend(CX7_MODULES);
+    }
+    modules
+}
+
+/// Record the detected mode and any `extra` modules the cmdline enabled on
+/// top of it; until then only [`BOOT_MODULES`] may load.
+pub fn set_mode(mode: &str, nvswitch: Option<&str>, extra: &[&'static str]) {
+    let mut modules = mode_modules(mode, nvswitch);
+    modules.extend(extra);
+    let _ = MODE_MODULES.set(modules);
+}
+
+/// Module names treat `-` and `_` alike; the kernel uses `_`.
+pub fn normalize(module: &str) -> String {
+    module.replace('-', "_")
+}
+
+/// Validate the `<name>.<param>` of an `nvrc.module.<name>.<param>=<value>`.
+pub fn check_param(key: &str, value: &str) -> Result<(String, String), String> {
+    let (module, param) = key
+        .split_once('.')
+        .filter(|(m, p)| !m.is_empty() && !p.is_empty())
+        .ok_or_else(|| format!("nvrc.module.{key}: expected <module>.<param>"))?;
+    if !param.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("nvrc.module.{key}: invalid parameter name"));
+    }
+    if value.contains('"') {
+        return Err(format!("nvrc.module.{key}: quotes are not allowed"));
+    }
+    if param == PIN && !manifest::is_sha256(value) {
+        return Err(format!("nvrc.module.{key}: not a sha256"));
+    }
+    Ok((normalize(module), param.to_owned()))
+}
+
+/// Per-module decisions [`load_in`] defers to.
+trait Rules {
+    /// The kernel's `param=value ...` string for `name`.
+    fn options(&self, name: &str) -> String;
+    fn allowed(&self, name: &str) -> bool;
+    /// Digests the `.ko` of `name` must match; usually none.
+    fn pins(&self, name: &str) -> Vec<String>;
+}
+
+/// The cmdline, the detected mode and the extension manifests.
+struct Boot;
+
+impl Rules for Boot {
+    fn options(&self, name: &str) -> String {
+        let single_gpu = name == "nvidia" && count_nvidia_gpus_from(PCI_DEVICES) == 1;
+        options(name, single_gpu, PARAMS.get().map_or(&[], Vec::as_slice))
+    }
+
+    fn allowed(&self, name: &str) -> bool {
+        BOOT_MODULES.contains(&name) || MODE_MODULES.get().is_some_and(|m| m.contains(&name))
+    }
+
+    fn pins(&self, name: &str) -> Vec<String> {
+        let cmdline = PARAMS
+            .get()
+            .into_iter()
+            .flatten()
+            .filter(|(m, p, _)| m == name && p == PIN)
+            .map(|(_, _, digest)| digest.clone());
+        cmdline.chain(consumer::module_sha256(name)).collect()
+    }
+}
+
+/// Load a kernel module and its dependencies. Disables NVLink for single-GPU
+/// nvidia; modules an extension declares come from that extension.
 pub fn load(module: &str) {
-    let single_gpu = module == "nvidia" && count_nvidia_gpus_from("/sys/bus/pci/devices") == 1;
-    let dirname = gpu_extension::modprobe_dirname(module);
-    let args = build_args(module, dirname.as_deref(), single_gpu);
-    let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
-    foreground(MODPROBE, &arg_refs);
+    MODPROBE_D_WARNING.call_once(|| {
+        let ignored = modprobe_conf(Path::new(MODPROBE_D));
+        if !ignored.is_empty() {
+            warn!("{MODPROBE_D} is ignored: {}", ignored.join(" "));
+        }
+    });
+    let root = consumer::modprobe_dirname(module).unwrap_or_else(|| "/".to_owned());
+    let release = fs::read_to_string(OSRELEASE).unwrap_or_else(|e| panic!("read {OSRELEASE}: {e}"));
+    let dir = Path::new(&root).join("lib/modules").join(release.trim());
+    let inserted = load_in(Path::new(SYS_MODULE), &dir, module, &Boot);
+    INSERTED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .extend(inserted);
+}
+
+/// The `*.conf` files under `dir`, sorted, which `/sbin/modprobe` would apply.
+fn modprobe_conf(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".conf"))
+        .collect();
+    files.sort();
+    files
 }
 
-fn build_args(module: &str, dirname: Option<&str>, single_gpu: bool) -> Vec<String> {
-    let mut args = Vec::new();
-    if let Some(dir) = dirname {
-        args.push("--dirname".to_owned());
-        args.push(dir.to_owned());
+/// Returns the modules actually inserted.
+fn load_in(sys_module: &Path, dir: &Path, module: &str, rules: &dyn Rules) -> Vec<String> {
+    let name = normalize(module);
+    if sys_module.join(&name).exists() {
+        info!("{name} already loaded");
+        return Vec::new();
     }
-    args.push(module.to_owned());
+    let dep_path = dir.join("modules.dep");
+    let deps = fs::read_to_string(&dep_path).unwrap_or_default();
+    let Some(chain) = dependencies(&deps, &name) else {
+        let builtin = fs::read_to_string(dir.join("modules.builtin")).unwrap_or_default();
+        if builtin.lines().any(|l| module_name(l) == name) {
+            info!("{name} is built in");
+            return Vec::new();
+        }
+        panic!("module {module} not found in {}", dep_path.display());
+    };
+    let mut inserted = Vec::new();
+    for path in chain {
+        let dep = module_name(&path);
+        if sys_module.join(&dep).exists() {
+            continue;
+        }
+        if !rules.allowed(&dep) {
+            lockdown::policy_violation(format_args!("module {dep} is not on the allow-list"));
+        }
+        insert(&dir.join(&path), &rules.options(&dep), &rules.pins(&dep));
+        inserted.push(dep);
+    }
+    inserted
+}
+
+/// `kernel/drivers/block/loop.ko.zst` -> `loop`
+fn module_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    normalize(file.split_once(".ko").map_or(file, |(name, _)| name))
+}
+
+/// The `.ko` paths to insert for `name`, dependencies first (modules.dep
+/// lists them nearest first, so they load in reverse), `name` last.
+fn dependencies(modules_dep: &str, name: &str) -> Option<Vec<String>> {
+    modules_dep.lines().find_map(|line| {
+        let (path, deps) = line.split_once(':')?;
+        (module_name(path) == name).then(|| {
+            let mut chain: Vec<String> = deps.split_whitespace().rev().map(str::to_owned).collect();
+            chain.push(path.trim().to_owned());
+            chain
+        })
+    })
+}
+
+/// NVRC's defaults for `name`, then the cmdline's (which replace a default
+/// of the same parameter), as the kernel's `param=value ...` string.
+fn options(name: &str, single_gpu: bool, overrides: &[(String, String, String)]) -> String {
+    let mut params: Vec<(&str, &str)> = Vec::new();
     if single_gpu {
-        args.push("NVreg_NvLinkDisable=1".to_owned());
+        params.push(("NVreg_NvLinkDisable", "1"));
+    }
+    // Graphics needs a DRM device and a framebuffer console on top of KMS
+    if name == "nvidia_drm" {
+        params.extend([("modeset", "1"), ("fbdev", "1")]);
     }
-    args
+    for (_, param, value) in overrides.iter().filter(|(m, p, _)| m == name && p != PIN) {
+        match params.iter_mut().find(|(p, _)| p == param) {
+            Some(existing) => existing.1 = value,
+            None => params.push((param, value)),
+        }
+    }
+    params
+        .iter()
+        .map(|(p, v)| format!("{p}={v}"))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-fn count_nvidia_gpus_from(pci_path: &str) -> usize {
-    let Ok(entries) = fs::read_dir(pci_path) else {
-        return 0;
+/// Insert one `.ko`. Pins are checked on the very fd handed to the kernel.
+fn insert(ko: &Path, options: &str, pins: &[String]) {
+    let mut file = File::open(ko).unwrap_or_else(|e| panic!("open {}: {e}", ko.display()));
+    if !pins.is_empty() {
+        let mut image = Vec::new();
+        file.read_to_end(&mut image)
+            .unwrap_or_else(|e| panic!("read {}: {e}", ko.display()));
+        let digest = hex_encode(&Sha256::digest(&image));
+        if let Some(pin) = pins.iter().find(|pin| !pin.eq_ignore_ascii_case(&digest)) {
+            panic!("{}: sha256 {digest} does not match pin {pin}", ko.display());
+        }
+    }
+    let compressed = ko.extension().is_some_and(|ext| ext != "ko");
+    let flags = match compressed {
+        true => ModuleInitFlags::from_bits_retain(MODULE_INIT_COMPRESSED_FILE),
+        false => ModuleInitFlags::empty(),
     };
-    entries
-        .flatten()
-        .filter(|e| {
-            let vendor = fs::read_to_string(e.path().join("vendor")).unwrap_or_default();
-            let class = fs::read_to_string(e.path().join("class")).unwrap_or_default();
-            vendor.trim() == "0x10de" && class.trim().starts_with("0x03")
+    let params = CString::new(options).unwrap_or_else(|e| panic!("{}: {e}", ko.display()));
+    match finit_module(&file, &params, flags) {
+        // Lost a race with udev or another loader
+        Ok(()) | Err(Errno::EEXIST) => info!("loaded {} {options}", ko.display()),
+        Err(e) => panic!("finit_module {}: {e}", ko.display()),
+    }
+}
+
+/// Check `/proc/modules` against what NVRC inserted and the allow-list.
+pub fn verify() {
+    let proc_modules =
+        fs::read_to_string(PROC_MODULES).unwrap_or_else(|e| panic!("read {PROC_MODULES}: {e}"));
+    let inserted = INSERTED.lock().unwrap_or_else(|e| e.into_inner());
+    for violation in violations(&proc_modules, &inserted, &Boot) {
+        lockdown::policy_violation(format_args!("modules: {violation}"));
+    }
+    info!("modules: verified {} inserted", inserted.len());
+}
+
+/// `name size refcount deps state address` per line.
+fn violations(proc_modules: &str, inserted: &[String], rules: &dyn Rules) -> Vec<String> {
+    let loaded: Vec<(&str, &str)> = proc_modules
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            Some((*fields.first()?, *fields.get(4)?))
         })
-        .count()
+        .collect();
+    let mut violations = Vec::new();
+    for (name, state) in &loaded {
+        if !rules.allowed(name) {
+            violations.push(format!("{name} is loaded but not on the allow-list"));
+        } else if *state != "Live" {
+            violations.push(format!("{name} is {state}"));
+        }
+    }
+    for name in inserted {
+        if !loaded.iter().any(|(n, _)| n == name) {
+            violations.push(format!("{name} was inserted but is gone"));
+        }
+    }
+    violations
+}
+
+fn count_nvidia_gpus_from(pci_path: &str) -> usize {
+    gpu_bind::gpus(Path::new(pci_path)).len()
 }
 
 #[cfg(test)]
@@ -78,42 +380,368 @@ mod tests {
         assert!(result.is_err());
     }
 
-    // === build_args ===
+    // === module resolution ===
+
+    const MODULES_DEP: &str = "\
+kernel/drivers/video/nvidia.ko: kernel/drivers/gpu/drm/drm.ko kernel/lib/zlib.ko.zst
+kernel/drivers/video/nvidia-uvm.ko: kernel/drivers/video/nvidia.ko kernel/drivers/gpu/drm/drm.ko
+kernel/drivers/gpu/drm/drm.ko:
+kernel/lib/zlib.ko.zst:
+";
 
     #[test]
-    fn test_build_args_plain() {
-        assert_eq!(build_args("erofs", None, false), vec!["erofs"]);
+    fn test_module_name() {
+        assert_eq!(module_name("kernel/drivers/block/loop.ko"), "loop");
+        assert_eq!(
+            module_name("kernel/drivers/video/nvidia-uvm.ko.xz"),
+            "nvidia_uvm"
+        );
+        assert_eq!(module_name("ext4.ko"), "ext4");
+        assert_eq!(module_name("ext4"), "ext4");
     }
 
     #[test]
-    fn test_build_args_single_gpu() {
+    fn test_dependencies() {
+        assert_eq!(
+            dependencies(MODULES_DEP, "nvidia_uvm").unwrap(),
+            [
+                "kernel/drivers/gpu/drm/drm.ko",
+                "kernel/drivers/video/nvidia.ko",
+                "kernel/drivers/video/nvidia-uvm.ko",
+            ]
+        );
         assert_eq!(
-            build_args("nvidia", None, true),
-            vec!["nvidia", "NVreg_NvLinkDisable=1"]
+            dependencies(MODULES_DEP, "drm").unwrap(),
+            ["kernel/drivers/gpu/drm/drm.ko"]
         );
+        assert_eq!(dependencies(MODULES_DEP, "nvidia_drm"), None);
+        assert_eq!(dependencies("", "loop"), None);
     }
 
     #[test]
-    fn test_build_args_extension_dirname() {
+    fn test_check_param() {
         assert_eq!(
-            build_args("nvidia", Some(gpu_extension::ROOT), false),
-            vec!["--dirname", gpu_extension::ROOT, "nvidia"]
+            check_param("nvidia-uvm.uvm_perf_prefetch_enable", "1").unwrap(),
+            (
+                "nvidia_uvm".to_owned(),
+                "uvm_perf_prefetch_enable".to_owned()
+            )
         );
+        assert!(check_param("nvidia", "1")
+            .unwrap_err()
+            .contains("<module>.<param>"));
+        assert!(check_param(".p", "1").is_err());
+        assert!(check_param("nvidia.a=b", "1")
+            .unwrap_err()
+            .contains("invalid parameter"));
+        assert!(check_param("nvidia.p", "\"x")
+            .unwrap_err()
+            .contains("quotes"));
+    }
+
+    // === options ===
+
+    fn overrides(list: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        list.iter()
+            .map(|(m, p, v)| (m.to_string(), p.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_options_plain() {
+        assert_eq!(options("erofs", false, &[]), "");
+    }
+
+    #[test]
+    fn test_options_single_gpu() {
+        assert_eq!(options("nvidia", true, &[]), "NVreg_NvLinkDisable=1");
     }
 
     #[test]
-    fn test_build_args_extension_dirname_single_gpu() {
+    fn test_options_cmdline() {
+        let cmdline = overrides(&[
+            ("nvidia", "NVreg_EnableGpuFirmware", "0"),
+            ("nvidia_uvm", "uvm_perf_prefetch_enable", "1"),
+            ("nvidia", "NVreg_NvLinkDisable", "0"),
+        ]);
+        // The cmdline replaces the single-GPU default in place
         assert_eq!(
-            build_args("nvidia", Some(gpu_extension::ROOT), true),
-            vec![
-                "--dirname",
-                gpu_extension::ROOT,
-                "nvidia",
-                "NVreg_NvLinkDisable=1"
+            options("nvidia", true, &cmdline),
+            "NVreg_NvLinkDisable=0 NVreg_EnableGpuFirmware=0"
+        );
+        assert_eq!(
+            options("nvidia_uvm", false, &cmdline),
+            "uvm_perf_prefetch_enable=1"
+        );
+        assert_eq!(options("drm", false, &cmdline), "");
+    }
+
+    #[test]
+    fn test_options_nvidia_drm() {
+        assert_eq!(options("nvidia_drm", false, &[]), "modeset=1 fbdev=1");
+        let cmdline = overrides(&[("nvidia_drm", "fbdev", "0")]);
+        assert_eq!(options("nvidia_drm", false, &cmdline), "modeset=1 fbdev=0");
+    }
+
+    // === allow-list and pins ===
+
+    #[test]
+    fn test_mode_modules() {
+        assert!(mode_modules("cpu", None).is_empty());
+        assert_eq!(mode_modules("gpu", None), ["nvidia", "nvidia_uvm"]);
+        assert_eq!(mode_modules("gpu", Some("nvl4")), ["nvidia", "nvidia_uvm"]);
+        assert!(mode_modules("gpu", Some("nvl5")).contains(&"mlx5_ib"));
+        assert_eq!(mode_modules("servicevm-nvl4", Some("nvl4")), ["nvidia"]);
+        assert!(!mode_modules("servicevm-nvl5", Some("nvl5")).contains(&"nvidia"));
+    }
+
+    #[test]
+    fn test_check_param_pin() {
+        let pin = "AB".repeat(32);
+        assert_eq!(
+            check_param("nvidia.sha256", &pin).unwrap(),
+            ("nvidia".to_owned(), PIN.to_owned())
+        );
+        assert!(check_param("nvidia.sha256", "abc")
+            .unwrap_err()
+            .contains("not a sha256"));
+    }
+
+    #[test]
+    fn test_options_skip_pins() {
+        let cmdline = overrides(&[("nvidia", PIN, &"ab".repeat(32))]);
+        assert_eq!(options("nvidia", false, &cmdline), "");
+    }
+
+    // === load_in ===
+
+    #[derive(Default)]
+    struct TestRules {
+        /// `None` allows everything.
+        allowed: Option<Vec<&'static str>>,
+        pins: Vec<(&'static str, String)>,
+    }
+
+    impl Rules for TestRules {
+        fn options(&self, _: &str) -> String {
+            String::new()
+        }
+
+        fn allowed(&self, name: &str) -> bool {
+            self.allowed.as_ref().is_none_or(|a| a.contains(&name))
+        }
+
+        fn pins(&self, name: &str) -> Vec<String> {
+            self.pins
+                .iter()
+                .filter(|(n, _)| *n == name)
+                .map(|(_, pin)| pin.clone())
+                .collect()
+        }
+    }
+
+    fn panic_message(result: std::thread::Result<Vec<String>>) -> String {
+        let err = result.unwrap_err();
+        err.downcast_ref::<String>().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_load_in_already_loaded() {
+        // Loaded modules are never opened, let alone inserted
+        let sys_module = TempDir::new().unwrap();
+        fs::create_dir(sys_module.path().join("nvidia_uvm")).unwrap();
+        let inserted = load_in(
+            sys_module.path(),
+            Path::new("/nonexistent"),
+            "nvidia-uvm",
+            &TestRules::default(),
+        );
+        assert!(inserted.is_empty());
+    }
+
+    #[test]
+    fn test_load_in_builtin() {
+        let sys_module = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("modules.builtin"),
+            "kernel/fs/ext4/ext4.ko\n",
+        )
+        .unwrap();
+        load_in(sys_module.path(), dir.path(), "ext4", &TestRules::default());
+    }
+
+    #[test]
+    fn test_load_in_not_found() {
+        let sys_module = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("modules.dep"), MODULES_DEP).unwrap();
+        let result = panic::catch_unwind(|| {
+            load_in(
+                sys_module.path(),
+                dir.path(),
+                "nvidia_drm",
+                &TestRules::default(),
+            )
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_load_in_skips_loaded_dependencies() {
+        // Everything but nvidia-uvm itself is loaded; its .ko is missing, so
+        // reaching insert() for it panics, and nothing else is touched.
+        let sys_module = TempDir::new().unwrap();
+        for dep in ["drm", "nvidia"] {
+            fs::create_dir(sys_module.path().join(dep)).unwrap();
+        }
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("modules.dep"), MODULES_DEP).unwrap();
+        let result = panic::catch_unwind(|| {
+            load_in(
+                sys_module.path(),
+                dir.path(),
+                "nvidia-uvm",
+                &TestRules::default(),
+            )
+        });
+        let msg = panic_message(result);
+        assert!(msg.contains("nvidia-uvm.ko"), "{msg}");
+    }
+
+    #[test]
+    fn test_load_in_dependency_not_allowed() {
+        let sys_module = TempDir::new().unwrap();
+        fs::create_dir(sys_module.path().join("nvidia")).unwrap();
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("modules.dep"), MODULES_DEP).unwrap();
+        let rules = TestRules {
+            allowed: Some(vec!["nvidia", "nvidia_uvm"]),
+            ..TestRules::default()
+        };
+        let result =
+            panic::catch_unwind(|| load_in(sys_module.path(), dir.path(), "nvidia-uvm", &rules));
+        let msg = panic_message(result);
+        if cfg!(feature = "confidential") {
+            // drm crept in as a dependency: refused before it is opened
+            assert!(msg.contains("drm is not on the allow-list"), "{msg}");
+        } else {
+            // Only warned; the missing drm.ko is what stops it here
+            assert!(msg.contains("drm.ko"), "{msg}");
+        }
+    }
+
+    #[test]
+    fn test_insert_pin_mismatch() {
+        let dir = TempDir::new().unwrap();
+        let ko = dir.path().join("swapped.ko");
+        fs::write(&ko, b"not the module that was pinned").unwrap();
+        let result = panic::catch_unwind(|| insert(&ko, "", &["00".repeat(32)]));
+        let err = result.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("does not match pin"), "{msg}");
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_insert_pin_match_reaches_kernel() {
+        require_root();
+        let dir = TempDir::new().unwrap();
+        let ko = dir.path().join("bogus.ko");
+        fs::write(&ko, b"not an ELF").unwrap();
+        let pin = hex_encode(&Sha256::digest(b"not an ELF"));
+        // Pin matches (in any case), so the kernel gets to reject it
+        let result = panic::catch_unwind(|| insert(&ko, "", &[pin.to_uppercase()]));
+        let err = result.unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("finit_module"), "{msg}");
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_insert_rejects_non_module() {
+        require_root();
+        let dir = TempDir::new().unwrap();
+        let ko = dir.path().join("bogus.ko");
+        fs::write(&ko, b"not an ELF").unwrap();
+        let result = panic::catch_unwind(|| insert(&ko, "", &[]));
+        assert!(result.is_err());
+    }
+
+    // === verify ===
+
+    const PROC_MODULES_GPU: &str = "\
+nvidia_uvm 1589248 0 - Live 0x0000000000000000 (O)
+nvidia 54071296 1 nvidia_uvm, Live 0x0000000000000000 (PO)
+";
+
+    #[test]
+    fn test_violations_expected_set() {
+        let rules = TestRules {
+            allowed: Some(vec!["nvidia", "nvidia_uvm"]),
+            ..TestRules::default()
+        };
+        let inserted = ["nvidia".to_owned(), "nvidia_uvm".to_owned()];
+        assert!(violations(PROC_MODULES_GPU, &inserted, &rules).is_empty());
+        assert!(violations("", &[], &rules).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let rules = TestRules {
+            allowed: Some(vec!["nvidia", "nvidia_uvm"]),
+            ..TestRules::default()
+        };
+        let proc_modules = "\
+nvidia 54071296 0 - Loading 0x0000000000000000 (PO)
+evil 16384 0 - Live 0x0000000000000000 (OE)
+";
+        let inserted = ["nvidia".to_owned(), "nvidia_uvm".to_owned()];
+        assert_eq!(
+            violations(proc_modules, &inserted, &rules),
+            [
+                "nvidia is Loading",
+                "evil is loaded but not on the allow-list",
+                "nvidia_uvm was inserted but is gone",
             ]
         );
     }
 
+    #[test]
+    fn test_modprobe_conf() {
+        let tmp = TempDir::new().unwrap();
+        assert!(modprobe_conf(&tmp.path().join("missing")).is_empty());
+        fs::write(tmp.path().join("nvidia.conf"), "options nvidia NVreg_X=1\n").unwrap();
+        fs::write(
+            tmp.path().join("blacklist-nouveau.conf"),
+            "blacklist nouveau\n",
+        )
+        .unwrap();
+        fs::write(tmp.path().join("README"), "").unwrap();
+        assert_eq!(
+            modprobe_conf(tmp.path()),
+            ["blacklist-nouveau.conf", "nvidia.conf"]
+        );
+    }
+
+    /// Modular dm-verity, autoloaded for the extensions before any mode.
+    #[test]
+    fn test_violations_extension_dm() {
+        let proc_modules = "\
+dm_verity 57344 1 - Live 0x0000000000000000
+reed_solomon 24576 1 dm_verity, Live 0x0000000000000000
+dm_bufio 49152 1 dm_verity, Live 0x0000000000000000
+dm_mod 184320 3 dm_verity,dm_bufio, Live 0x0000000000000000
+";
+        assert!(violations(proc_modules, &[], &Boot).is_empty());
+    }
+
     fn create_pci_device(tmpdir: &TempDir, name: &str, vendor: &str, class: &str) {
         let dev = tmpdir.path().join(name);
         fs::create_dir_all(&dev).unwrap();
@@ -2,12 +2,179 @@
 // Copyright (c) NVIDIA CORPORATION
 
 //! Filesystem setup for the minimal init environment.
-
+//!
+//! Everything NVRC mounts is declared in [`MOUNTS`]. Boot entries are mounted
+//! by [`setup`] before the cmdline is read; [`setup_mode`] adds the entries of
+//! the detected mode and those enabled with `nvrc.mount.<name>=<data>`, where
+//! `<data>` (possibly empty) replaces the entry's mount data.
+//!
+//! The root is remounted read-only, and before kata-agent starts [`audit`]
+//! checks every mount against the flag policy: nothing writable is executable
+//! unless listed in [`WRITABLE_EXEC`], and every tmpfs is nodev,nosuid.
+
+use crate::lockdown;
 use crate::macros::ResultExt;
 use nix::mount::MsFlags;
 use std::fs;
 use std::path::Path;
 
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Mounts that may be both writable and executable: the kernel's devtmpfs,
+/// and `/tmp`, which tools unpack and run helpers from.
+const WRITABLE_EXEC: &[&str] = &["/dev", "/tmp"];
+
+const COMMON: MsFlags = MsFlags::MS_NOSUID
+    .union(MsFlags::MS_NOEXEC)
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_RELATIME);
+
+/// When a [`MOUNTS`] entry is mounted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum When {
+    /// By [`setup`], before the cmdline is read.
+    Boot,
+    /// By [`setup_mode`] in the listed modes, or when enabled on the cmdline.
+    Modes(&'static [&'static str]),
+    /// By [`setup_mode`], only when enabled on the cmdline.
+    Cmdline,
+}
+
+/// One declared mount; `name` is what `nvrc.mount.<name>` refers to.
+#[derive(Debug)]
+struct Entry {
+    name: &'static str,
+    source: &'static str,
+    target: &'static str,
+    fstype: &'static str,
+    flags: MsFlags,
+    data: Option<&'static str>,
+    /// Required mounts panic on failure. Optional ones are skipped when the
+    /// kernel lacks the fstype or the target does not exist.
+    required: bool,
+    /// Create the target first; devtmpfs starts out without these dirs.
+    mkdir: bool,
+    when: When,
+}
+
+const fn entry(
+    name: &'static str,
+    source: &'static str,
+    target: &'static str,
+    fstype: &'static str,
+    flags: MsFlags,
+    data: Option<&'static str>,
+) -> Entry {
+    Entry {
+        name,
+        source,
+        target,
+        fstype,
+        flags,
+        data,
+        required: true,
+        mkdir: false,
+        when: When::Boot,
+    }
+}
+
+impl Entry {
+    const fn optional(self) -> Self {
+        Entry {
+            required: false,
+            ..self
+        }
+    }
+
+    const fn mkdir(self) -> Self {
+        Entry {
+            mkdir: true,
+            ..self
+        }
+    }
+
+    const fn when(self, when: When) -> Self {
+        Entry { when, ..self }
+    }
+}
+
+/// Required entries come first: optional ones consult /proc/filesystems.
+static MOUNTS: &[Entry] = &[
+    entry("proc", "proc", "/proc", "proc", COMMON, None),
+    entry("sysfs", "sysfs", "/sys", "sysfs", COMMON, None),
+    entry("run", "run", "/run", "tmpfs", COMMON, Some("mode=0755")),
+    // Not noexec: tools unpack and run helpers from /tmp.
+    entry(
+        "tmp",
+        "tmpfs",
+        "/tmp",
+        "tmpfs",
+        MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NODEV)
+            .union(MsFlags::MS_RELATIME),
+        None,
+    ),
+    // NCCL's intra-node transport lives in shared memory.
+    entry("shm", "shm", "/dev/shm", "tmpfs", COMMON, Some("mode=1777"))
+        .mkdir()
+        .when(When::Modes(&["gpu"])),
+    entry(
+        "securityfs",
+        "securityfs",
+        "/sys/kernel/security",
+        "securityfs",
+        COMMON,
+        None,
+    )
+    .optional(),
+    entry(
+        "configfs",
+        "configfs",
+        "/sys/kernel/config",
+        "configfs",
+        COMMON,
+        None,
+    )
+    .optional(),
+    entry(
+        "cgroup2",
+        "cgroup2",
+        "/sys/fs/cgroup",
+        "cgroup2",
+        COMMON,
+        None,
+    )
+    .optional(),
+    entry(
+        "devpts",
+        "devpts",
+        "/dev/pts",
+        "devpts",
+        MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NOEXEC)
+            .union(MsFlags::MS_RELATIME),
+        Some("newinstance,ptmxmode=0666,mode=0620"),
+    )
+    .optional()
+    .mkdir(),
+    entry("mqueue", "mqueue", "/dev/mqueue", "mqueue", COMMON, None)
+        .optional()
+        .mkdir(),
+    // Pages must be reserved for it to be useful, so only on request,
+    // e.g. nvrc.mount.hugetlbfs=pagesize=1G.
+    entry(
+        "hugetlbfs",
+        "hugetlbfs",
+        "/dev/hugepages",
+        "hugetlbfs",
+        COMMON,
+        None,
+    )
+    .optional()
+    .mkdir()
+    .when(When::Cmdline),
+];
+
 /// Mount a filesystem. Errors if mount fails.
 fn mount(source: &str, target: &str, fstype: &str, flags: MsFlags, data: Option<&str>) {
     nix::mount::mount(Some(source), target, Some(fstype), flags, data)
@@ -26,55 +193,208 @@ pub fn fs_available(filesystems: &str, fstype: &str) -> bool {
 
 /// Mount optional filesystem if the fstype is available AND the target exists.
 /// Used for securityfs and efivarfs that may not be present on all kernels.
-fn mount_optional(filesystems: &str, source: &str, target: &str, fstype: &str, flags: MsFlags) {
+fn mount_optional(
+    filesystems: &str,
+    source: &str,
+    target: &str,
+    fstype: &str,
+    flags: MsFlags,
+    data: Option<&str>,
+) {
     if fs_available(filesystems, fstype) && Path::new(target).exists() {
-        mount(source, target, fstype, flags, None);
+        mount(source, target, fstype, flags, data);
     }
 }
 
-/// Set up the minimal filesystem hierarchy required for GPU initialization.
-/// Creates /proc, /sys, /run, /tmp mounts.
+/// Validate an `nvrc.mount.<name>=<data>` enable. Boot entries are already
+/// mounted by the time the cmdline is read, so they cannot be changed.
+pub fn check_enable(name: &str) -> Result<(), String> {
+    let entry = MOUNTS
+        .iter()
+        .find(|e| e.name == name)
+        .ok_or_else(|| format!("nvrc.mount: unknown mount {name}"))?;
+    if entry.when == When::Boot {
+        return Err(format!(
+            "nvrc.mount.{name}: mounted before the cmdline is read"
+        ));
+    }
+    Ok(())
+}
+
+/// The non-boot entries for `mode`, with cmdline data (last one wins)
+/// replacing the declared data.
+fn selected<'a>(
+    mode: &str,
+    enables: &'a [(String, String)],
+) -> Vec<(&'static Entry, Option<&'a str>)> {
+    MOUNTS
+        .iter()
+        .filter_map(|entry| {
+            let enabled = enables.iter().rev().find(|(k, _)| k == entry.name);
+            let wanted = match entry.when {
+                When::Boot => false,
+                When::Modes(modes) => modes.contains(&mode) || enabled.is_some(),
+                When::Cmdline => enabled.is_some(),
+            };
+            let data = enabled.map_or(entry.data, |(_, v)| (!v.is_empty()).then_some(v.as_str()));
+            wanted.then_some((entry, data))
+        })
+        .collect()
+}
+
+fn mount_entries<'a>(root: &str, entries: impl IntoIterator<Item = (&'a Entry, Option<&'a str>)>) {
+    // Read on first use: proc itself is a required entry.
+    let mut filesystems = None;
+    for (entry, data) in entries {
+        let target = format!("{root}{}", entry.target);
+        if entry.required {
+            if entry.mkdir {
+                fs::create_dir_all(&target).or_panic(format_args!("create {target}"));
+            }
+            mount(entry.source, &target, entry.fstype, entry.flags, data);
+            continue;
+        }
+        let filesystems = filesystems
+            .get_or_insert_with(|| fs::read_to_string("/proc/filesystems").unwrap_or_default());
+        if entry.mkdir && fs_available(filesystems, entry.fstype) {
+            fs::create_dir_all(&target).or_panic(format_args!("create {target}"));
+        }
+        mount_optional(
+            filesystems,
+            entry.source,
+            &target,
+            entry.fstype,
+            entry.flags,
+            data,
+        );
+    }
+}
+
+/// Mount the entries of the detected `mode` and those enabled on the cmdline.
+pub fn setup_mode(mode: &str, enables: &[(String, String)]) {
+    setup_mode_at("", mode, enables)
+}
+
+fn setup_mode_at(root: &str, mode: &str, enables: &[(String, String)]) {
+    mount_entries(root, selected(mode, enables));
+}
+
+/// Set up the minimal filesystem hierarchy required for GPU initialization:
+/// the boot entries of [`MOUNTS`], then the root is remounted read-only.
 /// The kernel mounts devtmpfs on /dev before init runs; symlinks
 /// (/dev/stdin, /dev/stdout, /dev/stderr, /dev/fd, /dev/core) are
 /// created later by kata-agent.
 pub fn setup() {
-    setup_at("")
+    setup_at("");
+    remount_root();
 }
 
-/// Internal: setup with configurable root path (for testing with temp directories).
-fn setup_at(root: &str) {
-    let common = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV | MsFlags::MS_RELATIME;
+/// Nothing in the base image is written at run time; state lives on `/run`
+/// and `/tmp`. A root the kernel will not remount is a policy violation, not
+/// a reason to stop (the audit reports it again).
+fn remount_root() {
+    let flags = MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_NOSUID;
+    match nix::mount::mount(None::<&str>, "/", None::<&str>, flags, None::<&str>) {
+        Ok(()) => info!("remounted / read-only"),
+        Err(e) => lockdown::policy_violation(format_args!("remount / read-only: {e}")),
+    }
+}
 
-    mount("proc", &format!("{root}/proc"), "proc", common, None);
-    mount("sysfs", &format!("{root}/sys"), "sysfs", common, None);
-    mount(
-        "run",
-        &format!("{root}/run"),
-        "tmpfs",
-        common,
-        Some("mode=0755"),
-    );
+/// One `/proc/self/mountinfo` entry, as far as the policy cares.
+#[derive(Debug, PartialEq)]
+struct MountEntry {
+    target: String,
+    fstype: String,
+    /// Per-mount options (`ro`, `nosuid`, ...).
+    options: Vec<String>,
+    /// Superblock options; an erofs or a verity device is `ro` here even
+    /// when a bind of it is not.
+    super_options: Vec<String>,
+}
 
-    let tmp_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME;
-    mount("tmpfs", &format!("{root}/tmp"), "tmpfs", tmp_flags, None);
+impl MountEntry {
+    fn has(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
 
-    // Read once for all optional mounts
-    let filesystems = fs::read_to_string("/proc/filesystems").unwrap_or_default();
+    fn writable(&self) -> bool {
+        !self.has("ro") && !self.super_options.iter().any(|o| o == "ro")
+    }
+}
 
-    mount_optional(
-        &filesystems,
-        "securityfs",
-        &format!("{root}/sys/kernel/security"),
-        "securityfs",
-        common,
-    );
-    mount_optional(
-        &filesystems,
-        "configfs",
-        &format!("{root}/sys/kernel/config"),
-        "configfs",
-        common,
-    );
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_mountinfo(mountinfo: &str) -> Vec<MountEntry> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let fs: Vec<&str> = fs.split(' ').collect();
+            let split = |opts: &str| opts.split(',').map(str::to_owned).collect();
+            Some(MountEntry {
+                target: unescape(mount.get(4)?),
+                fstype: fs.first()?.to_string(),
+                options: split(mount.get(5)?),
+                super_options: split(fs.get(2)?),
+            })
+        })
+        .collect()
+}
+
+/// mountinfo escapes space, tab, newline and backslash as `\ooo`.
+fn unescape(field: &str) -> String {
+    let b = field.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        let octal = b
+            .get(i + 1..i + 4)
+            .filter(|d| d.iter().all(|c| (b'0'..=b'7').contains(c)));
+        match octal {
+            Some(d) if b[i] == b'\\' => {
+                out.push(d.iter().fold(0u8, |n, c| n.wrapping_mul(8) + (c - b'0')));
+                i += 4;
+            }
+            _ => {
+                out.push(b[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn violations(mounts: &[MountEntry]) -> Vec<String> {
+    let mut violations = Vec::new();
+    for m in mounts {
+        let target = &m.target;
+        if m.writable() && !m.has("noexec") && !WRITABLE_EXEC.contains(&target.as_str()) {
+            violations.push(format!(
+                "{target} ({}) is writable and executable",
+                m.fstype
+            ));
+        }
+        if m.fstype == "tmpfs" && !(m.has("nodev") && m.has("nosuid")) {
+            violations.push(format!("tmpfs {target} lacks nodev,nosuid"));
+        }
+    }
+    violations
+}
+
+/// Check every mount against the flag policy; see the module docs.
+pub fn audit() {
+    let mountinfo = fs::read_to_string(MOUNTINFO).or_panic(format_args!("read {MOUNTINFO}"));
+    let mounts = parse_mountinfo(&mountinfo);
+    for violation in violations(&mounts) {
+        lockdown::policy_violation(format_args!("mount policy: {violation}"));
+    }
+    info!("mount policy: checked {} mounts", mounts.len());
+}
+
+/// Internal: setup with configurable root path (for testing with temp directories).
+fn setup_at(root: &str) {
+    let boot = MOUNTS.iter().filter(|e| e.when == When::Boot);
+    mount_entries(root, boot.map(|e| (e, e.data)));
 }
 
 #[cfg(test)]
@@ -94,6 +414,174 @@ mod tests {
         })
     }
 
+    // === mount table ===
+
+    fn names(selected: &[(&Entry, Option<&str>)]) -> Vec<&'static str> {
+        selected.iter().map(|(e, _)| e.name).collect()
+    }
+
+    #[test]
+    fn test_mounts_table() {
+        for (i, entry) in MOUNTS.iter().enumerate() {
+            assert!(entry.target.starts_with('/'), "{}", entry.name);
+            assert_eq!(
+                MOUNTS.iter().position(|e| e.name == entry.name),
+                Some(i),
+                "duplicate {}",
+                entry.name
+            );
+            // Optional entries read /proc/filesystems, so proc comes first
+            if !entry.required {
+                assert!(MOUNTS[i..].iter().all(|e| !e.required), "{}", entry.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_selected_by_mode() {
+        assert!(selected("cpu", &[]).is_empty());
+        let gpu = selected("gpu", &[]);
+        assert_eq!(names(&gpu), ["shm"]);
+        assert_eq!(gpu[0].1, Some("mode=1777"));
+    }
+
+    #[test]
+    fn test_selected_by_cmdline() {
+        let enables = vec![
+            ("hugetlbfs".to_owned(), "pagesize=2M".to_owned()),
+            ("shm".to_owned(), "mode=1777,size=1G".to_owned()),
+            ("hugetlbfs".to_owned(), "pagesize=1G".to_owned()),
+        ];
+        let cpu = selected("cpu", &enables);
+        assert_eq!(names(&cpu), ["shm", "hugetlbfs"]);
+        assert_eq!(cpu[0].1, Some("mode=1777,size=1G"));
+        assert_eq!(cpu[1].1, Some("pagesize=1G"));
+
+        // An empty value enables the entry without mount data
+        let enables = vec![("hugetlbfs".to_owned(), String::new())];
+        let cpu = selected("cpu", &enables);
+        assert_eq!(names(&cpu), ["hugetlbfs"]);
+        assert_eq!(cpu[0].1, None);
+    }
+
+    #[test]
+    fn test_check_enable() {
+        assert!(check_enable("hugetlbfs").is_ok());
+        assert!(check_enable("shm").is_ok());
+        assert!(check_enable("proc")
+            .unwrap_err()
+            .contains("before the cmdline"));
+        assert!(check_enable("nfs").unwrap_err().contains("unknown mount"));
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_setup_mode_at_with_temp_root() {
+        use nix::mount::umount;
+        use tempfile::TempDir;
+
+        require_root();
+
+        let tmpdir = TempDir::new().unwrap();
+        let root = tmpdir.path().to_str().unwrap();
+        fs::create_dir_all(format!("{root}/dev")).unwrap();
+
+        let enables = vec![("hugetlbfs".to_owned(), "pagesize=2M".to_owned())];
+        setup_mode_at(root, "gpu", &enables);
+
+        let shm = format!("{root}/dev/shm");
+        assert!(is_mountpoint(&shm));
+        let hugepages = format!("{root}/dev/hugepages");
+        let filesystems = fs::read_to_string("/proc/filesystems").unwrap();
+        assert_eq!(
+            is_mountpoint(&hugepages),
+            fs_available(&filesystems, "hugetlbfs")
+        );
+
+        let _ = umount(hugepages.as_str());
+        let _ = umount(shm.as_str());
+    }
+
+    // === mount policy ===
+
+    const MOUNTINFO_GUEST: &str = "\
+22 1 254:1 / / ro,nosuid,relatime - ext4 /dev/root ro
+23 22 0:5 / /dev rw,relatime - devtmpfs devtmpfs rw,size=4096k,mode=755
+24 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw
+25 22 0:22 / /sys rw,nosuid,nodev,noexec,relatime - sysfs sysfs rw
+26 22 0:23 / /run rw,nosuid,nodev,noexec,relatime - tmpfs run rw,mode=755
+27 22 0:24 / /tmp rw,nosuid,nodev,relatime - tmpfs tmpfs rw
+28 25 0:7 / /sys/kernel/security rw,nosuid,nodev,noexec,relatime - securityfs securityfs rw
+29 26 253:0 / /run/kata-extensions/gpu ro,nosuid,nodev,relatime - erofs /dev/mapper/extension-gpu ro
+30 22 253:0 /lib/firmware/nvidia /lib/firmware/nvidia rw,relatime - erofs /dev/mapper/extension-gpu ro
+31 22 0:23 /ld.so.cache /etc/ld.so.cache ro,nosuid,nodev,noexec,relatime - tmpfs run rw,mode=755
+";
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let mounts = parse_mountinfo(MOUNTINFO_GUEST);
+        assert_eq!(mounts.len(), 10);
+        assert_eq!(
+            mounts[5],
+            MountEntry {
+                target: "/tmp".to_owned(),
+                fstype: "tmpfs".to_owned(),
+                options: vec!["rw", "nosuid", "nodev", "relatime"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+                super_options: vec!["rw".to_owned()],
+            }
+        );
+        // Optional fields (shared:N, master:N) sit before the separator.
+        let shared = parse_mountinfo("1 0 8:1 / /a\\040b rw shared:1 master:2 - ext4 /dev/sda1 rw");
+        assert_eq!(shared[0].target, "/a b");
+        assert_eq!(shared[0].fstype, "ext4");
+        assert!(parse_mountinfo("garbage").is_empty());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/a\\040b\\011c\\134d"), "/a b\tc\\d");
+        assert_eq!(unescape("/plain"), "/plain");
+        assert_eq!(unescape("/trailing\\04"), "/trailing\\04");
+    }
+
+    #[test]
+    fn test_violations_hardened_guest() {
+        // The bind of a read-only superblock counts as read-only.
+        assert!(violations(&parse_mountinfo(MOUNTINFO_GUEST)).is_empty());
+    }
+
+    #[test]
+    fn test_violations() {
+        let mountinfo = "\
+22 1 254:1 / / rw,relatime - ext4 /dev/root rw
+26 22 0:23 / /run rw,nosuid,noexec,relatime - tmpfs run rw
+27 22 0:24 / /dev/shm rw,nodev,noexec,relatime - tmpfs tmpfs rw
+28 22 0:25 / /mnt ro,relatime - ext4 /dev/vdb rw
+";
+        assert_eq!(
+            violations(&parse_mountinfo(mountinfo)),
+            [
+                "/ (ext4) is writable and executable",
+                "tmpfs /run lacks nodev,nosuid",
+                "tmpfs /dev/shm lacks nodev,nosuid",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audit_host_warns_without_confidential() {
+        // Dev hosts have writable roots; without `confidential` that only warns.
+        if !cfg!(feature = "confidential") {
+            audit();
+        }
+    }
+
     // === fs_available tests ===
 
     #[test]
@@ -124,6 +612,7 @@ mod tests {
             "/nonexistent/path",
             "tmpfs",
             MsFlags::empty(),
+            None,
         );
     }
 
@@ -144,6 +633,7 @@ mod tests {
             target.to_str().unwrap(),
             "configfs",
             MsFlags::empty(),
+            None,
         );
     }
 
@@ -170,6 +660,7 @@ mod tests {
             target.to_str().unwrap(),
             "tmpfs",
             MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            None,
         );
 
         assert!(is_mountpoint(target.to_str().unwrap()));
@@ -252,8 +743,17 @@ mod tests {
             assert!(is_mountpoint(&securityfs_path));
         }
 
+        if fs_available(&filesystems, "devpts") {
+            assert!(is_mountpoint(&format!("{root}/dev/pts")));
+        }
+        // Mode-specific entries wait for setup_mode
+        assert!(!is_mountpoint(&format!("{root}/dev/shm")));
+
         // Unmount nested mounts first to avoid EBUSY
         for dir in [
+            "dev/mqueue",
+            "dev/pts",
+            "sys/fs/cgroup",
             "sys/kernel/config",
             "sys/kernel/security",
             "tmp",
@@ -7,9 +7,58 @@
 //! the responsibility of the init process (PID 1). Services like
 //! nv-fabricmanager bind to 127.0.0.1 and fail with ENETUNREACH if `lo`
 //! is down.
+//!
+//! Other interfaces are configured over rtnetlink from
+//! `nvrc.net.<ifname>=<setting>,...`, where a setting is `up`, `down`,
+//! `mtu=<n>`, `addr=<ip>/<len>` or `route=<dst>/<len>[@<gateway>]`
+//! (`route=default@<gateway>` for the default route), e.g.
+//! `nvrc.net.ib0=up,mtu=4092,addr=192.168.100.2/24`. An `ib*` interface
+//! only exists once the ConnectX driver and IPoIB are loaded, so [`setup`]
+//! loads them first; it runs once the mode's allow-list is known and before
+//! fabric manager or nvlsm start.
+//!
+//! [`harden`] applies restrictive IPv4/IPv6 sysctls, and [`audit`] logs every
+//! interface before kata-agent starts. A VMM can hot-plug a virtio-net device
+//! the workload never asked for; with `nvrc.net.allow=<ifname>,...` any
+//! interface but `lo` and those listed refuses the boot.
 
 use crate::macros::ResultExt;
+use crate::modprobe;
+use crate::sysctl;
 use nix::errno::Errno;
+use std::fs;
+use std::net::IpAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+
+const PROC_SYS: &str = "/proc/sys";
+const SYS_CLASS_NET: &str = "/sys/class/net";
+
+/// What an `ib*` interface needs, in load order: the ConnectX driver, then
+/// the IPoIB ULP that creates the netdev.
+const IPOIB_DRIVERS: &[&str] = &["mlx5_ib", "ib_ipoib"];
+
+/// `(key, value, mandatory)`: IPv4 is always there, IPv6 may be compiled out.
+const NET_PROFILE: &[(&str, &str, bool)] = &[
+    ("net.ipv4.conf.all.rp_filter", "1", true),
+    ("net.ipv4.conf.default.rp_filter", "1", true),
+    ("net.ipv4.conf.all.accept_redirects", "0", true),
+    ("net.ipv4.conf.default.accept_redirects", "0", true),
+    ("net.ipv4.conf.all.secure_redirects", "0", true),
+    ("net.ipv4.conf.default.secure_redirects", "0", true),
+    ("net.ipv4.conf.all.send_redirects", "0", true),
+    ("net.ipv4.conf.default.send_redirects", "0", true),
+    ("net.ipv4.conf.all.accept_source_route", "0", true),
+    ("net.ipv4.conf.default.accept_source_route", "0", true),
+    ("net.ipv4.icmp_echo_ignore_broadcasts", "1", true),
+    ("net.ipv6.conf.all.accept_redirects", "0", false),
+    ("net.ipv6.conf.default.accept_redirects", "0", false),
+    ("net.ipv6.conf.all.accept_source_route", "0", false),
+    ("net.ipv6.conf.default.accept_source_route", "0", false),
+    // Interfaces that appear later (driver loads) start without SLAAC.
+    ("net.ipv6.conf.default.autoconf", "0", false),
+    ("net.ipv6.conf.default.accept_ra", "0", false),
+];
 
 const LOOPBACK: [libc::c_char; 3] = [b'l' as libc::c_char, b'o' as libc::c_char, 0];
 
@@ -34,10 +83,479 @@ pub fn loopback_up() {
     info!("loopback interface up");
 }
 
+fn interfaces_at(class_net: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(class_net)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Apply [`NET_PROFILE`], and turn off IPv6 autoconf on every present
+/// interface not configured with `nvrc.net.<ifname>`.
+pub fn harden(managed: &[Interface]) {
+    harden_at(Path::new(PROC_SYS), Path::new(SYS_CLASS_NET), managed)
+}
+
+fn harden_at(proc_sys: &Path, class_net: &Path, managed: &[Interface]) {
+    let keys: Vec<String> = interfaces_at(class_net)
+        .into_iter()
+        // lo has no SLAAC; a dotted name (VLAN) cannot be spelled as a key.
+        .filter(|name| name != "lo" && !name.contains('.'))
+        .filter(|name| !managed.iter().any(|m| &m.name == name))
+        .flat_map(|name| {
+            ["autoconf", "accept_ra"].map(|knob| format!("net.ipv6.conf.{name}.{knob}"))
+        })
+        .collect();
+    let mut entries = NET_PROFILE.to_vec();
+    entries.extend(keys.iter().map(|key| (key.as_str(), "0", false)));
+    sysctl::apply_at(proc_sys, &entries);
+}
+
+/// Log each interface with its driver and state; panic on one outside
+/// `allow` (plus `lo`) when an allow-list is given.
+pub fn audit(allow: Option<&[String]>) {
+    audit_at(Path::new(SYS_CLASS_NET), allow)
+}
+
+fn audit_at(class_net: &Path, allow: Option<&[String]>) {
+    for name in interfaces_at(class_net) {
+        let dir = class_net.join(&name);
+        let driver = fs::read_link(dir.join("device/driver"))
+            .ok()
+            .and_then(|p| p.file_name().map(|f| f.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "virtual".to_owned());
+        let state = fs::read_to_string(dir.join("operstate")).unwrap_or_default();
+        let state = state.trim();
+        info!("net: {name} driver={driver} state={state}");
+        if let Some(allow) = allow {
+            if name != "lo" && !allow.contains(&name) {
+                panic!("net: unexpected interface {name} (driver {driver})");
+            }
+        }
+    }
+}
+
+/// One setting of an `nvrc.net.<ifname>` parameter.
+#[derive(Debug, PartialEq)]
+pub enum Setting {
+    Up,
+    Down,
+    Mtu(u32),
+    Addr(IpAddr, u8),
+    Route {
+        dst: IpAddr,
+        len: u8,
+        via: Option<IpAddr>,
+    },
+}
+
+/// An interface and its settings, applied link first, then addresses, then
+/// routes (which may need those addresses to reach their gateway).
+#[derive(Debug, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub settings: Vec<Setting>,
+}
+
+fn max_len(ip: &IpAddr) -> u8 {
+    if ip.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+fn parse_prefix(param: &str, prefix: &str) -> Result<(IpAddr, u8), String> {
+    let (ip, len) = prefix
+        .split_once('/')
+        .ok_or_else(|| format!("{param}: expected <ip>/<len>, got {prefix:?}"))?;
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|e| format!("{param}: invalid address {ip:?}: {e}"))?;
+    let len = len
+        .parse()
+        .ok()
+        .filter(|&len| len <= max_len(&ip))
+        .ok_or_else(|| format!("{param}: invalid prefix length {len:?}"))?;
+    Ok((ip, len))
+}
+
+fn parse_route(param: &str, route: &str) -> Result<Setting, String> {
+    let (dst, via) = match route.split_once('@') {
+        Some((dst, via)) => {
+            let via: IpAddr = via
+                .parse()
+                .map_err(|e| format!("{param}: invalid gateway {via:?}: {e}"))?;
+            (dst, Some(via))
+        }
+        None => (route, None),
+    };
+    let (dst, len) = match (dst, via) {
+        ("default", Some(IpAddr::V4(_))) => (IpAddr::from([0u8; 4]), 0),
+        ("default", Some(IpAddr::V6(_))) => (IpAddr::from([0u8; 16]), 0),
+        ("default", None) => return Err(format!("{param}: default route needs a gateway")),
+        (dst, _) => parse_prefix(param, dst)?,
+    };
+    if via.is_some_and(|via| via.is_ipv4() != dst.is_ipv4()) {
+        return Err(format!("{param}: gateway family differs from {route:?}"));
+    }
+    Ok(Setting::Route { dst, len, via })
+}
+
+/// Parse `nvrc.net.<ifname>=<spec>`.
+pub fn parse(ifname: &str, spec: &str) -> Result<Interface, String> {
+    let param = format!("nvrc.net.{ifname}");
+    if ifname.is_empty() || ifname.len() >= libc::IFNAMSIZ || ifname.contains('/') {
+        return Err(format!("{param}: invalid interface name"));
+    }
+    let settings = spec
+        .split(',')
+        .map(|setting| match setting.split_once('=') {
+            None if setting == "up" => Ok(Setting::Up),
+            None if setting == "down" => Ok(Setting::Down),
+            Some(("mtu", mtu)) => mtu
+                .parse()
+                .map(Setting::Mtu)
+                .map_err(|e| format!("{param}: invalid mtu {mtu:?}: {e}")),
+            Some(("addr", prefix)) => {
+                parse_prefix(&param, prefix).map(|(ip, len)| Setting::Addr(ip, len))
+            }
+            Some(("route", route)) => parse_route(&param, route),
+            _ => Err(format!("{param}: unknown setting {setting:?}")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Interface {
+        name: ifname.to_owned(),
+        settings,
+    })
+}
+
+const NLMSG_HDRLEN: usize = 16;
+const NLA_HDRLEN: usize = 4;
+
+const fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn family(ip: &IpAddr) -> u8 {
+    if ip.is_ipv4() {
+        libc::AF_INET as u8
+    } else {
+        libc::AF_INET6 as u8
+    }
+}
+
+fn octets(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+/// A netlink request: header, fixed family header, then attributes.
+struct Message(Vec<u8>);
+
+impl Message {
+    fn new(kind: u16, flags: u16, seq: u32) -> Self {
+        let mut buf = vec![0; NLMSG_HDRLEN];
+        buf[4..6].copy_from_slice(&kind.to_ne_bytes());
+        buf[6..8].copy_from_slice(
+            &(flags | (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16).to_ne_bytes(),
+        );
+        buf[8..12].copy_from_slice(&seq.to_ne_bytes());
+        Message(buf)
+    }
+
+    fn push(mut self, bytes: &[u8]) -> Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    fn attr(mut self, kind: u16, data: &[u8]) -> Self {
+        let len = (NLA_HDRLEN + data.len()) as u16;
+        self.0.extend_from_slice(&len.to_ne_bytes());
+        self.0.extend_from_slice(&kind.to_ne_bytes());
+        self.0.extend_from_slice(data);
+        self.0.resize(align(self.0.len()), 0);
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&len.to_ne_bytes());
+        self.0
+    }
+}
+
+/// RTM_NEWLINK: set IFF_UP as `up` says (or leave it), and the MTU.
+fn newlink(seq: u32, index: u32, up: Option<bool>, mtu: Option<u32>) -> Vec<u8> {
+    let iff_up = libc::IFF_UP as u32;
+    let change = if up.is_some() { iff_up } else { 0 };
+    let flags = if up == Some(true) { iff_up } else { 0 };
+    // struct ifinfomsg: family, pad, type, index, flags, change
+    let mut ifinfo = [0u8; 16];
+    ifinfo[4..8].copy_from_slice(&index.to_ne_bytes());
+    ifinfo[8..12].copy_from_slice(&flags.to_ne_bytes());
+    ifinfo[12..16].copy_from_slice(&change.to_ne_bytes());
+    let msg = Message::new(libc::RTM_NEWLINK, 0, seq).push(&ifinfo);
+    match mtu {
+        Some(mtu) => msg.attr(libc::IFLA_MTU, &mtu.to_ne_bytes()),
+        None => msg,
+    }
+    .finish()
+}
+
+/// RTM_NEWADDR, replacing an existing identical address.
+fn newaddr(seq: u32, index: u32, ip: &IpAddr, len: u8) -> Vec<u8> {
+    // struct ifaddrmsg: family, prefixlen, flags, scope, index
+    let mut ifaddr = [family(ip), len, 0, libc::RT_SCOPE_UNIVERSE, 0, 0, 0, 0];
+    ifaddr[4..8].copy_from_slice(&index.to_ne_bytes());
+    let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    let msg = Message::new(libc::RTM_NEWADDR, flags, seq).push(&ifaddr);
+    let msg = match ip {
+        IpAddr::V4(_) => msg.attr(libc::IFA_LOCAL, &octets(ip)),
+        IpAddr::V6(_) => msg,
+    };
+    msg.attr(libc::IFA_ADDRESS, &octets(ip)).finish()
+}
+
+/// RTM_NEWROUTE in the main table. Without a gateway the destination is
+/// on-link.
+fn newroute(seq: u32, index: u32, dst: &IpAddr, len: u8, via: Option<&IpAddr>) -> Vec<u8> {
+    let scope = match via {
+        Some(_) => libc::RT_SCOPE_UNIVERSE,
+        None => libc::RT_SCOPE_LINK,
+    };
+    // struct rtmsg: family, dst_len, src_len, tos, table, protocol, scope, type, flags
+    let rtmsg = [
+        family(dst),
+        len,
+        0,
+        0,
+        libc::RT_TABLE_MAIN,
+        libc::RTPROT_BOOT,
+        scope,
+        libc::RTN_UNICAST,
+        0,
+        0,
+        0,
+        0,
+    ];
+    let flags = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+    let mut msg = Message::new(libc::RTM_NEWROUTE, flags, seq).push(&rtmsg);
+    if len > 0 {
+        msg = msg.attr(libc::RTA_DST, &octets(dst));
+    }
+    if let Some(via) = via {
+        msg = msg.attr(libc::RTA_GATEWAY, &octets(via));
+    }
+    msg.attr(libc::RTA_OIF, &index.to_ne_bytes()).finish()
+}
+
+/// The kernel's answer to request `seq`: Ok for an ACK (NLMSG_ERROR with
+/// error 0), the errno otherwise. None if `buf` holds no answer to `seq`.
+fn ack(buf: &[u8], seq: u32) -> Option<Result<(), Errno>> {
+    let mut rest = buf;
+    while rest.len() >= NLMSG_HDRLEN {
+        let u32_at = |b: &[u8], i: usize| u32::from_ne_bytes(b[i..i + 4].try_into().unwrap());
+        let len = u32_at(rest, 0) as usize;
+        if len < NLMSG_HDRLEN || len > rest.len() {
+            return Some(Err(Errno::EBADMSG));
+        }
+        let kind = u16::from_ne_bytes([rest[4], rest[5]]);
+        if kind == libc::NLMSG_ERROR as u16 && u32_at(rest, 8) == seq {
+            // struct nlmsgerr: int error, then the offending header
+            if len < NLMSG_HDRLEN + 4 {
+                return Some(Err(Errno::EBADMSG));
+            }
+            let error = u32_at(rest, NLMSG_HDRLEN) as i32;
+            return Some(match error {
+                0 => Ok(()),
+                e => Err(Errno::from_raw(-e)),
+            });
+        }
+        rest = &rest[align(len).min(rest.len())..];
+    }
+    None
+}
+
+/// A NETLINK_ROUTE socket talking to the kernel, one request at a time.
+struct Netlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Netlink {
+    fn open() -> Result<Self, Errno> {
+        let fd = Errno::result(unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        })?;
+        Ok(Netlink {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: 0,
+        })
+    }
+
+    /// Send the message built for the next sequence number, wait for its ACK.
+    fn request(&mut self, build: impl FnOnce(u32) -> Vec<u8>) -> Result<(), Errno> {
+        self.seq += 1;
+        let msg = build(self.seq);
+        let mut kernel: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        Errno::result(unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                msg.as_ptr().cast(),
+                msg.len(),
+                0,
+                std::ptr::from_ref(&kernel).cast(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        })?;
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = Errno::result(unsafe {
+                libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0)
+            })?;
+            if let Some(result) = ack(&buf[..n as usize], self.seq) {
+                return result;
+            }
+        }
+    }
+}
+
+fn drivers(interfaces: &[Interface]) -> &'static [&'static str] {
+    match interfaces.iter().any(|i| i.name.starts_with("ib")) {
+        true => IPOIB_DRIVERS,
+        false => &[],
+    }
+}
+
+/// Modules the configured interfaces add to the allow-list, dependencies included.
+pub fn modules(interfaces: &[Interface]) -> Vec<&'static str> {
+    if drivers(interfaces).is_empty() {
+        return Vec::new();
+    }
+    let mut modules = modprobe::CX7_MODULES.to_vec();
+    modules.extend(["ib_ipoib", "ib_cm"]);
+    modules
+}
+
+/// Load the drivers the `nvrc.net.<ifname>` interfaces need, then [`harden`]
+/// and [`configure`]. Runs after [`modprobe::set_mode`] and before any daemon
+/// binds to these addresses.
+pub fn setup(interfaces: &[Interface]) {
+    setup_with(interfaces, modprobe::load, harden, configure)
+}
+
+fn setup_with(
+    interfaces: &[Interface],
+    mut load: impl FnMut(&str),
+    harden: impl FnOnce(&[Interface]),
+    configure: impl FnOnce(&[Interface]),
+) {
+    for driver in drivers(interfaces) {
+        load(driver);
+    }
+    harden(interfaces);
+    configure(interfaces);
+}
+
+/// Apply the `nvrc.net.<ifname>` settings. The interfaces must exist by now.
+pub fn configure(interfaces: &[Interface]) {
+    if interfaces.is_empty() {
+        return;
+    }
+    let mut nl = Netlink::open().or_panic(format_args!("netlink socket"));
+    for iface in interfaces {
+        let name = &iface.name;
+        let cname = std::ffi::CString::new(name.as_str()).or_panic(format_args!("ifname {name}"));
+        let index = unsafe { libc::if_nametoindex(cname.as_ptr()) };
+        if index == 0 {
+            panic!("nvrc.net.{name}: no such interface");
+        }
+
+        let mut up = None;
+        let mut mtu = None;
+        for setting in &iface.settings {
+            match setting {
+                Setting::Up => up = Some(true),
+                Setting::Down => up = Some(false),
+                Setting::Mtu(m) => mtu = Some(*m),
+                _ => {}
+            }
+        }
+        if up.is_some() || mtu.is_some() {
+            nl.request(|seq| newlink(seq, index, up, mtu))
+                .or_panic(format_args!("{name}: set link"));
+        }
+        for setting in &iface.settings {
+            if let Setting::Addr(ip, len) = setting {
+                nl.request(|seq| newaddr(seq, index, ip, *len))
+                    .or_panic(format_args!("{name}: add {ip}/{len}"));
+            }
+        }
+        for setting in &iface.settings {
+            if let Setting::Route { dst, len, via } = setting {
+                nl.request(|seq| newroute(seq, index, dst, *len, via.as_ref()))
+                    .or_panic(format_args!("{name}: route {dst}/{len}"));
+            }
+        }
+        info!("{name} configured");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_utils::require_root;
+    use std::cell::RefCell;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_setup_loads_ipoib_first() {
+        let log = RefCell::new(Vec::new());
+        let interfaces = [
+            parse("eth0", "up").unwrap(),
+            parse("ib0", "up,mtu=4092,addr=192.168.100.2/24").unwrap(),
+        ];
+        setup_with(
+            &interfaces,
+            |m| log.borrow_mut().push(format!("load {m}")),
+            |i| log.borrow_mut().push(format!("harden {}", i.len())),
+            |i| log.borrow_mut().push(format!("configure {}", i.len())),
+        );
+        assert_eq!(
+            log.into_inner(),
+            ["load mlx5_ib", "load ib_ipoib", "harden 2", "configure 2"]
+        );
+
+        let log = RefCell::new(Vec::new());
+        setup_with(
+            &interfaces[..1],
+            |m| log.borrow_mut().push(format!("load {m}")),
+            |_| log.borrow_mut().push("harden".to_owned()),
+            |_| log.borrow_mut().push("configure".to_owned()),
+        );
+        assert_eq!(log.into_inner(), ["harden", "configure"]);
+    }
+
+    #[test]
+    fn test_modules() {
+        assert!(modules(&[parse("eth0", "up").unwrap()]).is_empty());
+        let ib = modules(&[parse("ib0", "up").unwrap()]);
+        for module in ["mlx5_ib", "ib_core", "ib_ipoib", "ib_cm"] {
+            assert!(ib.contains(&module), "{module}");
+        }
+    }
 
     #[test]
     #[cfg_attr(
@@ -87,6 +605,342 @@ mod tests {
         let _ = unsafe { libc::close(fd) };
     }
 
+    // === hardening and audit ===
+
+    /// A fake /sys/class/net: (name, driver) with `None` for a virtual device.
+    fn class_net(ifaces: &[(&str, Option<&str>)]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (name, driver) in ifaces {
+            let iface = dir.path().join(name);
+            fs::create_dir_all(&iface).unwrap();
+            fs::write(iface.join("operstate"), "up\n").unwrap();
+            if let Some(driver) = driver {
+                fs::create_dir_all(iface.join("device")).unwrap();
+                let target = format!("../../../bus/virtio/drivers/{driver}");
+                std::os::unix::fs::symlink(target, iface.join("device/driver")).unwrap();
+            }
+        }
+        dir
+    }
+
+    /// A fake /proc/sys holding every knob `harden_at` may write.
+    fn proc_sys(ifaces: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let mut keys: Vec<String> = NET_PROFILE.iter().map(|(k, _, _)| k.to_string()).collect();
+        for name in ifaces {
+            keys.push(format!("net.ipv6.conf.{name}.autoconf"));
+            keys.push(format!("net.ipv6.conf.{name}.accept_ra"));
+        }
+        for key in keys {
+            let path = dir.path().join(key.replace('.', "/"));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "1\n").unwrap();
+        }
+        dir
+    }
+
+    fn read(root: &Path, key: &str) -> String {
+        fs::read_to_string(root.join(key.replace('.', "/")))
+            .unwrap()
+            .trim()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_interfaces_at() {
+        let net = class_net(&[("lo", None), ("eth1", Some("virtio_net")), ("eth0", None)]);
+        assert_eq!(interfaces_at(net.path()), ["eth0", "eth1", "lo"]);
+        assert!(interfaces_at(Path::new("/nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn test_harden_at() {
+        let net = class_net(&[("lo", None), ("eth0", None), ("ib0", None)]);
+        let sys = proc_sys(&["eth0", "ib0"]);
+        let managed = [parse("ib0", "up").unwrap()];
+        harden_at(sys.path(), net.path(), &managed);
+
+        for (key, value, _) in NET_PROFILE {
+            assert_eq!(read(sys.path(), key), *value, "{key}");
+        }
+        assert_eq!(read(sys.path(), "net.ipv6.conf.eth0.autoconf"), "0");
+        assert_eq!(read(sys.path(), "net.ipv6.conf.eth0.accept_ra"), "0");
+        // Configured interfaces are left to their nvrc.net settings
+        assert_eq!(read(sys.path(), "net.ipv6.conf.ib0.autoconf"), "1");
+    }
+
+    #[test]
+    fn test_harden_at_without_ipv6() {
+        let net = class_net(&[("eth0", None)]);
+        let sys = proc_sys(&[]);
+        fs::remove_dir_all(sys.path().join("net/ipv6")).unwrap();
+        // IPv6 knobs are optional; the IPv4 ones still apply
+        harden_at(sys.path(), net.path(), &[]);
+        assert_eq!(read(sys.path(), "net.ipv4.conf.all.rp_filter"), "1");
+    }
+
+    #[test]
+    fn test_harden_at_missing_ipv4_panics() {
+        let net = class_net(&[]);
+        let sys = TempDir::new().unwrap();
+        let result = std::panic::catch_unwind(|| harden_at(sys.path(), net.path(), &[]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_audit_at() {
+        let net = class_net(&[("lo", None), ("eth0", Some("virtio_net"))]);
+        audit_at(net.path(), None);
+        audit_at(net.path(), Some(&["eth0".to_owned()]));
+    }
+
+    #[test]
+    fn test_audit_at_unexpected_interface() {
+        let net = class_net(&[("lo", None), ("eth0", None), ("eth1", Some("virtio_net"))]);
+        let allow = ["eth0".to_owned()];
+        let result = std::panic::catch_unwind(|| audit_at(net.path(), Some(&allow)));
+        assert!(result.is_err());
+    }
+
+    // === nvrc.net parsing ===
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let iface = parse(
+            "ib0",
+            "up,mtu=4092,addr=192.168.100.2/24,addr=fd00::2/64,route=10.0.0.0/8@192.168.100.1,route=172.16.0.0/12,route=default@fd00::1",
+        )
+        .unwrap();
+        assert_eq!(iface.name, "ib0");
+        assert_eq!(
+            iface.settings,
+            [
+                Setting::Up,
+                Setting::Mtu(4092),
+                Setting::Addr(ip("192.168.100.2"), 24),
+                Setting::Addr(ip("fd00::2"), 64),
+                Setting::Route {
+                    dst: ip("10.0.0.0"),
+                    len: 8,
+                    via: Some(ip("192.168.100.1")),
+                },
+                Setting::Route {
+                    dst: ip("172.16.0.0"),
+                    len: 12,
+                    via: None,
+                },
+                Setting::Route {
+                    dst: ip("::"),
+                    len: 0,
+                    via: Some(ip("fd00::1")),
+                },
+            ]
+        );
+        assert_eq!(parse("eth0", "down").unwrap().settings, [Setting::Down]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |ifname: &str, spec: &str| parse(ifname, spec).unwrap_err();
+        assert!(err("", "up").contains("invalid interface name"));
+        assert!(err("a/b", "up").contains("invalid interface name"));
+        assert!(err("sixteen_chars_xx", "up").contains("invalid interface name"));
+        assert!(err("eth0", "promisc").contains("unknown setting"));
+        assert!(err("eth0", "mtu=big").contains("invalid mtu"));
+        assert!(err("eth0", "addr=10.0.0.1").contains("<ip>/<len>"));
+        assert!(err("eth0", "addr=10.0.0.1/33").contains("prefix length"));
+        assert!(err("eth0", "addr=10.0.0/8").contains("invalid address"));
+        assert!(err("eth0", "route=default").contains("needs a gateway"));
+        assert!(err("eth0", "route=10.0.0.0/8@fd00::1").contains("family"));
+        assert!(err("eth0", "route=10.0.0.0/8@x").contains("invalid gateway"));
+    }
+
+    // === rtnetlink encoding ===
+
+    fn header(msg: &[u8]) -> (u32, u16, u16, u32) {
+        (
+            u32::from_ne_bytes(msg[0..4].try_into().unwrap()),
+            u16::from_ne_bytes(msg[4..6].try_into().unwrap()),
+            u16::from_ne_bytes(msg[6..8].try_into().unwrap()),
+            u32::from_ne_bytes(msg[8..12].try_into().unwrap()),
+        )
+    }
+
+    /// (type, data) of each attribute after a fixed header of `fixed` bytes.
+    fn attrs(msg: &[u8], fixed: usize) -> Vec<(u16, Vec<u8>)> {
+        let mut rest = &msg[NLMSG_HDRLEN + fixed..];
+        let mut attrs = Vec::new();
+        while !rest.is_empty() {
+            let len = u16::from_ne_bytes([rest[0], rest[1]]) as usize;
+            let kind = u16::from_ne_bytes([rest[2], rest[3]]);
+            attrs.push((kind, rest[NLA_HDRLEN..len].to_vec()));
+            rest = &rest[align(len)..];
+        }
+        attrs
+    }
+
+    const REQUEST_ACK: u16 = (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+    const CREATE_REPLACE: u16 = (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16;
+
+    #[test]
+    fn test_newlink() {
+        let msg = newlink(7, 3, Some(true), Some(4092));
+        assert_eq!(header(&msg), (40, libc::RTM_NEWLINK, REQUEST_ACK, 7));
+        let ifinfo = &msg[NLMSG_HDRLEN..NLMSG_HDRLEN + 16];
+        assert_eq!(ifinfo[4..8], 3u32.to_ne_bytes());
+        assert_eq!(ifinfo[8..12], (libc::IFF_UP as u32).to_ne_bytes());
+        assert_eq!(ifinfo[12..16], (libc::IFF_UP as u32).to_ne_bytes());
+        assert_eq!(
+            attrs(&msg, 16),
+            [(libc::IFLA_MTU, 4092u32.to_ne_bytes().to_vec())]
+        );
+
+        // Down clears IFF_UP; MTU alone leaves the flags untouched
+        let down = newlink(1, 3, Some(false), None);
+        assert_eq!(header(&down).0, 32);
+        assert_eq!(down[NLMSG_HDRLEN + 8..NLMSG_HDRLEN + 12], [0; 4]);
+        assert_eq!(
+            down[NLMSG_HDRLEN + 12..],
+            (libc::IFF_UP as u32).to_ne_bytes()
+        );
+        let mtu = newlink(1, 3, None, Some(1500));
+        assert_eq!(mtu[NLMSG_HDRLEN + 8..NLMSG_HDRLEN + 16], [0; 8]);
+    }
+
+    #[test]
+    fn test_newaddr() {
+        let msg = newaddr(2, 5, &ip("192.168.100.2"), 24);
+        assert_eq!(
+            header(&msg),
+            (40, libc::RTM_NEWADDR, REQUEST_ACK | CREATE_REPLACE, 2)
+        );
+        let ifaddr = &msg[NLMSG_HDRLEN..NLMSG_HDRLEN + 8];
+        assert_eq!(
+            ifaddr[..4],
+            [libc::AF_INET as u8, 24, 0, libc::RT_SCOPE_UNIVERSE]
+        );
+        assert_eq!(ifaddr[4..], 5u32.to_ne_bytes());
+        assert_eq!(
+            attrs(&msg, 8),
+            [
+                (libc::IFA_LOCAL, vec![192, 168, 100, 2]),
+                (libc::IFA_ADDRESS, vec![192, 168, 100, 2]),
+            ]
+        );
+
+        let v6 = newaddr(3, 5, &ip("fd00::2"), 64);
+        assert_eq!(header(&v6).0, 44);
+        assert_eq!(v6[NLMSG_HDRLEN], libc::AF_INET6 as u8);
+        assert_eq!(attrs(&v6, 8), [(libc::IFA_ADDRESS, octets(&ip("fd00::2")))]);
+    }
+
+    #[test]
+    fn test_newroute() {
+        let msg = newroute(4, 5, &ip("10.0.0.0"), 8, Some(&ip("192.168.100.1")));
+        assert_eq!(
+            header(&msg),
+            (52, libc::RTM_NEWROUTE, REQUEST_ACK | CREATE_REPLACE, 4)
+        );
+        let rtmsg = &msg[NLMSG_HDRLEN..NLMSG_HDRLEN + 12];
+        assert_eq!(
+            rtmsg[..8],
+            [
+                libc::AF_INET as u8,
+                8,
+                0,
+                0,
+                libc::RT_TABLE_MAIN,
+                libc::RTPROT_BOOT,
+                libc::RT_SCOPE_UNIVERSE,
+                libc::RTN_UNICAST
+            ]
+        );
+        assert_eq!(
+            attrs(&msg, 12),
+            [
+                (libc::RTA_DST, vec![10, 0, 0, 0]),
+                (libc::RTA_GATEWAY, vec![192, 168, 100, 1]),
+                (libc::RTA_OIF, 5u32.to_ne_bytes().to_vec()),
+            ]
+        );
+
+        // A default route has no RTA_DST; an on-link one no gateway
+        let default = newroute(5, 5, &ip("0.0.0.0"), 0, Some(&ip("192.168.100.1")));
+        let kinds: Vec<u16> = attrs(&default, 12).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, [libc::RTA_GATEWAY, libc::RTA_OIF]);
+        let onlink = newroute(6, 5, &ip("172.16.0.0"), 12, None);
+        assert_eq!(onlink[NLMSG_HDRLEN + 6], libc::RT_SCOPE_LINK);
+        let kinds: Vec<u16> = attrs(&onlink, 12).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(kinds, [libc::RTA_DST, libc::RTA_OIF]);
+    }
+
+    // === rtnetlink decoding ===
+
+    /// NLMSG_ERROR answering `seq`, echoing a bare request header.
+    fn nlmsgerr(seq: u32, error: i32) -> Vec<u8> {
+        let mut msg = Message::new(libc::NLMSG_ERROR as u16, 0, seq)
+            .push(&error.to_ne_bytes())
+            .push(&[0; NLMSG_HDRLEN])
+            .finish();
+        msg[6..8].copy_from_slice(&0u16.to_ne_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_ack() {
+        assert_eq!(ack(&nlmsgerr(1, 0), 1), Some(Ok(())));
+        assert_eq!(
+            ack(&nlmsgerr(1, -libc::EEXIST), 1),
+            Some(Err(Errno::EEXIST))
+        );
+        // Answers to other requests are skipped
+        assert_eq!(ack(&nlmsgerr(1, 0), 2), None);
+        let mut two = nlmsgerr(1, -libc::ENODEV);
+        two.extend(nlmsgerr(2, 0));
+        assert_eq!(ack(&two, 2), Some(Ok(())));
+        assert_eq!(ack(&two, 1), Some(Err(Errno::ENODEV)));
+        assert_eq!(ack(&[], 1), None);
+    }
+
+    #[test]
+    fn test_ack_malformed() {
+        let mut msg = nlmsgerr(1, 0);
+        msg[..4].copy_from_slice(&100u32.to_ne_bytes());
+        assert_eq!(ack(&msg, 1), Some(Err(Errno::EBADMSG)));
+        msg[..4].copy_from_slice(&8u32.to_ne_bytes());
+        assert_eq!(ack(&msg, 1), Some(Err(Errno::EBADMSG)));
+        let truncated = Message::new(libc::NLMSG_ERROR as u16, 0, 1).finish();
+        assert_eq!(ack(&truncated, 1), Some(Err(Errno::EBADMSG)));
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_configure_loopback() {
+        require_root();
+        // Everything here is already true of lo, so the kernel just ACKs it
+        let lo = parse("lo", "up,addr=127.0.0.1/8").unwrap();
+        configure(&[lo]);
+    }
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "root-gated: require_root re-execs the test binary via sudo, which miri cannot emulate"
+    )]
+    fn test_configure_missing_interface() {
+        require_root();
+        let missing = parse("nvrc-missing0", "up").unwrap();
+        let result = std::panic::catch_unwind(|| configure(&[missing]));
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_loopback_const() {
         assert_eq!(LOOPBACK, [b'l' as libc::c_char, b'o' as libc::c_char, 0]);
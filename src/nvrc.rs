@@ -3,6 +3,9 @@
 
 //! NVRC configuration state and daemon lifecycle management.
 
+use crate::gpudirect::Feature;
+use crate::hugepages::Hugepages;
+use crate::net::Interface;
 use std::process::Child;
 
 /// Central configuration state for the NVIDIA Runtime Container init.
@@ -23,6 +26,26 @@ pub struct NVRC {
     pub uvm_persistence_mode: Option<bool>,
     /// Enable DCGM exporter for GPU metrics
     pub dcgm_enabled: Option<bool>,
+    /// Load nvidia-modeset and nvidia-drm for rendering and video encode
+    pub graphics: Option<bool>,
+    /// Confine each daemon with a Landlock ruleset
+    pub landlock_daemons: Option<bool>,
+    /// `nvrc.sysctl.<key>=<value>` overrides of the hardening profile, in cmdline order
+    pub sysctl_overrides: Vec<(String, String)>,
+    /// `nvrc.mount.<name>=<data>` enables of [`crate::mount`] entries, in cmdline order
+    pub mount_enables: Vec<(String, String)>,
+    /// `nvrc.hugepages.<size>=<count>` reservations, in cmdline order
+    pub hugepages: Vec<Hugepages>,
+    /// Panic instead of warning when fewer hugepages than requested are allocated
+    pub hugepages_strict: Option<bool>,
+    /// `nvrc.net.<ifname>=<settings>` interfaces, in cmdline order
+    pub net: Vec<Interface>,
+    /// `nvrc.net.allow=<ifname>,...`: the only interfaces besides `lo` allowed to exist
+    pub net_allow: Option<Vec<String>>,
+    /// `nvrc.module.<name>.<param>=<value>` as (module, param, value), in cmdline order
+    pub module_params: Vec<(String, String, String)>,
+    /// `nvrc.gpudirect=rdma,gds,gdrcopy` features loaded in GPU mode
+    pub gpudirect: Vec<Feature>,
 
     /// Port GUID for NVL5+ systems (0x-prefixed hex string)
     pub port_guid: Option<String>,
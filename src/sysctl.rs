@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Kernel sysctl hardening profile.
+//!
+//! Applied right before module loading is disabled and control is handed to
+//! kata-agent. Every write is read back: a knob the kernel silently clamps or
+//! ignores is as bad as one never written. Entries can be overridden with
+//! `nvrc.sysctl.<key>=<value>`; the `confidential` feature locks the
+//! mandatory subset so the cmdline cannot weaken it.
+
+use log::{info, warn};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const PROC_SYS: &str = "/proc/sys";
+
+/// One profile entry. `mandatory` knobs must exist and stick; the rest depend
+/// on optional kernel config (Yama) or may break odd workloads, so they only
+/// warn when the kernel lacks or rejects them.
+pub struct Sysctl {
+    pub key: &'static str,
+    pub value: &'static str,
+    pub mandatory: bool,
+    /// The knob disables a feature the kernel may not be built with; without
+    /// the knob there is nothing to disable, so the entry is skipped.
+    pub if_present: bool,
+}
+
+pub const PROFILE: &[Sysctl] = &[
+    // Hide kernel pointers from everyone, including root in the guest.
+    Sysctl {
+        key: "kernel.kptr_restrict",
+        value: "2",
+        mandatory: true,
+        if_present: false,
+    },
+    Sysctl {
+        key: "kernel.dmesg_restrict",
+        value: "1",
+        mandatory: true,
+        if_present: false,
+    },
+    // 1 is sticky: it cannot be cleared again without a reboot. Only present
+    // with CONFIG_BPF_SYSCALL.
+    Sysctl {
+        key: "kernel.unprivileged_bpf_disabled",
+        value: "1",
+        mandatory: true,
+        if_present: true,
+    },
+    // One-way: no kexec into an unmeasured kernel. Only present with
+    // CONFIG_KEXEC_CORE, which minimal guest kernels often leave out.
+    Sysctl {
+        key: "kernel.kexec_load_disabled",
+        value: "1",
+        mandatory: true,
+        if_present: true,
+    },
+    Sysctl {
+        key: "kernel.perf_event_paranoid",
+        value: "3",
+        mandatory: true,
+        if_present: false,
+    },
+    // Only present with CONFIG_SECURITY_YAMA. 2 keeps ptrace to CAP_SYS_PTRACE
+    // (cuda-gdb, strace); 3 is one-way and left to an explicit override.
+    Sysctl {
+        key: "kernel.yama.ptrace_scope",
+        value: "2",
+        mandatory: false,
+        if_present: false,
+    },
+    Sysctl {
+        key: "fs.protected_hardlinks",
+        value: "1",
+        mandatory: true,
+        if_present: false,
+    },
+    Sysctl {
+        key: "fs.protected_symlinks",
+        value: "1",
+        mandatory: true,
+        if_present: false,
+    },
+    Sysctl {
+        key: "fs.protected_fifos",
+        value: "2",
+        mandatory: false,
+        if_present: false,
+    },
+    Sysctl {
+        key: "fs.protected_regular",
+        value: "2",
+        mandatory: false,
+        if_present: false,
+    },
+];
+
+/// `kernel.yama.ptrace_scope` -> `<root>/kernel/yama/ptrace_scope`
+fn proc_path(root: &Path, key: &str) -> PathBuf {
+    root.join(key.replace('.', "/"))
+}
+
+/// Write a sysctl and confirm by reading it back. Whitespace-separated
+/// multi-value knobs compare field by field, so `"4 4 1 7"` matches the
+/// kernel's tab-separated echo.
+pub fn write_verified(root: &Path, key: &str, value: &str) -> io::Result<()> {
+    let path = proc_path(root, key);
+    // No create: a missing knob must fail, not become a stray file.
+    let mut file = OpenOptions::new().write(true).truncate(true).open(&path)?;
+    file.write_all(format!("{value}\n").as_bytes())?;
+    let got = fs::read_to_string(&path)?;
+    if got.split_whitespace().ne(value.split_whitespace()) {
+        return Err(io::Error::other(format!(
+            "read back {:?}, expected {value:?}",
+            got.trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Apply `(key, value, mandatory)` entries. A mandatory knob that is missing or
+/// does not stick panics; an optional one warns and is skipped.
+pub fn apply_at(root: &Path, entries: &[(&str, &str, bool)]) {
+    for &(key, value, mandatory) in entries {
+        match write_verified(root, key, value) {
+            Ok(()) => info!("sysctl {key}={value}"),
+            Err(e) if mandatory => panic!("sysctl {key}={value}: {e}"),
+            Err(e) => warn!("sysctl {key}={value} skipped: {e}"),
+        }
+    }
+}
+
+/// Look up a profile entry by key.
+fn find(key: &str) -> Option<&'static Sysctl> {
+    PROFILE.iter().find(|s| s.key == key)
+}
+
+/// Validate an `nvrc.sysctl.<key>=<value>` override. Only profile keys can be
+/// overridden, values are integers, and with the `confidential` feature the
+/// mandatory subset is locked.
+pub fn check_override(key: &str, value: &str) -> Result<(), String> {
+    let entry = find(key).ok_or_else(|| format!("nvrc.sysctl: unknown key {key}"))?;
+    value
+        .parse::<i64>()
+        .map_err(|e| format!("nvrc.sysctl.{key}: invalid value {value:?}: {e}"))?;
+    if entry.mandatory && cfg!(feature = "confidential") {
+        return Err(format!("nvrc.sysctl.{key}: locked by confidential build"));
+    }
+    Ok(())
+}
+
+/// Apply [`PROFILE`] with cmdline overrides (last one wins) to /proc/sys.
+pub fn harden(overrides: &[(String, String)]) {
+    harden_at(Path::new(PROC_SYS), overrides)
+}
+
+fn harden_at(root: &Path, overrides: &[(String, String)]) {
+    let entries: Vec<_> = PROFILE
+        .iter()
+        .filter(|s| {
+            let absent = s.if_present && !proc_path(root, s.key).exists();
+            if absent {
+                info!("sysctl {}: not built in, nothing to disable", s.key);
+            }
+            !absent
+        })
+        .map(|s| {
+            let value = overrides
+                .iter()
+                .rev()
+                .find(|(k, _)| k == s.key)
+                .map_or(s.value, |(_, v)| v.as_str());
+            (s.key, value, s.mandatory)
+        })
+        .collect();
+    apply_at(root, &entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+    use tempfile::TempDir;
+
+    /// Fake /proc/sys with every profile knob present and zeroed.
+    fn fake_proc_sys() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        for s in PROFILE {
+            let path = proc_path(tmp.path(), s.key);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "0\n").unwrap();
+        }
+        tmp
+    }
+
+    fn value(root: &Path, key: &str) -> String {
+        fs::read_to_string(proc_path(root, key))
+            .unwrap()
+            .trim()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_proc_path() {
+        assert_eq!(
+            proc_path(Path::new("/proc/sys"), "kernel.yama.ptrace_scope"),
+            Path::new("/proc/sys/kernel/yama/ptrace_scope")
+        );
+    }
+
+    #[test]
+    fn test_harden_applies_profile() {
+        let tmp = fake_proc_sys();
+        harden_at(tmp.path(), &[]);
+        for s in PROFILE {
+            assert_eq!(value(tmp.path(), s.key), s.value, "{}", s.key);
+        }
+    }
+
+    #[test]
+    fn test_harden_override_last_wins() {
+        let tmp = fake_proc_sys();
+        let overrides = vec![
+            ("fs.protected_regular".to_owned(), "0".to_owned()),
+            ("fs.protected_regular".to_owned(), "1".to_owned()),
+        ];
+        harden_at(tmp.path(), &overrides);
+        assert_eq!(value(tmp.path(), "fs.protected_regular"), "1");
+        assert_eq!(value(tmp.path(), "kernel.kptr_restrict"), "2");
+    }
+
+    #[test]
+    fn test_harden_missing_optional_warns() {
+        let tmp = fake_proc_sys();
+        fs::remove_dir_all(tmp.path().join("kernel/yama")).unwrap();
+        harden_at(tmp.path(), &[]);
+        assert_eq!(value(tmp.path(), "kernel.dmesg_restrict"), "1");
+    }
+
+    /// A kernel without kexec or BPF has nothing to disable.
+    #[test]
+    fn test_harden_feature_not_built_in() {
+        let tmp = fake_proc_sys();
+        fs::remove_file(tmp.path().join("kernel/kexec_load_disabled")).unwrap();
+        fs::remove_file(tmp.path().join("kernel/unprivileged_bpf_disabled")).unwrap();
+        harden_at(tmp.path(), &[]);
+        assert!(!tmp.path().join("kernel/kexec_load_disabled").exists());
+        assert_eq!(value(tmp.path(), "kernel.kptr_restrict"), "2");
+    }
+
+    #[test]
+    fn test_harden_missing_mandatory_panics() {
+        let tmp = fake_proc_sys();
+        fs::remove_file(tmp.path().join("kernel/kptr_restrict")).unwrap();
+        let result = panic::catch_unwind(|| harden_at(tmp.path(), &[]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_verified_detects_mismatch() {
+        // /dev/null accepts the write but reads back empty, like a knob the
+        // kernel silently ignores.
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("kernel")).unwrap();
+        std::os::unix::fs::symlink("/dev/null", tmp.path().join("kernel/kptr_restrict")).unwrap();
+        let err = write_verified(tmp.path(), "kernel.kptr_restrict", "2").unwrap_err();
+        assert!(err.to_string().contains("read back"));
+    }
+
+    #[test]
+    fn test_write_verified_multi_value() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("kernel")).unwrap();
+        fs::write(tmp.path().join("kernel/printk"), "7\t4\t1\t7\n").unwrap();
+        write_verified(tmp.path(), "kernel.printk", "4 4 1 7").unwrap();
+    }
+
+    #[test]
+    fn test_check_override() {
+        assert!(check_override("fs.protected_fifos", "1").is_ok());
+        assert!(check_override("kernel.core_pattern", "|/bin/sh")
+            .unwrap_err()
+            .contains("unknown key"));
+        assert!(check_override("fs.protected_fifos", "x")
+            .unwrap_err()
+            .contains("invalid value"));
+    }
+
+    #[test]
+    fn test_check_override_mandatory() {
+        let result = check_override("kernel.kptr_restrict", "0");
+        assert_eq!(result.is_err(), cfg!(feature = "confidential"));
+    }
+}
@@ -102,16 +102,7 @@ fn poll_at(path: &Path) -> std::io::Result<()> {
 /// Daemons like nvidia-persistenced signal readiness via syslog. File-based
 /// approach works regardless of log level and survives for post-mortem debugging.
 fn forward_message(msg: &str) -> std::io::Result<()> {
-    let logfile = LOGFILE.get_or_try_init(|| {
-        OpenOptions::new()
-            .create(true)
-            .append(true)
-            .mode(0o600) // Restrict to owner only
-            .open(SYSLOG_FILE)
-            .map(Mutex::new)
-    })?;
-
-    let mut file = logfile
+    let mut file = logfile()?
         .lock()
         .unwrap_or_else(|poisoned| poisoned.into_inner());
     writeln!(file, "{}", msg)?;
@@ -123,6 +114,20 @@ fn forward_message(msg: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Open (creating if needed) the global log file. Called eagerly before the
+/// syslog child is Landlock-confined, so the file exists for its rule and the
+/// open fd is inherited; later writes need no path access at all.
+pub fn logfile() -> std::io::Result<&'static Mutex<File>> {
+    LOGFILE.get_or_try_init(|| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600) // Restrict to owner only
+            .open(SYSLOG_FILE)
+            .map(Mutex::new)
+    })
+}
+
 /// One-shot poll for testing: bind, poll once, return.
 /// Socket is dropped after call—suitable for tests with temp paths.
 fn poll_once(path: &Path) -> std::io::Result<()> {
@@ -6,10 +6,18 @@
 //! Generates CDI (Container Device Interface) specs so container runtimes
 //! can discover and mount GPU devices without needing the legacy hook.
 
+use std::fs;
+
 use crate::execute::foreground;
 use crate::gpu_extension;
+use crate::macros::ResultExt;
 
 const NVIDIA_CTK: &str = "/bin/nvidia-ctk";
+const CDI_SPEC: &str = "/var/run/cdi/nvidia.yaml";
+
+/// What a graphics CDI spec must hand to containers: the DRM nodes and the
+/// EGL/Vulkan driver library behind the glvnd and ICD loaders.
+const GRAPHICS_SPEC: &[&str] = &["/dev/dri/card", "/dev/dri/renderD", "libEGL_nvidia.so"];
 
 /// Run nvidia-ctk with given arguments.
 fn ctk(args: &[&str]) {
@@ -19,11 +27,29 @@ fn ctk(args: &[&str]) {
 /// Generate the CDI spec (`/var/run/cdi/nvidia.yaml`) so runtimes can inject GPU
 /// devices without the legacy hook. With composable images the extension-derived
 /// flags point nvidia-ctk at `/run/kata-extensions/gpu`; see the [`gpu_extension`] helpers
-/// for why each is needed (all no-ops for the monolithic image).
-pub fn nvidia_ctk_cdi() {
+/// for why each is needed (all no-ops for the monolithic image). With
+/// `graphics`, the spec must also carry the graphics devices and libraries.
+pub fn nvidia_ctk_cdi(graphics: bool) {
     let args = cdi_args(gpu_extension::driver_root(), gpu_extension::cdi_hook_path());
     let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
     ctk(&arg_refs);
+    if graphics {
+        let spec = fs::read_to_string(CDI_SPEC).or_panic(CDI_SPEC);
+        check_graphics_spec(&spec);
+    }
+}
+
+/// nvidia-ctk silently leaves out what it cannot find, e.g. DRM nodes when
+/// nvidia-drm is not loaded or EGL libraries a compute-only image lacks.
+fn check_graphics_spec(spec: &str) {
+    let missing: Vec<&str> = GRAPHICS_SPEC
+        .iter()
+        .copied()
+        .filter(|entry| !spec.contains(entry))
+        .collect();
+    if !missing.is_empty() {
+        panic!("{CDI_SPEC}: no {} for graphics", missing.join(", "));
+    }
 }
 
 /// Assemble the `nvidia-ctk cdi generate` arguments from the (possibly empty)
@@ -33,7 +59,7 @@ fn cdi_args(driver_root: Option<String>, cdi_hook_path: Option<String>) -> Vec<S
         "-d".to_owned(),
         "cdi".to_owned(),
         "generate".to_owned(),
-        "--output=/var/run/cdi/nvidia.yaml".to_owned(),
+        format!("--output={CDI_SPEC}"),
     ];
     if let Some(root) = driver_root {
         args.push(format!("--driver-root={root}"));
@@ -63,11 +89,27 @@ mod tests {
     #[cfg_attr(miri, ignore = "spawns a process, which miri cannot emulate")]
     fn test_nvidia_ctk_cdi_fails_without_binary() {
         let result = panic::catch_unwind(|| {
-            nvidia_ctk_cdi();
+            nvidia_ctk_cdi(false);
         });
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_check_graphics_spec() {
+        let spec = "\
+containerEdits:
+  deviceNodes:
+  - path: /dev/dri/card1
+  - path: /dev/dri/renderD128
+  mounts:
+  - hostPath: /usr/lib/x86_64-linux-gnu/libEGL_nvidia.so.570.133.20
+";
+        check_graphics_spec(spec);
+        let compute = "containerEdits:\n  deviceNodes:\n  - path: /dev/nvidia0\n";
+        let result = panic::catch_unwind(|| check_graphics_spec(compute));
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_cdi_args_monolithic() {
         // No extension: only the base generate args, no extension-specific flags.
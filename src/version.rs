@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (c) NVIDIA CORPORATION
+
+//! Driver/userspace version consistency.
+//!
+//! With composable images the kernel modules and the userspace come from
+//! separately built extensions (or the base image), and nothing stops a
+//! mismatched pair from booting. CUDA only notices inside the container, with
+//! an error that says nothing about versions. Once nvidia is loaded, the
+//! kernel module version from `/proc/driver/nvidia/version` must match
+//! `libnvidia-ml.so.<version>` and the `--version` of every NVIDIA binary the
+//! mode runs.
+
+use log::info;
+use std::fs;
+use std::path::Path;
+
+use crate::consumer;
+use crate::execute;
+use crate::gpu_extension;
+use crate::ldcache;
+use crate::macros::ResultExt;
+
+const PROC_VERSION: &str = "/proc/driver/nvidia/version";
+const NVML: &str = "libnvidia-ml.so.";
+
+/// Compare the loaded kernel module with NVML and `binaries` (rootfs paths,
+/// mapped into the gpu extension). Must run after nvidia is loaded.
+pub fn check(binaries: &[&str]) {
+    let proc = fs::read_to_string(PROC_VERSION).or_panic(PROC_VERSION);
+    let kernel = kernel_version(&proc)
+        .unwrap_or_else(|| panic!("{PROC_VERSION}: no NVRM version in {proc:?}"));
+
+    let mut dirs = consumer::library_dirs();
+    dirs.extend(ldcache::SYSTEM_DIRS.iter().map(|d| d.to_string()));
+    let (lib, version) = nvml_version(&dirs).unwrap_or_else(|e| panic!("{e}"));
+    let mut components = vec![(lib, version)];
+
+    for bin in binaries {
+        let bin = gpu_extension::path(bin);
+        let out = execute::output(&bin, &["--version"]);
+        let version =
+            version_token(&out).unwrap_or_else(|| panic!("{bin} --version: no version in {out:?}"));
+        components.push((bin, version));
+    }
+
+    let mismatches = mismatches(&kernel, &components);
+    if !mismatches.is_empty() {
+        panic!(
+            "driver version mismatch: kernel module {kernel}, but {}",
+            mismatches.join(", ")
+        );
+    }
+    info!(
+        "driver version {kernel} (kernel module, {} userspace components)",
+        components.len()
+    );
+}
+
+/// `570.133.20` from `NVRM version: NVIDIA UNIX Open Kernel Module for x86_64  570.133.20  Release Build ...`
+fn kernel_version(proc: &str) -> Option<String> {
+    proc.lines()
+        .find_map(|line| line.strip_prefix("NVRM version:"))
+        .and_then(version_token)
+}
+
+/// The first `<digits>.<digits>[...]` word, as the NVIDIA tools print it.
+fn version_token(text: &str) -> Option<String> {
+    text.split(|c: char| c.is_whitespace() || c == ':' || c == ',')
+        .find(|word| {
+            word.contains('.')
+                && word
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(str::to_owned)
+}
+
+/// The versioned NVML in the first of `dirs` that has one, skipping the
+/// `.so.1` soname link. Two versions side by side are an error: which one
+/// the loader picks is not ours to guess.
+fn nvml_version(dirs: &[String]) -> Result<(String, String), String> {
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, String)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let version = version_token(name.strip_prefix(NVML)?)?;
+                let path = Path::new(dir).join(&name);
+                Some((path.display().to_string(), version))
+            })
+            .collect();
+        found.sort();
+        found.dedup_by(|a, b| a.1 == b.1);
+        match found.len() {
+            0 => continue,
+            1 => return Ok(found.remove(0)),
+            _ => {
+                let libs: Vec<&str> = found.iter().map(|(lib, _)| lib.as_str()).collect();
+                return Err(format!(
+                    "several NVML versions in {dir}: {}",
+                    libs.join(", ")
+                ));
+            }
+        }
+    }
+    Err(format!("no {NVML}<version> in {dirs:?}"))
+}
+
+/// `<component> is <version>` for every component not at `kernel`.
+fn mismatches(kernel: &str, components: &[(String, String)]) -> Vec<String> {
+    components
+        .iter()
+        .filter(|(_, version)| version != kernel)
+        .map(|(component, version)| format!("{component} is {version}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PROC: &str = "\
+NVRM version: NVIDIA UNIX Open Kernel Module for x86_64  570.133.20  Release Build  (dvs-builder@U16-I2-C03-12-4)  Fri Mar 14 22:11:25 UTC 2025
+GCC version:  gcc version 12.3.0 (Ubuntu 12.3.0-1ubuntu1~22.04)
+";
+
+    #[test]
+    fn test_kernel_version() {
+        assert_eq!(kernel_version(PROC).as_deref(), Some("570.133.20"));
+        // The GCC line is not the driver
+        assert_eq!(kernel_version("GCC version:  gcc version 12.3.0\n"), None);
+    }
+
+    #[test]
+    fn test_version_token() {
+        let smi = "NVIDIA-SMI version  : 570.133.20\nNVML version        : 570.133\n";
+        assert_eq!(version_token(smi).as_deref(), Some("570.133.20"));
+        let fm = "Fabric Manager version is : 570.133.20\n";
+        assert_eq!(version_token(fm).as_deref(), Some("570.133.20"));
+        let persistenced = "nvidia-persistenced:  version 570.133.20\n";
+        assert_eq!(version_token(persistenced).as_deref(), Some("570.133.20"));
+        assert_eq!(version_token("x86_64 v2. .5 1"), None);
+    }
+
+    #[test]
+    fn test_nvml_version() {
+        let base = TempDir::new().unwrap();
+        let ext = TempDir::new().unwrap();
+        fs::write(base.path().join("libnvidia-ml.so.1"), "").unwrap();
+        fs::write(base.path().join("libnvidia-ml.so.550.127.05"), "").unwrap();
+        fs::write(ext.path().join("libnvidia-ml.so.1"), "").unwrap();
+        fs::write(ext.path().join("libnvidia-ml.so.570.133.20"), "").unwrap();
+
+        // Extension dirs come first, like in the loader cache
+        let dirs = [
+            ext.path().display().to_string(),
+            base.path().display().to_string(),
+        ];
+        let (lib, version) = nvml_version(&dirs).unwrap();
+        assert_eq!(version, "570.133.20");
+        assert!(lib.starts_with(&dirs[0]));
+        assert!(nvml_version(&dirs[..0])
+            .unwrap_err()
+            .contains("no libnvidia-ml.so."));
+    }
+
+    #[test]
+    fn test_nvml_version_stale_copy() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("libnvidia-ml.so.570.133.20"), "").unwrap();
+        fs::write(dir.path().join("libnvidia-ml.so.550.127.05"), "").unwrap();
+        let dirs = [dir.path().display().to_string()];
+        let err = nvml_version(&dirs).unwrap_err();
+        assert!(err.contains("several NVML versions"), "{err}");
+        assert!(
+            err.contains("550.127.05") && err.contains("570.133.20"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let components = [
+            (
+                "libnvidia-ml.so.570.133.20".to_owned(),
+                "570.133.20".to_owned(),
+            ),
+            ("/bin/nvidia-smi".to_owned(), "570.133.20".to_owned()),
+            ("/bin/nv-fabricmanager".to_owned(), "570.124.06".to_owned()),
+        ];
+        assert!(mismatches("570.133.20", &components[..2]).is_empty());
+        assert_eq!(
+            mismatches("570.133.20", &components),
+            ["/bin/nv-fabricmanager is 570.124.06"]
+        );
+    }
+}